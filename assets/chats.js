@@ -0,0 +1,86 @@
+/**
+ * Conversations only load their latest messages,
+ * so keep them in view when a conversation is opened.
+ * Scrolling up loads older messages.
+ */
+function scroll_to_latest_message() {
+    const chat_info_container = document.getElementById("chat_info_container");
+    const focus = document.querySelector("[data-focus-message]");
+
+    // Opened from a search result, show that message instead.
+    if (focus !== null) {
+        const message = document.getElementById(focus.dataset.focusMessage);
+
+        focus.remove();
+
+        if (message !== null) {
+            message.scrollIntoView({ block: "center" });
+            message.classList.add("chats__focused");
+
+            return;
+        }
+    }
+
+    if (chat_info_container !== null) {
+        chat_info_container.scrollTop = chat_info_container.scrollHeight;
+    }
+}
+
+document.addEventListener("DOMContentLoaded", scroll_to_latest_message);
+document.addEventListener("htmx:afterSwap", (ev) => {
+    if (ev.detail.target.id === "chat_container") {
+        scroll_to_latest_message();
+    }
+});
+
+// No need to be notified of messages in the conversation that is open.
+document.addEventListener("htmx:oobAfterSwap", (ev) => {
+    const notification = ev.detail.target;
+
+    if (notification.id !== "chat_notification" || notification.firstElementChild === null) {
+        return;
+    }
+
+    const conversation_id = notification.firstElementChild.dataset.conversationId;
+
+    if (document.querySelector(`#chat_info_container[data-conversation-id="${conversation_id}"]`) !== null) {
+        notification.innerHTML = "";
+    }
+});
+
+// Enter sends the message, Shift+Enter starts a new line.
+document.addEventListener("keydown", (ev) => {
+    if (ev.target.id !== "message_input" || ev.key !== "Enter" || ev.shiftKey || ev.isComposing) {
+        return;
+    }
+
+    ev.preventDefault();
+    ev.target.form.requestSubmit();
+});
+
+// Copies a code block of a message, without its line numbers.
+document.addEventListener("click", async (ev) => {
+    const button = ev.target.closest("[data-copy-code]");
+
+    if (button === null) {
+        return;
+    }
+
+    // Would open or close a collapsed code block too.
+    ev.preventDefault();
+
+    const code = button.closest(".chats__code").querySelector("code");
+
+    await navigator.clipboard.writeText(code.textContent);
+    button.textContent = "Copied";
+    setTimeout(() => {
+        button.textContent = "Copy";
+    }, 2000);
+});
+
+// Scheduled messages are sent at a time picked in the user's timezone, which the server does not know.
+document.addEventListener("htmx:configRequest", (ev) => {
+    if (ev.detail.path.startsWith("/chats/scheduled")) {
+        ev.detail.parameters.timezone_offset = new Date().getTimezoneOffset();
+    }
+});
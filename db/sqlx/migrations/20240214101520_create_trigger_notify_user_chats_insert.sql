@@ -0,0 +1,12 @@
+-- Lets every instance of the server know about new messages, see `chats::pubsub`.
+-- Only the id is sent since NOTIFY payloads are limited to 8000 bytes.
+CREATE OR REPLACE FUNCTION notify_user_chats_insert() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('user_chats_insert', NEW.id::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER user_chats_insert_notify
+AFTER INSERT ON user_chats
+FOR EACH ROW EXECUTE FUNCTION notify_user_chats_insert();
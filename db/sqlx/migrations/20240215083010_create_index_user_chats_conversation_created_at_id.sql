@@ -0,0 +1,7 @@
+-- Used for paginating the messages of a conversation, see `Chat::get_messages`.
+CREATE INDEX IF NOT EXISTS user_chats_conversation_created_at_id ON user_chats (
+    LEAST(owner_id, receiver_id),
+    GREATEST(owner_id, receiver_id),
+    created_at DESC,
+    id DESC
+);
@@ -0,0 +1,10 @@
+-- How far a user has read the messages another user sent them
+CREATE TABLE IF NOT EXISTS user_chat_reads (
+    user_id INTEGER NOT NULL,
+    other_user_id INTEGER NOT NULL,
+    last_read_message_id INTEGER NOT NULL,
+    read_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, other_user_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (other_user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,14 @@
+-- When the message was last edited, if it ever was
+ALTER TABLE user_chats ADD COLUMN IF NOT EXISTS edited_at TIMESTAMPTZ;
+
+-- Every text a message had before it was edited
+CREATE TABLE IF NOT EXISTS user_chat_revisions (
+    id SERIAL PRIMARY KEY,
+    message_id INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    -- When this text was replaced
+    edited_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (message_id) REFERENCES user_chats(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS user_chat_revisions_message_id ON user_chat_revisions (message_id, edited_at);
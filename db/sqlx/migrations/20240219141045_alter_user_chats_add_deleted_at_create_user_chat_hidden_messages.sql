@@ -0,0 +1,12 @@
+-- When the message was deleted for everyone. Its text is removed but the message stays as a placeholder.
+ALTER TABLE user_chats ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+-- Messages a user deleted only for themselves
+CREATE TABLE IF NOT EXISTS user_chat_hidden_messages (
+    user_id INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    hidden_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, message_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (message_id) REFERENCES user_chats(id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+-- The earlier message of the same conversation this message replies to
+ALTER TABLE user_chats ADD COLUMN IF NOT EXISTS reply_to INTEGER;
+ALTER TABLE user_chats ADD CONSTRAINT user_chats_reply_to_fkey FOREIGN KEY (reply_to) REFERENCES user_chats(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS user_chats_reply_to ON user_chats (reply_to);
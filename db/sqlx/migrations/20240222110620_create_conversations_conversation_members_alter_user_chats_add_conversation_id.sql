@@ -0,0 +1,56 @@
+-- A 1:1 conversation between two users, or a named group of any number of members
+CREATE TABLE IF NOT EXISTS conversations (
+    id SERIAL PRIMARY KEY,
+    -- Only groups have names
+    name TEXT,
+    is_group BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Whoever created the group, they can remove other members
+    created_by INTEGER,
+    -- Both users of a 1:1 conversation, lowest id first, so there is only one per pair
+    first_user_id INTEGER,
+    second_user_id INTEGER,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL,
+    FOREIGN KEY (first_user_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (second_user_id) REFERENCES users(id) ON DELETE CASCADE,
+    UNIQUE (first_user_id, second_user_id),
+    CHECK (is_group OR (first_user_id IS NOT NULL AND second_user_id IS NOT NULL AND first_user_id <= second_user_id))
+);
+
+CREATE TABLE IF NOT EXISTS conversation_members (
+    conversation_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    joined_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (conversation_id, user_id),
+    FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS conversation_members_user_id ON conversation_members (user_id);
+
+ALTER TABLE user_chats ADD COLUMN IF NOT EXISTS conversation_id INTEGER;
+ALTER TABLE user_chats ADD CONSTRAINT user_chats_conversation_id_fkey FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE CASCADE;
+
+-- Every existing pair of users becomes a conversation with both of them as members
+INSERT INTO conversations (first_user_id, second_user_id)
+SELECT DISTINCT LEAST(owner_id, receiver_id), GREATEST(owner_id, receiver_id) FROM user_chats
+ON CONFLICT (first_user_id, second_user_id) DO NOTHING;
+
+INSERT INTO conversation_members (conversation_id, user_id)
+SELECT id, first_user_id FROM conversations WHERE NOT is_group
+UNION
+SELECT id, second_user_id FROM conversations WHERE NOT is_group
+ON CONFLICT (conversation_id, user_id) DO NOTHING;
+
+UPDATE user_chats SET conversation_id = conversations.id
+FROM conversations
+WHERE conversations.first_user_id = LEAST(user_chats.owner_id, user_chats.receiver_id)
+AND conversations.second_user_id = GREATEST(user_chats.owner_id, user_chats.receiver_id);
+
+ALTER TABLE user_chats ALTER COLUMN conversation_id SET NOT NULL;
+
+-- Messages of a group have no single receiver
+ALTER TABLE user_chats ALTER COLUMN receiver_id DROP NOT NULL;
+ALTER TABLE user_chats ALTER COLUMN receiver_display_name DROP NOT NULL;
+
+CREATE INDEX IF NOT EXISTS user_chats_conversation_id_created_at_id ON user_chats (conversation_id, created_at DESC, id DESC);
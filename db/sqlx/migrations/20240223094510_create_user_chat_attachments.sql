@@ -0,0 +1,15 @@
+-- Files attached to messages. `owner_id` is whoever uploaded them, to enforce their quota.
+CREATE TABLE IF NOT EXISTS user_chat_attachments (
+    id SERIAL PRIMARY KEY,
+    message_id INTEGER NOT NULL,
+    owner_id INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    content_type VARCHAR(255) NOT NULL,
+    size BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (message_id) REFERENCES user_chats(id) ON DELETE CASCADE,
+    FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS user_chat_attachments_message_id_idx ON user_chat_attachments (message_id);
+CREATE INDEX IF NOT EXISTS user_chat_attachments_owner_id_idx ON user_chat_attachments (owner_id);
@@ -0,0 +1,12 @@
+-- Attachments are downloaded through the server instead of their public URL,
+-- so keep the name of the object in storage and the name the file was uploaded with.
+ALTER TABLE user_chat_attachments ADD COLUMN IF NOT EXISTS object_name TEXT;
+ALTER TABLE user_chat_attachments ADD COLUMN IF NOT EXISTS file_name TEXT;
+
+UPDATE user_chat_attachments
+SET object_name = regexp_replace(url, '^https://storage\.cloud\.google\.com/[^/]+/', ''),
+    file_name = regexp_replace(url, '^.*-chat_server-', '');
+
+ALTER TABLE user_chat_attachments ALTER COLUMN object_name SET NOT NULL;
+ALTER TABLE user_chat_attachments ALTER COLUMN file_name SET NOT NULL;
+ALTER TABLE user_chat_attachments DROP COLUMN IF EXISTS url;
@@ -0,0 +1,6 @@
+-- Nothing looks messages up by their exact text, and a btree cannot index long messages anyway.
+DROP INDEX IF EXISTS user_chats_messages;
+
+-- Used for searching messages, see `MessageSearchResult::search`.
+-- Queries have to use the same expression for the index to be used.
+CREATE INDEX IF NOT EXISTS user_chats_message_search ON user_chats USING GIN (to_tsvector('english', message));
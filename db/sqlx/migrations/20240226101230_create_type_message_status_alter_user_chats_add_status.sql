@@ -0,0 +1,13 @@
+-- Where a message is on its way to its recipients
+CREATE TYPE message_status AS ENUM ('sent', 'delivered', 'seen');
+
+ALTER TABLE user_chats
+ADD COLUMN status message_status NOT NULL DEFAULT 'sent';
+
+-- Whatever was already read has been seen
+UPDATE user_chats
+SET status = 'seen'
+FROM user_chat_reads reads
+WHERE reads.user_id = user_chats.receiver_id
+AND reads.other_user_id = user_chats.owner_id
+AND user_chats.id <= reads.last_read_message_id;
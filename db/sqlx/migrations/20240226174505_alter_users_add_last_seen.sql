@@ -0,0 +1,3 @@
+-- When the user last had a chat open, unlike last_login_date which only changes when logging in
+ALTER TABLE users
+ADD COLUMN last_seen TIMESTAMPTZ;
@@ -0,0 +1,4 @@
+-- Generated by whoever sends a message, so sending it again does not save it twice
+ALTER TABLE user_chats
+ADD COLUMN client_id VARCHAR(64),
+ADD CONSTRAINT user_chats_owner_id_client_id_key UNIQUE (owner_id, client_id);
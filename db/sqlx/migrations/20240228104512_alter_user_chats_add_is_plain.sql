@@ -0,0 +1,3 @@
+-- Plain messages are shown as they were typed instead of as markdown
+ALTER TABLE user_chats
+ADD COLUMN is_plain BOOLEAN NOT NULL DEFAULT FALSE;
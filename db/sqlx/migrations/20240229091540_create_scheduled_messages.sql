@@ -0,0 +1,15 @@
+-- Messages users wrote to be sent later. A row is deleted once its message is sent, or cancelled.
+CREATE TABLE IF NOT EXISTS scheduled_messages (
+    id SERIAL PRIMARY KEY,
+    owner_id INTEGER NOT NULL,
+    receiver_id INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    is_plain BOOLEAN NOT NULL DEFAULT FALSE,
+    send_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (receiver_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS scheduled_messages_send_at_idx ON scheduled_messages (send_at);
+CREATE INDEX IF NOT EXISTS scheduled_messages_owner_id_receiver_id_idx ON scheduled_messages (owner_id, receiver_id);
@@ -0,0 +1,13 @@
+-- New messages of the conversation disappear this many seconds after they are seen, unless it is NULL
+ALTER TABLE conversations
+ADD COLUMN disappear_after_seconds INTEGER CHECK (disappear_after_seconds > 0);
+
+-- Copied from the conversation when a message is sent, so changing it only affects new messages.
+-- `expires_at` is set once the message is seen, or once it is sent in groups, where it has no single reader.
+ALTER TABLE user_chats
+ADD COLUMN disappear_after_seconds INTEGER,
+ADD COLUMN expires_at TIMESTAMPTZ,
+-- Written by the server, like when disappearing messages are turned on
+ADD COLUMN is_system BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS user_chats_expires_at_idx ON user_chats (expires_at) WHERE expires_at IS NOT NULL;
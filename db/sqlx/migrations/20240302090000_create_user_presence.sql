@@ -0,0 +1,12 @@
+-- Which server instances a user has a chat open on. Every instance keeps its rows alive
+-- while the user is connected to it, so the rows of an instance that went away run out on their own.
+CREATE TABLE IF NOT EXISTS user_presence (
+    instance_id TEXT NOT NULL,
+    user_id INTEGER NOT NULL,
+    online_until TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (instance_id, user_id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS user_presence_user_id_idx ON user_presence (user_id);
+CREATE INDEX IF NOT EXISTS user_presence_online_until_idx ON user_presence (online_until);
@@ -0,0 +1,5 @@
+-- How many times sending a scheduled message failed. It is tried again a while later,
+-- until it failed too many times and is left for its owner to edit or cancel.
+ALTER TABLE scheduled_messages
+ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0,
+ADD COLUMN failed_at TIMESTAMPTZ;
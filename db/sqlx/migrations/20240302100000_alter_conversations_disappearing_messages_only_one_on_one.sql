@@ -0,0 +1,5 @@
+-- Group messages are seen by every member at a different time, so they do not disappear.
+UPDATE conversations SET disappear_after_seconds = NULL WHERE is_group;
+
+ALTER TABLE conversations
+ADD CONSTRAINT conversations_disappear_after_seconds_not_group CHECK (NOT is_group OR disappear_after_seconds IS NULL);
@@ -1,7 +1,7 @@
 use rocket::{form::Form, post, response::content::RawHtml, FromForm};
 use rocket_db_pools::Connection;
 
-use crate::{db::Db, user::user_struct::User};
+use crate::{db::Db, user::user_struct::User, html, utils::{html::Html, rate_limit::RateLimit}};
 
 #[derive(FromForm)]
 pub struct SearchData {
@@ -12,10 +12,11 @@ pub struct SearchData {
 pub async fn search(
     mut db: Connection<Db>,
     data: Form<SearchData>,
-    user: User
-) -> RawHtml<String> {
+    user: User,
+    _rate_limit: RateLimit
+) -> RawHtml<Html> {
     if data.search.is_empty() {
-        return RawHtml("".to_string());
+        return RawHtml(Html::new());
     }
 
     let users = User::search_for_users_with_display_name(&mut db, &user.display_name, &data.search).await;
@@ -23,15 +24,13 @@ pub async fn search(
     match users {
         Ok(users) => {
             if users.is_empty() {
-                return RawHtml("<ul><li><p>No users found</p></li></ul>".to_string());
+                return RawHtml(Html::trusted("<ul><li><p>No users found</p></li></ul>"));
             }
 
-            let mut html = String::new();
-
-            html.push_str("<ul>");
+            let mut html = Html::trusted("<ul>");
 
             for user in users {
-                html.push_str(&format!(
+                html.push(&html!(
                     "
                     <li>
                         <button
@@ -61,11 +60,11 @@ pub async fn search(
                 ));
             }
 
-            html.push_str("</ul>");
+            html.push_markup("</ul>");
 
             RawHtml(html)
         },
-        Err(_) => RawHtml("<ul><li><p>No users found</p></li></ul>".to_string())
+        Err(_) => RawHtml(Html::trusted("<ul><li><p>No users found</p></li></ul>"))
     
     }
 }
@@ -0,0 +1,348 @@
+//! WebSocket transport for chats.
+//!
+//! One socket per logged in user is enough to both send and receive
+//! messages. Every frame is a JSON object with a `type` field:
+//!
+//! Client to server:
+//! - `{"type": "message", "receiver_id": 2, "message": "Hi!", "reply_to": 5, "nonce": "abc", "client_id": "...", "plain": false}`
+//!   saves and broadcasts a message. `reply_to` is the optional id of the message it replies to.
+//!   Messages are markdown unless `plain` is `true`.
+//!   `nonce` is optional and is echoed back in the `ack`.
+//!   `client_id` is an optional id of up to 64 characters the client generates for the message.
+//!   Sending a message with a `client_id` that was used before only acks the message that was saved with it.
+//!   Messages count against the same rate limit as `/chats/send`, and are answered with a 429 `error` past it.
+//! - `{"type": "typing", "receiver_id": 2}` lets the receiver know the user is typing. Nothing is sent back,
+//!   unless the two do not share a conversation, which is answered with a 403 `error`.
+//!   Typing counts against the rate limit of `/chats/typing`, and is answered with a 429 `error` past it.
+//! - `{"type": "ping"}` is answered with a `ping` so clients can keep the connection alive.
+//!
+//! Server to client:
+//! - `{"type": "message", "id": 7, "sender_id": 1, "receiver_id": 2, "message": "Hi!", "created_at": "..."}`
+//!   for every message the user sent or received, including ones sent through `/chats/send`.
+//!   Replies also have `reply_to` and `quoted`, a `{"id", "sender_id", "message", "deleted"}` snippet of the original.
+//!   Files sent through `/chats/send` are in `attachments`, each a `{"id", "url", "fileName", "contentType", "size"}`.
+//!   `url` is where participants of the conversation can download the file.
+//!   Every message has the `conversation_id` it belongs to. Group messages have no `receiver_id`,
+//!   the socket gets them for every group the user is a member of. They are sent through `/chats/groups/send`.
+//!   `status` is `sent`, `delivered` or `seen`. Receiving a message through the socket delivers it.
+//!   `client_id` is the one it was sent with, if any.
+//!   `message` is the markdown as it was typed, `is_plain` is `true` if it should be shown as it is instead.
+//!   `is_system` is `true` for notices like disappearing messages being turned on, which no one sent themselves.
+//! - `{"type": "edited", "id": 7, "sender_id": 1, "receiver_id": 2, "message": "Hello!", "created_at": "...", "edited_at": "..."}`
+//!   when a message is edited, with its new text.
+//! - `{"type": "deleted", "id": 7, "sender_id": 1, "receiver_id": 2, "message": "", "deleted_at": "...", ...}`
+//!   when a message is deleted for everyone. Its text is gone, show a placeholder instead.
+//! - `{"type": "reacted", "message_id": 7, "conversation_id": 3, "sender_id": 1, "receiver_id": 2, "reactions": [{"emoji": "👍", "count": 1, "user_ids": [2]}]}`
+//!   when someone reacted to a message or took their reaction back, with every reaction it now has.
+//! - `{"type": "hidden", "user_id": 1, "conversation_id": 3, "other_user_id": 2, "message_id": 7}`
+//!   when the user deleted a message for themselves, on any of their devices.
+//! - `{"type": "expired", "conversation_id": 3, "sender_id": 1, "receiver_id": 2, "message_ids": [6, 7]}`
+//!   when disappearing messages ran out of time and were deleted for everyone.
+//! - `{"type": "membership", "conversation_id": 4, "user_id": 1, "joined": true}`
+//!   when someone was added to one of the user's groups, or left or was removed from it.
+//!   The socket follows the groups the user joins, and stops following the ones they leave.
+//! - `{"type": "delivered", "message_id": 7, "conversation_id": 3, "sender_id": 1, "receiver_id": 2}`
+//!   the first time a message was sent to one of its recipients.
+//! - `{"type": "seen", "reader_id": 2, "sender_id": 1, "last_read_message_id": 7, "read_at": "...", "message_ids": [6, 7]}`
+//!   when `reader_id` has read the messages `sender_id` sent them. `message_ids` are the ones that were not seen before.
+//! - `{"type": "presence", "user_id": 2, "online": true, "last_seen": "...", "partner_ids": [1, 3]}`
+//!   when someone the user has a conversation with comes online or goes offline.
+//!   Having this socket open counts as being online.
+//! - `{"type": "typing", "user_id": 1, "receiver_id": 2}` whenever someone reports they are typing.
+//!   It is up to the client to hide it after a few seconds.
+//! - `{"type": "resync", "chats": [{"id": 7, "senderId": 1, "receiverId": 2, "message": "Hi!", "unreadCount": 1, ...}], "groups": [{"id": 4, "name": "...", "message": "Hi!", "isDeleted": false}]}`
+//!   when some of the events above were dropped because the socket fell behind, or lost while the server was reconnecting to the database.
+//!   Every 1:1 conversation of the user with its latest message and how many messages are unread, and every group of the user
+//!   with its latest message, as they are in the database.
+//! - `{"type": "ack", "nonce": "abc", "id": 7, "created_at": "..."}` once a sent message is saved.
+//! - `{"type": "error", "code": 404, "reason": "Not Found", "message": "..."}` when a frame could not be handled.
+//! - `{"type": "ping"}`
+use rocket::{futures::{SinkExt, StreamExt}, get, http::Status, response::status, tokio::{select, sync::broadcast::error::RecvError}, Shutdown, State};
+use rocket_ws::{Channel, Message, WebSocket};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{chats::{chat_struct::{Chat, ChatRoomMessage, NewMessage}, conversation::{Conversation, GroupPreview}, event::ChatEvent, presence::Presence, pubsub::PubSub, topics::{Subscriptions, Topic}}, db::Db, errors::error::ErrorJson, user::user_struct::User, utils::rate_limit::{ClientRateLimit, RetryAfter}};
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ClientFrame {
+    Message {
+        receiver_id: i32,
+        message: String,
+        reply_to: Option<i32>,
+        nonce: Option<String>,
+        client_id: Option<String>,
+        #[serde(default)]
+        plain: bool,
+    },
+    Typing {
+        receiver_id: i32,
+    },
+    Ping,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ServerFrame {
+    Ack {
+        nonce: Option<String>,
+        id: Option<i32>,
+        created_at: Option<String>,
+    },
+    Error(ErrorJson),
+    Ping,
+    Resync {
+        chats: Vec<Chat>,
+        groups: Vec<GroupPreview>,
+    },
+    /// Any [`ChatEvent`], which already carries its own `type`.
+    #[serde(untagged)]
+    Event(ChatEvent),
+}
+
+impl ServerFrame {
+    fn error(status: Status, message: &str) -> Self {
+        ServerFrame::Error(ErrorJson {
+            code: status.code,
+            message: message.to_string(),
+            reason: status.reason_lossy().to_string(),
+        })
+    }
+
+    fn to_message(&self) -> Message {
+        Message::Text(serde_json::to_string(self).unwrap_or_default())
+    }
+}
+
+/// The route whose rate limit messages sent through the socket count against
+const SEND_ROUTE: &str = "/chats/send";
+/// The route whose rate limit typing frames count against
+const TYPING_ROUTE: &str = "/chats/typing";
+
+/// Saves a message sent through the socket and publishes it
+/// the same way `/chats/send` does.
+/// Returns the message saved before instead if its `client_id` was sent before.
+async fn send_message(
+    pool: &PgPool,
+    pubsub: &PubSub,
+    user: &User,
+    receiver_id: i32,
+    new_message: NewMessage,
+) -> Result<ChatRoomMessage, status::Custom<String>> {
+    if new_message.message.trim().is_empty() {
+        return Err(status::Custom(Status::BadRequest, "Message cannot be empty.".to_string()));
+    }
+
+    if !new_message.has_valid_client_id() {
+        return Err(status::Custom(Status::BadRequest, "The id of the message is too long.".to_string()));
+    }
+
+    let mut conn = pool.acquire().await.map_err(|err| {
+        println!("Error: {:?}", err);
+
+        status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string())
+    })?;
+
+    if let Some(msg) = sent_before(&mut conn, user, &new_message).await? {
+        return Ok(msg);
+    }
+
+    let receiver_name = match User::get_display_name(&mut conn, &receiver_id).await {
+        Ok(Some(receiver_name)) => receiver_name,
+        Ok(None) => return Err(status::Custom(Status::NotFound, "User to chat with not found.".to_string())),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string()));
+        }
+    };
+
+    let saved = Chat::save_chat(&mut conn, &user.id, &receiver_id, &receiver_name, &new_message, &[]).await;
+
+    match saved {
+        Ok(msg) => {
+            pubsub.publish(ChatEvent::Message(msg.clone())).await;
+
+            Ok(msg)
+        }
+        // Sent again while the first one was being saved.
+        Err(err) if ChatRoomMessage::is_duplicate(&err) => match sent_before(&mut conn, user, &new_message).await? {
+            Some(msg) => Ok(msg),
+            None => Err(status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string())),
+        },
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            Err(status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string()))
+        }
+    }
+}
+
+/// Follows every group in `groups`, and stops following the ones that are not.
+fn follow_groups(pubsub: &PubSub, subscriptions: &mut Subscriptions<ChatEvent>, groups: &[GroupPreview]) {
+    subscriptions.retain(|topic| match topic {
+        Topic::Group(conversation_id) => groups.iter().any(|group| group.id == conversation_id),
+        _ => true,
+    });
+
+    for group in groups {
+        subscriptions.add(pubsub.subscribe(Topic::Group(group.id)));
+    }
+}
+
+/// Every 1:1 conversation and group of `user` as it is in the database.
+/// Joining or leaving a group may have been dropped too, so the socket follows the groups again.
+async fn resync(pool: &PgPool, pubsub: &PubSub, subscriptions: &mut Subscriptions<ChatEvent>, user: &User) -> ServerFrame {
+    let sidebar = match pool.acquire().await {
+        Ok(mut conn) => match Chat::get_user_chats(&mut conn, &user.id, &String::new()).await {
+            Ok(chats) => Conversation::get_groups_of_user(&mut conn, &user.id).await.map(|groups| (chats, groups)),
+            Err(err) => Err(err),
+        },
+        Err(err) => Err(err),
+    };
+
+    match sidebar {
+        Ok((chats, groups)) => {
+            follow_groups(pubsub, subscriptions, &groups);
+
+            ServerFrame::Resync { chats, groups }
+        },
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            ServerFrame::error(Status::InternalServerError, "Something went wrong. Please try again.")
+        }
+    }
+}
+
+/// The message `user` already sent with the `client_id` of `new_message`.
+async fn sent_before(
+    conn: &mut PgConnection,
+    user: &User,
+    new_message: &NewMessage,
+) -> Result<Option<ChatRoomMessage>, status::Custom<String>> {
+    let Some(client_id) = &new_message.client_id else {
+        return Ok(None);
+    };
+
+    ChatRoomMessage::get_by_client_id(conn, &user.id, client_id).await.map_err(|err| {
+        println!("Error: {:?}", err);
+
+        status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string())
+    })
+}
+
+#[get("/chats")]
+pub fn chats(
+    ws: WebSocket,
+    db: &State<Db>,
+    pubsub: &State<PubSub>,
+    presence: &State<Presence>,
+    rate_limit: ClientRateLimit,
+    user: User,
+    mut end: Shutdown
+) -> Channel<'static> {
+    let pool: PgPool = (***db).clone();
+    let pubsub = pubsub.inner().clone();
+    let presence = presence.inner().clone();
+    let mut subscriptions = Subscriptions::new(vec![pubsub.subscribe(Topic::User(user.id))]);
+
+    ws.channel(move |mut stream| Box::pin(async move {
+        let _connected = presence.connect(user.id).await;
+        // Group messages are only published to their group.
+        let groups = match pool.acquire().await {
+            Ok(mut conn) => Conversation::get_groups_of_user(&mut conn, &user.id).await,
+            Err(err) => Err(err),
+        };
+
+        match groups {
+            Ok(groups) => follow_groups(&pubsub, &mut subscriptions, &groups),
+            Err(err) => {
+                println!("Error: {:?}", err);
+                stream.send(ServerFrame::error(Status::InternalServerError, "Something went wrong. Please try again.").to_message()).await?;
+
+                return Ok(());
+            }
+        }
+
+        loop {
+            let reply = select! {
+                frame = stream.next() => match frame {
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str::<ClientFrame>(&text) {
+                        Ok(ClientFrame::Message { receiver_id, message, reply_to, nonce, client_id, plain }) => {
+                            if let Err(wait) = rate_limit.take(SEND_ROUTE) {
+                                let RetryAfter(seconds) = RetryAfter::from(wait);
+                                let message = format!("You are doing that too often. Please try again in {} seconds.", seconds);
+
+                                stream.send(ServerFrame::error(Status::TooManyRequests, &message).to_message()).await?;
+                                continue;
+                            }
+
+                            match send_message(&pool, &pubsub, &user, receiver_id, NewMessage::new(message, reply_to, client_id, plain)).await {
+                                Ok(msg) => ServerFrame::Ack { nonce, id: msg.id, created_at: msg.created_at },
+                                Err(status::Custom(status, message)) => ServerFrame::error(status, &message),
+                            }
+                        },
+                        Ok(ClientFrame::Typing { receiver_id }) => {
+                            if let Err(wait) = rate_limit.take(TYPING_ROUTE) {
+                                let RetryAfter(seconds) = RetryAfter::from(wait);
+                                let message = format!("You are doing that too often. Please try again in {} seconds.", seconds);
+
+                                stream.send(ServerFrame::error(Status::TooManyRequests, &message).to_message()).await?;
+                                continue;
+                            }
+
+                            if !presence.are_partners(user.id, receiver_id).await {
+                                stream.send(ServerFrame::error(Status::Forbidden, "You are not authorized to view this chat.").to_message()).await?;
+                                continue;
+                            }
+
+                            pubsub.publish_local(ChatEvent::Typing { user_id: user.id, receiver_id });
+                            continue;
+                        },
+                        Ok(ClientFrame::Ping) => ServerFrame::Ping,
+                        Err(_) => ServerFrame::error(Status::BadRequest, "Invalid frame."),
+                    },
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(err)) => return Err(err),
+                },
+                event = subscriptions.recv_from() => match event {
+                    // Also published to the user, or to every topic, so it is only sent once.
+                    Some((Topic::Group(_), Ok(event))) if event.topics().is_empty() || event.topics().contains(&Topic::User(user.id)) => continue,
+                    // Some events were dropped or lost, send what they would have changed instead.
+                    Some((_, Ok(ChatEvent::Resync) | Err(RecvError::Lagged(_)))) => resync(&pool, &pubsub, &mut subscriptions, &user).await,
+                    Some((_, Ok(event))) => {
+                        if let ChatEvent::Membership { conversation_id, user_id, joined } = &event {
+                            if *user_id == user.id && *joined {
+                                subscriptions.add(pubsub.subscribe(Topic::Group(*conversation_id)));
+                            } else if *user_id == user.id {
+                                subscriptions.remove(Topic::Group(*conversation_id));
+                            }
+                        }
+
+                        ServerFrame::Event(event)
+                    },
+                    Some((_, Err(RecvError::Closed))) | None => break,
+                },
+                _ = &mut end => break
+            };
+
+            stream.send(reply.to_message()).await?;
+
+            if let ServerFrame::Event(ChatEvent::Message(msg)) = &reply {
+                if msg.sender_id != user.id {
+                    pubsub.mark_as_delivered(&pool, msg).await;
+                }
+            }
+        }
+
+        Ok(())
+    }))
+}
+
+#[get("/chats", rank = 2)]
+pub fn unauthorized_chats() -> status::Custom<String> {
+    status::Custom(Status::Unauthorized, "You are not authorized to chat without being logged in.".to_string())
+}
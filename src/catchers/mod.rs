@@ -1,7 +1,7 @@
-use rocket::{catch,  Request};
+use rocket::{catch, http::Header, Request, Responder};
 use rocket_dyn_templates::{context, Template};
 
-use crate::{cookies, errors::error::ErrorJson};
+use crate::{cookies, errors::error::{Error, ErrorJson, ErrorReason}, utils::rate_limit::RetryAfter};
 
 
 #[catch(500)]
@@ -75,3 +75,26 @@ pub fn unauthorized(
         lang: language
     })
 }
+
+#[derive(Responder)]
+pub struct TooManyRequests {
+    message: String,
+    retry_after: Header<'static>,
+}
+
+/// Only rate limited routes answer with 429, and those are all requested by htmx or scripts,
+/// so this is the same error string those routes send instead of a page.
+#[catch(429)]
+pub fn too_many_requests(
+    request: &Request
+) -> TooManyRequests {
+    let RetryAfter(seconds) = *request.local_cache(|| RetryAfter(1));
+
+    TooManyRequests {
+        message: Error::to_string(Error::new(
+            ErrorReason::TooManyRequests,
+            format!("You are doing that too often. Please try again in {} seconds.", seconds)
+        )),
+        retry_after: Header::new("Retry-After", seconds.to_string()),
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use crate::consts;
+
+/// A file attached to a message.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Attachment {
+    pub id: i32,
+    /// Where participants of the conversation can download it
+    pub url: String,
+    /// The name it was uploaded with
+    #[serde(rename = "fileName")]
+    pub file_name: String,
+    #[serde(rename = "contentType")]
+    pub content_type: String,
+    /// In bytes
+    pub size: i64,
+}
+
+/// A file that was uploaded but is not attached to a message yet.
+#[derive(Debug, Clone)]
+pub struct NewAttachment {
+    /// The name of the object it is stored as
+    pub object_name: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: i64,
+}
+
+/// Where an attachment is stored, and the conversation whose participants can download it.
+#[derive(Debug, Clone)]
+pub struct StoredAttachment {
+    pub object_name: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub conversation_id: i32,
+}
+
+impl Attachment {
+    fn new(id: i32, file_name: String, content_type: String, size: i64) -> Self {
+        Self {
+            id,
+            url: format!("/chats/attachments/{}", id),
+            file_name,
+            content_type,
+            size,
+        }
+    }
+
+    /// Images are shown in the conversation, every other file is downloaded.
+    pub fn is_image(&self) -> bool {
+        consts::ALLOWED_IMAGE_TYPES.contains(&self.content_type.as_str())
+    }
+
+    /// Gets the attachments of each message, in the order they were attached.
+    pub async fn get_by_message_ids(
+        db: &mut PgConnection,
+        message_ids: &[i32],
+    ) -> Result<HashMap<i32, Vec<Self>>, sqlx::Error> {
+        let records = sqlx::query!(
+            r#"
+            SELECT id, message_id, file_name, content_type, size
+            FROM user_chat_attachments
+            WHERE message_id = ANY($1)
+            ORDER BY id
+            "#,
+            message_ids
+        ).fetch_all(&mut *db).await?;
+
+        let mut attachments: HashMap<i32, Vec<Self>> = HashMap::new();
+
+        for record in records {
+            attachments.entry(record.message_id).or_default().push(
+                Self::new(record.id, record.file_name, record.content_type, record.size)
+            );
+        }
+
+        Ok(attachments)
+    }
+
+    /// Attaches uploaded files to a message `owner_id` sent.
+    pub async fn insert_all(
+        db: &mut PgConnection,
+        message_id: &i32,
+        owner_id: &i32,
+        attachments: &[NewAttachment],
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let object_names: Vec<String> = attachments.iter().map(|attachment| attachment.object_name.clone()).collect();
+        let file_names: Vec<String> = attachments.iter().map(|attachment| attachment.file_name.clone()).collect();
+        let content_types: Vec<String> = attachments.iter().map(|attachment| attachment.content_type.clone()).collect();
+        let sizes: Vec<i64> = attachments.iter().map(|attachment| attachment.size).collect();
+
+        let records = sqlx::query!(
+            r#"
+            INSERT INTO user_chat_attachments (message_id, owner_id, object_name, file_name, content_type, size)
+            SELECT $1, $2, object_name, file_name, content_type, size
+            FROM UNNEST($3::text[], $4::text[], $5::text[], $6::bigint[]) AS attachment(object_name, file_name, content_type, size)
+            RETURNING id, file_name, content_type, size;
+            "#,
+            message_id,
+            owner_id,
+            &object_names,
+            &file_names,
+            &content_types,
+            &sizes
+        ).fetch_all(&mut *db).await?;
+
+        Ok(records.into_iter().map(|record| Self::new(record.id, record.file_name, record.content_type, record.size)).collect())
+    }
+
+    /// How many bytes of attachments `owner_id` has uploaded, to check against their quota.
+    pub async fn get_total_size_of_user(
+        db: &mut PgConnection,
+        owner_id: &i32,
+    ) -> Result<i64, sqlx::Error> {
+        let record = sqlx::query!(
+            r#"
+            SELECT COALESCE(SUM(size), 0)::bigint AS "total!"
+            FROM user_chat_attachments
+            WHERE owner_id = $1
+            "#,
+            owner_id
+        ).fetch_one(&mut *db).await?;
+
+        Ok(record.total)
+    }
+
+    /// Where an attachment is stored, to download it.
+    pub async fn get_stored(
+        db: &mut PgConnection,
+        id: &i32,
+    ) -> Result<Option<StoredAttachment>, sqlx::Error> {
+        sqlx::query_as!(
+            StoredAttachment,
+            r#"
+            SELECT
+            user_chat_attachments.object_name,
+            user_chat_attachments.file_name,
+            user_chat_attachments.content_type,
+            user_chats.conversation_id
+            FROM user_chat_attachments
+            JOIN user_chats ON user_chats.id = user_chat_attachments.message_id
+            WHERE user_chat_attachments.id = $1
+            AND (user_chats.expires_at IS NULL OR user_chats.expires_at > CURRENT_TIMESTAMP)
+            "#,
+            id
+        ).fetch_optional(&mut *db).await
+    }
+}
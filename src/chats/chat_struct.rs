@@ -1,9 +1,528 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use rocket::form::{self, FromForm, FromFormField, ValueField};
 use rocket_db_pools::Connection;
 use serde::{Deserialize, Serialize};
-use sqlx::Acquire;
-use time::{Date, OffsetDateTime};
+use sqlx::{Acquire, PgConnection};
+use time::OffsetDateTime;
+
+use crate::{consts, db::Db, user::user_struct::Gender, utils::{get_placeholder_display_image, markdown}};
+
+use super::{attachment::{Attachment, NewAttachment}, conversation::Conversation, topics::Topic};
+
+pub fn format_created_at(created_at: &OffsetDateTime) -> String {
+    format!("{}-{}-{} at {}:{}:{}", created_at.year(), created_at.month(), created_at.day(), created_at.hour(), created_at.minute(), created_at.second())
+}
+
+/// A snippet of the message another message replies to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct QuotedMessage {
+    pub id: i32,
+    pub sender_id: i32,
+    /// Empty once the message is deleted for everyone
+    pub message: String,
+    pub deleted: bool,
+    /// Shown as it was typed instead of as markdown
+    pub is_plain: bool,
+}
+
+impl QuotedMessage {
+    /// Builds it from the columns of a `LEFT JOIN` on `reply_to`, which are all null without a reply.
+    pub fn from_columns(
+        id: Option<i32>,
+        sender_id: Option<i32>,
+        message: Option<String>,
+        deleted_at: Option<OffsetDateTime>,
+        is_plain: Option<bool>,
+    ) -> Option<Self> {
+        Some(Self {
+            id: id?,
+            sender_id: sender_id?,
+            message: message?,
+            deleted: deleted_at.is_some(),
+            is_plain: is_plain.unwrap_or_default(),
+        })
+    }
+}
+
+/// Where a message is on its way to its recipients.
+#[derive(Debug, Clone, Copy, PartialEq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "message_status", rename_all = "lowercase")]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum MessageStatus {
+    /// Saved, but none of the recipients got it yet
+    Sent,
+    /// Shown to at least one recipient
+    Delivered,
+    /// The recipient opened the conversation
+    Seen,
+}
+
+/// Every message `sender_id` sent to a reader that the reader has now seen.
+#[derive(Debug, Clone)]
+pub struct ReadReceipt {
+    pub last_read_message_id: i32,
+    pub read_at: OffsetDateTime,
+    /// The messages that were not seen before
+    pub message_ids: Vec<i32>,
+}
+
+/// A message that was deleted once its timer ran out, see [`Chat::delete_expired`].
+#[derive(Debug, Clone)]
+pub struct ExpiredMessage {
+    pub id: i32,
+    pub conversation_id: i32,
+    pub sender_id: i32,
+    /// `None` in groups
+    pub receiver_id: Option<i32>,
+    /// The objects its attachments were stored as
+    pub object_names: Vec<String>,
+}
+
+/// Everyone who reacted to a message with the same emoji.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Reaction {
+    pub emoji: String,
+    pub count: i64,
+    /// In the order they reacted
+    pub user_ids: Vec<i32>,
+}
+
+impl Reaction {
+    pub fn has_reacted(&self, user_id: i32) -> bool {
+        self.user_ids.contains(&user_id)
+    }
+
+    /// Gets the reactions to each message, in the order they were first used.
+    pub async fn get_by_message_ids(
+        db: &mut PgConnection,
+        message_ids: &[i32],
+    ) -> Result<HashMap<i32, Vec<Self>>, sqlx::Error> {
+        let records = sqlx::query!(
+            r#"
+            SELECT
+            message_id,
+            emoji,
+            COUNT(*) AS "count!",
+            ARRAY_AGG(user_id ORDER BY created_at) AS "user_ids!"
+            FROM user_chat_reactions
+            WHERE message_id = ANY($1)
+            GROUP BY message_id, emoji
+            ORDER BY MIN(created_at)
+            "#,
+            message_ids
+        ).fetch_all(&mut *db).await?;
+
+        let mut reactions: HashMap<i32, Vec<Self>> = HashMap::new();
+
+        for record in records {
+            reactions.entry(record.message_id).or_default().push(Self {
+                emoji: record.emoji,
+                count: record.count,
+                user_ids: record.user_ids,
+            });
+        }
+
+        Ok(reactions)
+    }
 
-use crate::{db::Db, user::user_struct::Gender, utils::get_placeholder_display_image};
+    /// Reacts to a message with `emoji`, or takes the reaction back if `user_id` already reacted with it.
+    /// Returns whether the reaction was added.
+    pub async fn toggle(
+        db: &mut PgConnection,
+        user_id: &i32,
+        message_id: &i32,
+        emoji: &String,
+    ) -> Result<bool, sqlx::Error> {
+        let record = sqlx::query!(
+            r#"
+            WITH removed AS (
+                DELETE FROM user_chat_reactions
+                WHERE message_id = $1 AND user_id = $2 AND emoji = $3
+                RETURNING message_id
+            ), added AS (
+                INSERT INTO user_chat_reactions (message_id, user_id, emoji)
+                SELECT $1, $2, $3
+                WHERE NOT EXISTS (SELECT 1 FROM removed)
+                RETURNING message_id
+            )
+            SELECT EXISTS (SELECT 1 FROM added) AS "added!";
+            "#,
+            message_id,
+            user_id,
+            emoji
+        ).fetch_one(&mut *db).await?;
+
+        Ok(record.added)
+    }
+}
+
+/// A message as it travels through the chat queue
+/// to every SSE and WebSocket subscriber.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChatRoomMessage {
+    pub id: Option<i32>,
+    pub conversation_id: i32,
+    pub sender_id: i32,
+    /// `None` in groups, where every member receives the message
+    pub receiver_id: Option<i32>,
+    pub message: String,
+    pub created_at: Option<String>,
+    pub edited_at: Option<String>,
+    /// Set once the message is deleted for everyone, its text is then empty.
+    pub deleted_at: Option<String>,
+    /// The id of the message this one replies to
+    pub reply_to: Option<i32>,
+    pub quoted: Option<QuotedMessage>,
+    pub reactions: Vec<Reaction>,
+    pub attachments: Vec<Attachment>,
+    pub status: MessageStatus,
+    /// The id the sender gave the message, if any
+    pub client_id: Option<String>,
+    /// Shown as it was typed instead of as markdown
+    pub is_plain: bool,
+    /// Written by the server, like when disappearing messages are turned on
+    pub is_system: bool,
+}
+
+/// A message someone is about to send
+pub struct NewMessage {
+    pub message: String,
+    /// The id of the message it replies to
+    pub reply_to: Option<i32>,
+    /// Generated by the sender so a message sent twice is only saved once,
+    /// see [`ChatRoomMessage::get_by_client_id`].
+    pub client_id: Option<String>,
+    /// Shown as it was typed instead of as markdown
+    pub is_plain: bool,
+    /// Written by the server instead of the sender, it never disappears
+    pub is_system: bool,
+}
+
+impl NewMessage {
+    /// Leaves out an empty `client_id`.
+    pub fn new(message: String, reply_to: Option<i32>, client_id: Option<String>, is_plain: bool) -> Self {
+        Self {
+            message,
+            reply_to,
+            client_id: client_id.filter(|client_id| !client_id.is_empty()),
+            is_plain,
+            is_system: false,
+        }
+    }
+
+    /// A message about the conversation itself, sent in the name of whoever changed it.
+    pub fn system(message: String) -> Self {
+        Self {
+            message,
+            reply_to: None,
+            client_id: None,
+            is_plain: true,
+            is_system: true,
+        }
+    }
+
+    pub fn has_valid_client_id(&self) -> bool {
+        match &self.client_id {
+            Some(client_id) => client_id.len() <= consts::MAX_CLIENT_ID_LENGTH,
+            None => true,
+        }
+    }
+}
+
+/// A row of `user_chats`, along with the message it replies to.
+/// Every query that gets whole messages selects these columns.
+pub(crate) struct MessageRow {
+    pub(crate) id: i32,
+    pub(crate) conversation_id: i32,
+    pub(crate) owner_id: i32,
+    pub(crate) receiver_id: Option<i32>,
+    pub(crate) message: String,
+    pub(crate) created_at: OffsetDateTime,
+    pub(crate) edited_at: Option<OffsetDateTime>,
+    pub(crate) deleted_at: Option<OffsetDateTime>,
+    pub(crate) status: MessageStatus,
+    pub(crate) client_id: Option<String>,
+    pub(crate) is_plain: bool,
+    pub(crate) is_system: bool,
+    pub(crate) quoted_id: Option<i32>,
+    pub(crate) quoted_owner_id: Option<i32>,
+    pub(crate) quoted_message: Option<String>,
+    pub(crate) quoted_deleted_at: Option<OffsetDateTime>,
+    pub(crate) quoted_is_plain: Option<bool>,
+}
+
+impl From<MessageRow> for ChatRoomMessage {
+    fn from(row: MessageRow) -> Self {
+        let quoted = QuotedMessage::from_columns(row.quoted_id, row.quoted_owner_id, row.quoted_message, row.quoted_deleted_at, row.quoted_is_plain);
+
+        Self {
+            id: Some(row.id),
+            conversation_id: row.conversation_id,
+            sender_id: row.owner_id,
+            receiver_id: row.receiver_id,
+            message: row.message,
+            created_at: Some(format_created_at(&row.created_at)),
+            edited_at: row.edited_at.as_ref().map(format_created_at),
+            deleted_at: row.deleted_at.as_ref().map(format_created_at),
+            reply_to: quoted.as_ref().map(|quoted| quoted.id),
+            quoted,
+            reactions: Vec::new(),
+            attachments: Vec::new(),
+            status: row.status,
+            client_id: row.client_id,
+            is_plain: row.is_plain,
+            is_system: row.is_system,
+        }
+    }
+}
+
+impl ChatRoomMessage {
+    /// The message as `user_id` sees it in the conversation.
+    pub fn to_chat_message(&self, user_id: i32) -> ChatMessage {
+        ChatMessage {
+            id: self.id.unwrap_or_default(),
+            is_receiver_message: self.sender_id != user_id,
+            message: self.message.clone(),
+            sender_id: self.sender_id,
+            created_at: self.created_at.clone().unwrap_or_default(),
+            receiver_id: self.receiver_id,
+            viewer_id: user_id,
+            edited_at: self.edited_at.clone(),
+            deleted_at: self.deleted_at.clone(),
+            quoted: self.quoted.clone(),
+            reactions: self.reactions.clone(),
+            attachments: self.attachments.clone(),
+            status: self.status,
+            is_plain: self.is_plain,
+            is_system: self.is_system,
+        }
+    }
+
+    /// What the message says in previews, like the sidebar.
+    pub fn preview(&self) -> Cow<'_, str> {
+        markdown::preview(&self.message, self.is_plain)
+    }
+
+    /// Every topic this message has to be published to.
+    pub fn topics(&self) -> Vec<Topic> {
+        Topic::for_message(self.conversation_id, self.sender_id, self.receiver_id)
+    }
+
+    pub async fn get_by_id(
+        db: &mut PgConnection,
+        id: &i32,
+    ) -> Result<Self, sqlx::Error> {
+        let row = sqlx::query_as!(
+            MessageRow,
+            r#"
+            SELECT
+            user_chats.id,
+            user_chats.conversation_id,
+            user_chats.owner_id,
+            user_chats.receiver_id,
+            user_chats.message,
+            user_chats.created_at,
+            user_chats.edited_at,
+            user_chats.deleted_at,
+            user_chats.status AS "status: MessageStatus",
+            user_chats.client_id,
+            user_chats.is_plain,
+            user_chats.is_system,
+            quoted.id AS "quoted_id?",
+            quoted.owner_id AS "quoted_owner_id?",
+            quoted.message AS "quoted_message?",
+            quoted.deleted_at AS "quoted_deleted_at?",
+            quoted.is_plain AS "quoted_is_plain?"
+            FROM user_chats
+            LEFT JOIN user_chats quoted ON quoted.id = user_chats.reply_to
+            WHERE user_chats.id = $1
+            "#,
+            id
+        ).fetch_one(&mut *db).await?;
+
+        let mut msg = Self::from(row);
+
+        if let Some(reactions) = Reaction::get_by_message_ids(db, &[*id]).await?.remove(id) {
+            msg.reactions = reactions;
+        }
+
+        if let Some(attachments) = Attachment::get_by_message_ids(db, &[*id]).await?.remove(id) {
+            msg.attachments = attachments;
+        }
+
+        Ok(msg)
+    }
+
+    /// Messages of a conversation sent after `after_id`, oldest first,
+    /// without the ones `user_id` deleted for themselves.
+    /// Catches up subscribers that missed some of them.
+    pub async fn get_after(
+        db: &mut PgConnection,
+        user_id: &i32,
+        conversation_id: &i32,
+        after_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let rows = sqlx::query_as!(
+            MessageRow,
+            r#"
+            SELECT
+            user_chats.id,
+            user_chats.conversation_id,
+            user_chats.owner_id,
+            user_chats.receiver_id,
+            user_chats.message,
+            user_chats.created_at,
+            user_chats.edited_at,
+            user_chats.deleted_at,
+            user_chats.status AS "status: MessageStatus",
+            user_chats.client_id,
+            user_chats.is_plain,
+            user_chats.is_system,
+            quoted.id AS "quoted_id?",
+            quoted.owner_id AS "quoted_owner_id?",
+            quoted.message AS "quoted_message?",
+            quoted.deleted_at AS "quoted_deleted_at?",
+            quoted.is_plain AS "quoted_is_plain?"
+            FROM user_chats
+            LEFT JOIN user_chats quoted ON quoted.id = user_chats.reply_to
+            WHERE user_chats.conversation_id = $1
+            AND user_chats.id > $2
+            AND (user_chats.expires_at IS NULL OR user_chats.expires_at > CURRENT_TIMESTAMP)
+            AND NOT EXISTS (
+                SELECT 1 FROM user_chat_hidden_messages hidden
+                WHERE hidden.user_id = $3 AND hidden.message_id = user_chats.id
+            )
+            ORDER BY user_chats.id
+            LIMIT $4
+            "#,
+            conversation_id,
+            after_id,
+            user_id,
+            limit
+        ).fetch_all(&mut *db).await?;
+
+        let message_ids: Vec<i32> = rows.iter().map(|row| row.id).collect();
+        let mut reactions = Reaction::get_by_message_ids(db, &message_ids).await?;
+        let mut attachments = Attachment::get_by_message_ids(db, &message_ids).await?;
+
+        Ok(rows.into_iter().map(|row| {
+            let id = row.id;
+            let mut msg = Self::from(row);
+
+            msg.reactions = reactions.remove(&id).unwrap_or_default();
+            msg.attachments = attachments.remove(&id).unwrap_or_default();
+
+            msg
+        }).collect())
+    }
+
+    /// The id of the latest message of a conversation, if it has any.
+    pub async fn get_latest_id(
+        db: &mut PgConnection,
+        conversation_id: &i32,
+    ) -> Result<Option<i32>, sqlx::Error> {
+        let record = sqlx::query!(
+            "SELECT MAX(id) AS id FROM user_chats WHERE conversation_id = $1",
+            conversation_id
+        ).fetch_one(&mut *db).await?;
+
+        Ok(record.id)
+    }
+
+    /// The message `sender_id` already sent with `client_id`, if any.
+    pub async fn get_by_client_id(
+        db: &mut PgConnection,
+        sender_id: &i32,
+        client_id: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let record = sqlx::query!(
+            "SELECT id FROM user_chats WHERE owner_id = $1 AND client_id = $2",
+            sender_id,
+            client_id
+        ).fetch_optional(&mut *db).await?;
+
+        match record {
+            Some(record) => Ok(Some(Self::get_by_id(db, &record.id).await?)),
+            None => Ok(None)
+        }
+    }
+
+    /// Whether saving a message failed because its sender already sent one with the same `client_id`.
+    /// Happens when the same message is sent twice at the same time.
+    pub fn is_duplicate(err: &sqlx::Error) -> bool {
+        err.as_database_error()
+            .and_then(|err| err.constraint())
+            .is_some_and(|constraint| constraint == "user_chats_owner_id_client_id_key")
+    }
+
+    /// Saves a message to a conversation and returns it.
+    /// `receiver_id` and `receiver_display_name` are only set in 1:1 conversations.
+    /// `reply_to` is ignored unless it is a message of the same conversation.
+    pub async fn insert(
+        db: &mut PgConnection,
+        conversation_id: &i32,
+        sender_id: &i32,
+        receiver_id: Option<&i32>,
+        receiver_display_name: Option<&String>,
+        new_message: &NewMessage,
+    ) -> Result<Self, sqlx::Error> {
+        let row = sqlx::query_as!(
+            MessageRow,
+            r#"
+            WITH inserted AS (
+                INSERT INTO user_chats (
+                    conversation_id, owner_id, receiver_id, receiver_display_name, message, reply_to, client_id, is_plain, is_system,
+                    disappear_after_seconds
+                )
+                SELECT $1, $2, $3, $4, $5, (
+                    SELECT id FROM user_chats
+                    WHERE id = $6 AND conversation_id = $1
+                ), $7, $8, $9,
+                CASE WHEN NOT $9 THEN conversations.disappear_after_seconds END
+                FROM conversations
+                WHERE conversations.id = $1
+                RETURNING id, conversation_id, owner_id, receiver_id, message, created_at, edited_at, deleted_at, status, reply_to, client_id, is_plain, is_system
+            )
+            SELECT
+            inserted.id AS "id!",
+            inserted.conversation_id AS "conversation_id!",
+            inserted.owner_id AS "owner_id!",
+            inserted.receiver_id,
+            inserted.message AS "message!",
+            inserted.created_at AS "created_at!",
+            inserted.edited_at,
+            inserted.deleted_at,
+            inserted.status AS "status!: MessageStatus",
+            inserted.client_id,
+            inserted.is_plain AS "is_plain!",
+            inserted.is_system AS "is_system!",
+            quoted.id AS "quoted_id?",
+            quoted.owner_id AS "quoted_owner_id?",
+            quoted.message AS "quoted_message?",
+            quoted.deleted_at AS "quoted_deleted_at?",
+            quoted.is_plain AS "quoted_is_plain?"
+            FROM inserted
+            LEFT JOIN user_chats quoted ON quoted.id = inserted.reply_to;
+            "#,
+            conversation_id,
+            sender_id,
+            receiver_id,
+            receiver_display_name,
+            new_message.message,
+            new_message.reply_to,
+            new_message.client_id,
+            new_message.is_plain,
+            new_message.is_system
+        ).fetch_one(&mut *db).await?;
+
+        Ok(Self::from(row))
+    }
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Chat {
@@ -22,11 +541,66 @@ pub struct Chat {
     pub sender_avatar: String,
     #[serde(rename = "created_at")]
     pub created_at: OffsetDateTime,
+    /// The latest message as it is shown in previews
     pub message: String,
+    /// Messages the user has not read yet
+    #[serde(rename = "unreadCount")]
+    pub unread_count: i64,
+}
+
+/// Points at the oldest message of a page of messages.
+/// Passed around as `<created_at in microseconds>_<id>`, e.g. `1707753600000000_42`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct MessageCursor {
+    pub created_at: OffsetDateTime,
+    pub id: i32,
+}
+
+impl MessageCursor {
+    pub fn to_param(&self) -> String {
+        format!("{}_{}", self.created_at.unix_timestamp_nanos() / 1000, self.id)
+    }
+
+    pub fn from_param(param: &str) -> Option<Self> {
+        let (created_at, id) = param.split_once('_')?;
+        let created_at = OffsetDateTime::from_unix_timestamp_nanos(created_at.parse::<i128>().ok()? * 1000).ok()?;
+
+        Some(Self {
+            created_at,
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl<'v> FromFormField<'v> for MessageCursor {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        MessageCursor::from_param(field.value)
+            .ok_or_else(|| form::Error::validation("Invalid cursor.").into())
+    }
+}
+
+/// Where the page of newer messages starts when a conversation was opened at an older message.
+/// Messages newer than `until` are sent by the conversation's event stream instead.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, FromForm)]
+pub struct NewerMessages {
+    pub after: MessageCursor,
+    pub until: i32,
+}
+
+/// Which messages to load to show a part of a conversation that is not its latest messages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageWindow {
+    /// Where loading the latest `limit` messages starts, going back
+    pub before: Option<MessageCursor>,
+    pub limit: i64,
+    /// `None` once there are no newer messages left to load
+    pub newer: Option<NewerMessages>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ChatMessage {
+    pub id: i32,
     #[serde(rename = "isReceiverMessage")]
     pub is_receiver_message: bool,
     pub message: String,
@@ -34,8 +608,26 @@ pub struct ChatMessage {
     pub sender_id: i32,
     #[serde(rename = "createdAt")]
     pub created_at: String,
+    /// `None` in groups
     #[serde(rename = "receiverId")]
-    pub receiver_id: i32,
+    pub receiver_id: Option<i32>,
+    /// Whoever the message is shown to
+    #[serde(rename = "viewerId")]
+    pub viewer_id: i32,
+    #[serde(rename = "editedAt")]
+    pub edited_at: Option<String>,
+    #[serde(rename = "deletedAt")]
+    pub deleted_at: Option<String>,
+    pub quoted: Option<QuotedMessage>,
+    pub reactions: Vec<Reaction>,
+    pub attachments: Vec<Attachment>,
+    pub status: MessageStatus,
+    /// Shown as it was typed instead of as markdown
+    #[serde(rename = "isPlain")]
+    pub is_plain: bool,
+    /// Written by the server, like when disappearing messages are turned on
+    #[serde(rename = "isSystem")]
+    pub is_system: bool,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -54,95 +646,512 @@ pub struct MessagesInChat {
     pub sender_id: i32,
     #[serde(rename = "senderAvatar")]
     pub sender_avatar: String,
-    pub messages: Vec<ChatMessage>
+    pub messages: Vec<ChatMessage>,
+    /// Where the next page of older messages starts, if there is one.
+    pub before: Option<MessageCursor>,
+    /// Where the next page of newer messages starts, if the latest ones were left out.
+    pub newer: Option<NewerMessages>,
+    /// `None` until one of them sends the first message
+    #[serde(rename = "conversationId")]
+    pub conversation_id: Option<i32>,
+    /// How long new messages last once they are seen, `None` unless they disappear
+    #[serde(rename = "disappearAfterSeconds")]
+    pub disappear_after_seconds: Option<i32>,
 }
 
-impl MessagesInChat {
-    pub fn new(
-        id: String,
-        receiver_name: String,
-        receiver_id: i32,
-        receiver_avatar: String,
-        sender_name: String,
-        sender_id: i32,
-        sender_avatar: String,
-        messages: Vec<ChatMessage>
-    ) -> Self {
-        Self {
-            id,
-            receiver_name,
-            receiver_id,
-            receiver_avatar,
-            sender_name,
+impl Chat {
+    /// Returns the saved message, along with the files attached to it.
+    /// `reply_to` is ignored unless it is a message of the same conversation.
+    pub async fn save_chat(
+        db: &mut PgConnection,
+        sender_id: &i32,
+        receiver_id: &i32,
+        receiver_display_name: &String,
+        new_message: &NewMessage,
+        attachments: &[NewAttachment],
+    ) -> Result<ChatRoomMessage, sqlx::Error> {
+        // Subscribers are notified once this commits, so the attachments are there by then.
+        let mut transaction = db.begin().await?;
+        let conversation_id = Conversation::get_or_create_direct(&mut transaction, sender_id, receiver_id).await?;
+
+        let mut msg = ChatRoomMessage::insert(
+            &mut transaction,
+            &conversation_id,
             sender_id,
-            sender_avatar,
-            messages
+            Some(receiver_id),
+            Some(receiver_display_name),
+            new_message
+        ).await?;
+
+        if !attachments.is_empty() {
+            msg.attachments = Attachment::insert_all(&mut transaction, &msg.id.unwrap_or_default(), sender_id, attachments).await?;
         }
+
+        transaction.commit().await?;
+
+        Ok(msg)
     }
-}
 
-impl Chat {
-    pub fn new(
-        id: i32,
-        receiver_name: String,
-        receiver_id: i32,
-        receiver_avatar: String,
-        sender_name: String,
-        sender_id: i32,
-        sender_avatar: String,
-        created_at: OffsetDateTime,
-        message: String,
-    ) -> Self {
-        Self {
+    /// Replaces the text of a message `owner_id` sent, keeping the previous text
+    /// in `user_chat_revisions`. Returns `None` if they did not send it.
+    pub async fn edit_chat(
+        db: &mut PgConnection,
+        owner_id: &i32,
+        message_id: &i32,
+        message: &String,
+    ) -> Result<Option<ChatRoomMessage>, sqlx::Error> {
+        let record = sqlx::query!(
+            r#"
+            WITH previous AS (
+                SELECT id, message FROM user_chats
+                WHERE id = $1 AND owner_id = $2 AND deleted_at IS NULL
+                AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                FOR UPDATE
+            ), revision AS (
+                INSERT INTO user_chat_revisions (message_id, message)
+                SELECT id, message FROM previous
+            )
+            UPDATE user_chats
+            SET message = $3, edited_at = CURRENT_TIMESTAMP
+            FROM previous
+            WHERE user_chats.id = previous.id
+            RETURNING user_chats.id;
+            "#,
+            message_id,
+            owner_id,
+            message
+        ).fetch_optional(&mut *db).await?;
+
+        match record {
+            // Gets it again for what it quotes.
+            Some(record) => Ok(Some(ChatRoomMessage::get_by_id(db, &record.id).await?)),
+            None => Ok(None)
+        }
+    }
+
+    /// Removes the text of a message `owner_id` sent, along with its revisions, reactions and attachments,
+    /// leaving a placeholder behind. Returns it with the storage objects of its attachments,
+    /// which are left for the caller to delete, or `None` if they did not send it.
+    pub async fn delete_for_everyone(
+        db: &mut PgConnection,
+        owner_id: &i32,
+        message_id: &i32,
+    ) -> Result<Option<(ChatRoomMessage, Vec<String>)>, sqlx::Error> {
+        let record = sqlx::query!(
+            r#"
+            WITH deleted AS (
+                UPDATE user_chats
+                SET message = '', deleted_at = CURRENT_TIMESTAMP
+                WHERE id = $1 AND owner_id = $2 AND deleted_at IS NULL
+                    RETURNING id
+            ), revisions AS (
+                DELETE FROM user_chat_revisions
+                WHERE message_id IN (SELECT id FROM deleted)
+            ), reactions AS (
+                DELETE FROM user_chat_reactions
+                WHERE message_id IN (SELECT id FROM deleted)
+            ), attachments AS (
+                DELETE FROM user_chat_attachments
+                WHERE message_id IN (SELECT id FROM deleted)
+                RETURNING object_name
+            )
+            SELECT
+            id AS "id!",
+            ARRAY(SELECT object_name FROM attachments) AS "object_names!"
+            FROM deleted;
+            "#,
+            message_id,
+            owner_id
+        ).fetch_optional(&mut *db).await?;
+
+        match record {
+            Some(record) => Ok(Some((ChatRoomMessage::get_by_id(db, &record.id).await?, record.object_names))),
+            None => Ok(None)
+        }
+    }
+
+    /// Hides a message from `user_id` only. Returns `false` if they are not part of its conversation.
+    pub async fn delete_for_me(
+        db: &mut PgConnection,
+        user_id: &i32,
+        message_id: &i32,
+    ) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO user_chat_hidden_messages (user_id, message_id)
+            SELECT $1, id FROM user_chats
+            WHERE id = $2 AND EXISTS (
+                SELECT 1 FROM conversation_members member
+                WHERE member.conversation_id = user_chats.conversation_id AND member.user_id = $1
+            )
+            ON CONFLICT (user_id, message_id) DO UPDATE SET hidden_at = user_chat_hidden_messages.hidden_at;
+            "#,
+            user_id,
+            message_id
+        ).execute(&mut *db).await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Deletes up to `limit` messages that expired, along with their revisions, reactions and attachments.
+    /// Rows being deleted are locked, so other instances skip them.
+    pub async fn delete_expired(
+        db: &mut PgConnection,
+        limit: i64,
+    ) -> Result<Vec<ExpiredMessage>, sqlx::Error> {
+        sqlx::query_as!(
+            ExpiredMessage,
+            r#"
+            WITH expired AS (
+                SELECT id FROM user_chats
+                WHERE expires_at <= CURRENT_TIMESTAMP
+                ORDER BY expires_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            ), attachments AS (
+                DELETE FROM user_chat_attachments
+                WHERE message_id IN (SELECT id FROM expired)
+                RETURNING message_id, object_name
+            ), deleted AS (
+                DELETE FROM user_chats
+                WHERE id IN (SELECT id FROM expired)
+                RETURNING id, conversation_id, owner_id, receiver_id
+            )
+            SELECT
+            deleted.id AS "id!",
+            deleted.conversation_id AS "conversation_id!",
+            deleted.owner_id AS "sender_id!",
+            deleted.receiver_id,
+            ARRAY(
+                SELECT attachments.object_name FROM attachments
+                WHERE attachments.message_id = deleted.id
+            ) AS "object_names!"
+            FROM deleted
+            ORDER BY deleted.id;
+            "#,
+            limit
+        ).fetch_all(&mut *db).await
+    }
+
+    /// The latest message between `user_id` and `other_user_id` that `user_id` has not deleted for themselves.
+    pub async fn get_latest_visible_message(
+        db: &mut PgConnection,
+        user_id: &i32,
+        other_user_id: &i32,
+    ) -> Result<Option<ChatRoomMessage>, sqlx::Error> {
+        let row = sqlx::query_as!(
+            MessageRow,
+            r#"
+            SELECT
             id,
-            receiver_name,
+            conversation_id,
+            owner_id,
             receiver_id,
-            receiver_avatar,
-            sender_name,
-            sender_id,
-            sender_avatar,
-            created_at,
             message,
-        }
+            created_at,
+            edited_at,
+            deleted_at,
+            status AS "status: MessageStatus",
+            client_id,
+            is_plain,
+            is_system,
+            NULL::int AS "quoted_id?",
+            NULL::int AS "quoted_owner_id?",
+            NULL::text AS "quoted_message?",
+            NULL::timestamptz AS "quoted_deleted_at?",
+            NULL::boolean AS "quoted_is_plain?"
+            FROM user_chats
+            WHERE LEAST(owner_id, receiver_id) = LEAST($1::int, $2::int)
+            AND GREATEST(owner_id, receiver_id) = GREATEST($1::int, $2::int)
+            AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+            AND NOT EXISTS (
+                SELECT 1 FROM user_chat_hidden_messages hidden
+                WHERE hidden.user_id = $1 AND hidden.message_id = user_chats.id
+            )
+            ORDER BY created_at DESC, id DESC
+            LIMIT 1
+            "#,
+            user_id,
+            other_user_id
+        ).fetch_optional(&mut *db).await?;
+
+        Ok(row.map(ChatRoomMessage::from))
     }
 
-    pub async fn save_chat(
-        db: &mut Connection<Db>,
+    /// The messages to load to open a conversation at `message_id`: a page from it on and a page before it.
+    /// `None` if it is not a message of the conversation `user_id` can see.
+    pub async fn messages_around(
+        db: &mut PgConnection,
+        user_id: &i32,
+        conversation_id: &i32,
+        message_id: &i32,
+    ) -> Result<Option<MessageWindow>, sqlx::Error> {
+        let target = sqlx::query!(
+            r#"
+            SELECT created_at FROM user_chats
+            WHERE id = $1 AND conversation_id = $2
+            AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+            AND EXISTS (
+                SELECT 1 FROM conversation_members member
+                WHERE member.conversation_id = $2 AND member.user_id = $3
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM user_chat_hidden_messages hidden
+                WHERE hidden.user_id = $3 AND hidden.message_id = user_chats.id
+            )
+            "#,
+            message_id,
+            conversation_id,
+            user_id
+        ).fetch_optional(&mut *db).await?;
+
+        let target = match target {
+            Some(target) => MessageCursor {
+                created_at: target.created_at,
+                id: *message_id,
+            },
+            None => return Ok(None)
+        };
+
+        let until = ChatRoomMessage::get_latest_id(db, conversation_id).await?.unwrap_or(*message_id);
+        let mut window = Self::messages_after(db, user_id, conversation_id, &target, until, consts::MESSAGES_PER_PAGE).await?;
+
+        window.limit += consts::MESSAGES_PER_PAGE;
+
+        Ok(Some(window))
+    }
+
+    /// The messages to load for a page of at most `limit` messages from `after` on, oldest first,
+    /// leaving out the ones `user_id` deleted for themselves and the ones newer than `until`.
+    pub async fn messages_after(
+        db: &mut PgConnection,
+        user_id: &i32,
+        conversation_id: &i32,
+        after: &MessageCursor,
+        until: i32,
+        limit: i64,
+    ) -> Result<MessageWindow, sqlx::Error> {
+        // One extra row is where the next page starts.
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, created_at FROM user_chats
+            WHERE conversation_id = $1
+            AND (created_at, id) >= ($2, $3)
+            AND id <= $4
+            AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+            AND NOT EXISTS (
+                SELECT 1 FROM user_chat_hidden_messages hidden
+                WHERE hidden.user_id = $6 AND hidden.message_id = user_chats.id
+            )
+            ORDER BY created_at, id
+            LIMIT $5
+            "#,
+            conversation_id,
+            after.created_at,
+            after.id,
+            until,
+            limit + 1,
+            user_id
+        ).fetch_all(&mut *db).await?;
+
+        let newer = rows.get(limit as usize).map(|row| NewerMessages {
+            after: MessageCursor {
+                created_at: row.created_at,
+                id: row.id,
+            },
+            until,
+        });
+        // Right after the last message of the page, as `before` leaves it out.
+        let before = match newer {
+            Some(newer) => Some(newer.after),
+            None => rows.last().map(|row| MessageCursor {
+                created_at: row.created_at,
+                id: row.id + 1,
+            }),
+        };
+
+        Ok(MessageWindow {
+            before,
+            limit: rows.len().min(limit as usize) as i64,
+            newer,
+        })
+    }
+
+    /// How many of the messages `sender_id` sent to `reader_id` they have not read yet.
+    pub async fn get_unread_count(
+        db: &mut PgConnection,
+        reader_id: &i32,
         sender_id: &i32,
-        receiver_id: &i32,
-        receiver_display_name: &String,
-        message: &String,
-    ) -> Result<OffsetDateTime, sqlx::Error> {
+    ) -> Result<i64, sqlx::Error> {
         let record = sqlx::query!(
             r#"
-            INSERT INTO user_chats (owner_id, receiver_id, message, receiver_display_name)
-            VALUES ($1, $2, $3, $4)
-            RETURNING created_at;
+            SELECT COUNT(*) AS "count!" FROM user_chats unread
+            WHERE unread.receiver_id = $1
+            AND unread.owner_id = $2
+            AND unread.deleted_at IS NULL
+            AND unread.id > COALESCE((
+                SELECT last_read_message_id FROM user_chat_reads
+                WHERE user_id = $1 AND other_user_id = $2
+            ), 0)
             "#,
+            reader_id,
+            sender_id
+        ).fetch_one(&mut *db).await?;
+
+        Ok(record.count)
+    }
+
+    /// Marks every message `sender_id` sent to `reader_id` as read, and seen.
+    /// Starts the timer of the ones that disappear.
+    /// Returns the id of the latest read message, when it was read and which messages were not seen before,
+    /// or `None` if there was nothing new to read.
+    pub async fn mark_as_read(
+        db: &mut PgConnection,
+        reader_id: &i32,
+        sender_id: &i32,
+    ) -> Result<Option<ReadReceipt>, sqlx::Error> {
+        let record = sqlx::query!(
+            r#"
+            WITH read AS (
+                INSERT INTO user_chat_reads (user_id, other_user_id, last_read_message_id)
+                SELECT $1, $2, MAX(id) FROM user_chats
+                WHERE owner_id = $2 AND receiver_id = $1
+                HAVING MAX(id) IS NOT NULL
+                ON CONFLICT (user_id, other_user_id) DO UPDATE
+                SET last_read_message_id = EXCLUDED.last_read_message_id,
+                read_at = CURRENT_TIMESTAMP
+                WHERE user_chat_reads.last_read_message_id < EXCLUDED.last_read_message_id
+                RETURNING last_read_message_id, read_at
+            ), seen AS (
+                UPDATE user_chats
+                SET status = 'seen',
+                expires_at = CURRENT_TIMESTAMP + make_interval(secs => user_chats.disappear_after_seconds)
+                FROM read
+                WHERE user_chats.owner_id = $2 AND user_chats.receiver_id = $1
+                AND user_chats.id <= read.last_read_message_id
+                AND user_chats.status <> 'seen'
+                RETURNING user_chats.id
+            )
+            SELECT
+            read.last_read_message_id AS "last_read_message_id!",
+            read.read_at AS "read_at!",
+            ARRAY(SELECT id FROM seen ORDER BY id) AS "message_ids!"
+            FROM read;
+            "#,
+            reader_id,
+            sender_id
+        ).fetch_optional(&mut *db).await?;
+
+        Ok(record.map(|record| ReadReceipt {
+            last_read_message_id: record.last_read_message_id,
+            read_at: record.read_at,
+            message_ids: record.message_ids,
+        }))
+    }
+
+    /// The messages `sender_id` sent `reader_id` from `first_id` up to `last_id`,
+    /// which are the ones a [`ReadReceipt`] saw for the first time.
+    pub async fn get_read_ids(
+        db: &mut PgConnection,
+        reader_id: &i32,
+        sender_id: &i32,
+        first_id: &i32,
+        last_id: &i32,
+    ) -> Result<Vec<i32>, sqlx::Error> {
+        let records = sqlx::query!(
+            r#"
+            SELECT id FROM user_chats
+            WHERE owner_id = $2 AND receiver_id = $1
+            AND id BETWEEN $3 AND $4
+            ORDER BY id
+            "#,
+            reader_id,
             sender_id,
-            receiver_id,
-            message,
-            receiver_display_name
-        ).fetch_one(&mut ***db).await?;
+            first_id,
+            last_id
+        ).fetch_all(&mut *db).await?;
+
+        Ok(records.into_iter().map(|record| record.id).collect())
+    }
+
+    /// Marks a message as delivered the first time one of its recipients gets it.
+    /// Returns `false` if it was already delivered or seen.
+    pub async fn mark_as_delivered(
+        db: &mut PgConnection,
+        message_id: &i32,
+    ) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query!(
+            "UPDATE user_chats SET status = 'delivered' WHERE id = $1 AND status = 'sent'",
+            message_id
+        ).execute(&mut *db).await?;
 
-        Ok(record.created_at)
+        Ok(res.rows_affected() > 0)
     }
 
+    /// Gets the latest `limit` messages sent before `before`, oldest first,
+    /// leaving out the ones `user_id` deleted for themselves.
     pub async fn get_messages(
         db: &mut Connection<Db>,
+        user_id: &i32,
         owner_id: &i32,
         receiver_id: &i32,
+        before: Option<&MessageCursor>,
+        limit: i64,
     ) -> Result<MessagesInChat, sqlx::Error> {
-        let user_chats = sqlx::query! (
+        // One extra row tells us if there is an older page.
+        let mut user_chats = sqlx::query! (
             r#"
-            SELECT * FROM user_chats
-            WHERE (owner_id = $1 AND receiver_id = $2)
-            OR (owner_id = $2 AND receiver_id = $1)
-            ORDER BY created_at ASC
+            SELECT
+            user_chats.id,
+            user_chats.message,
+            user_chats.owner_id,
+            user_chats.receiver_id,
+            user_chats.created_at,
+            user_chats.edited_at,
+            user_chats.deleted_at,
+            user_chats.status AS "status: MessageStatus",
+            user_chats.client_id,
+            user_chats.is_plain,
+            user_chats.is_system,
+            quoted.id AS "quoted_id?",
+            quoted.owner_id AS "quoted_owner_id?",
+            quoted.message AS "quoted_message?",
+            quoted.deleted_at AS "quoted_deleted_at?",
+            quoted.is_plain AS "quoted_is_plain?"
+            FROM user_chats
+            LEFT JOIN user_chats quoted ON quoted.id = user_chats.reply_to
+            WHERE LEAST(user_chats.owner_id, user_chats.receiver_id) = LEAST($1::int, $2::int)
+            AND GREATEST(user_chats.owner_id, user_chats.receiver_id) = GREATEST($1::int, $2::int)
+            AND ($3::timestamptz IS NULL OR (user_chats.created_at, user_chats.id) < ($3::timestamptz, $4::int))
+            AND (user_chats.expires_at IS NULL OR user_chats.expires_at > CURRENT_TIMESTAMP)
+            AND NOT EXISTS (
+                SELECT 1 FROM user_chat_hidden_messages hidden
+                WHERE hidden.user_id = $6 AND hidden.message_id = user_chats.id
+            )
+            ORDER BY user_chats.created_at DESC, user_chats.id DESC
+            LIMIT $5
             "#,
             owner_id,
-            receiver_id
+            receiver_id,
+            before.map(|cursor| cursor.created_at),
+            before.map(|cursor| cursor.id),
+            limit + 1,
+            user_id
         ).fetch_all(&mut ***db).await?;
+
+        let has_older_messages = user_chats.len() as i64 > limit;
+
+        user_chats.truncate(limit as usize);
+        user_chats.reverse();
+
+        let before = if has_older_messages {
+            user_chats.first().map(|chat| MessageCursor {
+                created_at: chat.created_at,
+                id: chat.id,
+            })
+        } else {
+            None
+        };
         
         let user_chat_owner = sqlx::query! (
             r#"
@@ -170,39 +1179,58 @@ impl Chat {
             receiver_id
         ).fetch_one(&mut ***db).await?;
 
+        let message_ids: Vec<i32> = user_chats.iter().map(|chat| chat.id).collect();
+        let mut reactions = Reaction::get_by_message_ids(db, &message_ids).await?;
+        let mut attachments = Attachment::get_by_message_ids(db, &message_ids).await?;
         let mut messages = Vec::new();
 
         for chat in user_chats {
-            let date = chat.created_at.date();
-
             messages.push(ChatMessage {
-                is_receiver_message: chat.receiver_id != *receiver_id,
+                id: chat.id,
+                is_receiver_message: chat.receiver_id != Some(*receiver_id),
                 message: chat.message,
                 sender_id: chat.owner_id,
-                created_at: format!("{}-{}-{} at {}:{}:{}", date.year(), date.month(), date.day(), chat.created_at.hour(), chat.created_at.minute(), chat.created_at.second()),
+                created_at: format_created_at(&chat.created_at),
                 receiver_id: chat.receiver_id,
+                viewer_id: *user_id,
+                edited_at: chat.edited_at.as_ref().map(format_created_at),
+                deleted_at: chat.deleted_at.as_ref().map(format_created_at),
+                quoted: QuotedMessage::from_columns(chat.quoted_id, chat.quoted_owner_id, chat.quoted_message, chat.quoted_deleted_at, chat.quoted_is_plain),
+                reactions: reactions.remove(&chat.id).unwrap_or_default(),
+                attachments: attachments.remove(&chat.id).unwrap_or_default(),
+                status: chat.status,
+                is_plain: chat.is_plain,
+                is_system: chat.is_system,
             });
         }
 
         let user_chat_user_display_image = get_placeholder_display_image(user_chat_owner.display_image.as_ref(), &user_chat_owner.gender);
         let user_chat_receiver_display_image = get_placeholder_display_image(user_chat_receiver.display_image.as_ref(), &user_chat_receiver.gender);
 
-        let messages_in_chat = MessagesInChat::new(
-            format!("sender_id={}&receiver_id={}", owner_id, receiver_id),
-            user_chat_receiver.display_name,
-            user_chat_receiver.id,
-            user_chat_receiver_display_image,
-            user_chat_owner.display_name,
-            user_chat_owner.id,
-            user_chat_user_display_image,
-            messages,
-        );
+        let conversation_id = Conversation::get_direct_id(db, owner_id, receiver_id).await?;
+        let disappear_after_seconds = match &conversation_id {
+            Some(conversation_id) => Conversation::get_disappear_after(db, conversation_id).await?,
+            None => None,
+        };
 
-        Ok(messages_in_chat)
+        Ok(MessagesInChat {
+            id: format!("sender_id={}&receiver_id={}", owner_id, receiver_id),
+            receiver_name: user_chat_receiver.display_name,
+            receiver_id: user_chat_receiver.id,
+            receiver_avatar: user_chat_receiver_display_image,
+            sender_name: user_chat_owner.display_name,
+            sender_id: user_chat_owner.id,
+            sender_avatar: user_chat_user_display_image,
+            messages,
+            before,
+            newer: None,
+            conversation_id,
+            disappear_after_seconds,
+        })
     }
 
     pub async fn get_user_chats(
-        db: &mut Connection<Db>,
+        db: &mut PgConnection,
         user_id: &i32,
         search: &String,
     ) -> Result<Vec<Chat>, sqlx::Error> {
@@ -217,21 +1245,39 @@ impl Chat {
                     owner_id,
                     created_at,
                     receiver_display_name,
+                    deleted_at,
+                    is_plain,
                     ROW_NUMBER() OVER (
                         PARTITION BY GREATEST(receiver_id, owner_id),
                         LEAST(receiver_id, owner_id)
                         ORDER BY created_at DESC
                     ) AS rn
                     FROM user_chats
-                    WHERE owner_id = $1 OR receiver_id = $1
+                    WHERE (owner_id = $1 OR receiver_id = $1)
+                    AND receiver_id IS NOT NULL
+                    AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                    AND NOT EXISTS (
+                        SELECT 1 FROM user_chat_hidden_messages hidden
+                        WHERE hidden.user_id = $1 AND hidden.message_id = user_chats.id
+                    )
                 )
-                SELECT *
+                SELECT *, (
+                    SELECT COUNT(*) FROM user_chats unread
+                    WHERE unread.receiver_id = $1
+                    AND unread.owner_id = CASE WHEN LatestChats.owner_id = $1 THEN LatestChats.receiver_id ELSE LatestChats.owner_id END
+                    AND unread.deleted_at IS NULL
+                    AND unread.id > COALESCE((
+                        SELECT last_read_message_id FROM user_chat_reads
+                        WHERE user_id = $1
+                        AND other_user_id = CASE WHEN LatestChats.owner_id = $1 THEN LatestChats.receiver_id ELSE LatestChats.owner_id END
+                    ), 0)
+                ) AS "unread_count!"
                 FROM LatestChats
                 WHERE rn = 1
                 ORDER BY created_at DESC;
                 "#,
                 user_id,
-            ).fetch_all(&mut ***db).await?;
+            ).fetch_all(&mut *db).await?;
 
             let mut user_chats = Vec::new();
 
@@ -247,7 +1293,7 @@ impl Chat {
                         WHERE id = $1
                     "#,
                     chat.owner_id
-                ).fetch_one(&mut ***db).await?;
+                ).fetch_one(&mut *db).await?;
 
                 let user_chat_receiver = sqlx::query! (
                     r#"
@@ -260,22 +1306,23 @@ impl Chat {
                         WHERE id = $1
                     "#,
                     chat.receiver_id
-                ).fetch_one(&mut ***db).await?;
+                ).fetch_one(&mut *db).await?;
 
                 let user_chat_owner_display_image = get_placeholder_display_image(user_chat_owner.display_image.as_ref(), &user_chat_owner.gender);
                 let user_chat_receiver_display_image = get_placeholder_display_image(user_chat_receiver.display_image.as_ref(), &user_chat_receiver.gender);
 
-                user_chats.push(Chat::new(
-                    chat.id,
-                    user_chat_receiver.display_name,
-                    user_chat_receiver.id,
-                    user_chat_receiver_display_image,
-                    user_chat_owner.display_name,
-                    user_chat_owner.id,
-                    user_chat_owner_display_image,
-                    chat.created_at,
-                    chat.message,
-                ));
+                user_chats.push(Chat {
+                    id: chat.id,
+                    receiver_name: user_chat_receiver.display_name,
+                    receiver_id: user_chat_receiver.id,
+                    receiver_avatar: user_chat_receiver_display_image,
+                    sender_name: user_chat_owner.display_name,
+                    sender_id: user_chat_owner.id,
+                    sender_avatar: user_chat_owner_display_image,
+                    created_at: chat.created_at,
+                    message: if chat.deleted_at.is_some() { consts::DELETED_MESSAGE_PLACEHOLDER.to_string() } else { markdown::preview(&chat.message, chat.is_plain).into_owned() },
+                    unread_count: chat.unread_count,
+                });
             }
 
             Ok(user_chats)
@@ -290,23 +1337,41 @@ impl Chat {
                     owner_id,
                     created_at,
                     receiver_display_name,
+                    deleted_at,
+                    is_plain,
                     ROW_NUMBER() OVER (
                         PARTITION BY GREATEST(receiver_id, owner_id),
                         LEAST(receiver_id, owner_id)
                         ORDER BY created_at DESC
                     ) AS rn
                     FROM user_chats
-                    WHERE owner_id = $1 OR receiver_id = $1 AND
-                    similarity(receiver_display_name, $2) > 0.2
+                    WHERE (owner_id = $1 OR receiver_id = $1 AND
+                    similarity(receiver_display_name, $2) > 0.2)
+                    AND receiver_id IS NOT NULL
+                    AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                    AND NOT EXISTS (
+                        SELECT 1 FROM user_chat_hidden_messages hidden
+                        WHERE hidden.user_id = $1 AND hidden.message_id = user_chats.id
+                    )
                 )
-                SELECT *
+                SELECT *, (
+                    SELECT COUNT(*) FROM user_chats unread
+                    WHERE unread.receiver_id = $1
+                    AND unread.owner_id = CASE WHEN LatestChats.owner_id = $1 THEN LatestChats.receiver_id ELSE LatestChats.owner_id END
+                    AND unread.deleted_at IS NULL
+                    AND unread.id > COALESCE((
+                        SELECT last_read_message_id FROM user_chat_reads
+                        WHERE user_id = $1
+                        AND other_user_id = CASE WHEN LatestChats.owner_id = $1 THEN LatestChats.receiver_id ELSE LatestChats.owner_id END
+                    ), 0)
+                ) AS "unread_count!"
                 FROM LatestChats
                 WHERE rn = 1
                 ORDER BY similarity(receiver_display_name, $2) DESC;
                 "#,
                 user_id,
                 search
-            ).fetch_all(&mut ***db).await?;
+            ).fetch_all(&mut *db).await?;
 
             let mut user_chats = Vec::new();
 
@@ -322,7 +1387,7 @@ impl Chat {
                         WHERE id = $1
                     "#,
                     chat.owner_id
-                ).fetch_one(&mut ***db).await?;
+                ).fetch_one(&mut *db).await?;
 
                 let user_chat_receiver = sqlx::query! (
                     r#"
@@ -335,22 +1400,23 @@ impl Chat {
                         WHERE id = $1
                     "#,
                     chat.receiver_id
-                ).fetch_one(&mut ***db).await?;
+                ).fetch_one(&mut *db).await?;
 
                 let user_chat_owner_display_image = get_placeholder_display_image(user_chat_owner.display_image.as_ref(), &user_chat_owner.gender);
                 let user_chat_receiver_display_image = get_placeholder_display_image(user_chat_receiver.display_image.as_ref(), &user_chat_receiver.gender);
 
-                user_chats.push(Chat::new(
-                    chat.id,
-                    user_chat_receiver.display_name,
-                    user_chat_receiver.id,
-                    user_chat_receiver_display_image,
-                    user_chat_owner.display_name,
-                    user_chat_owner.id,
-                    user_chat_owner_display_image,
-                    chat.created_at,
-                    chat.message,
-                ));
+                user_chats.push(Chat {
+                    id: chat.id,
+                    receiver_name: user_chat_receiver.display_name,
+                    receiver_id: user_chat_receiver.id,
+                    receiver_avatar: user_chat_receiver_display_image,
+                    sender_name: user_chat_owner.display_name,
+                    sender_id: user_chat_owner.id,
+                    sender_avatar: user_chat_owner_display_image,
+                    created_at: chat.created_at,
+                    message: if chat.deleted_at.is_some() { consts::DELETED_MESSAGE_PLACEHOLDER.to_string() } else { markdown::preview(&chat.message, chat.is_plain).into_owned() },
+                    unread_count: chat.unread_count,
+                });
             }
 
             Ok(user_chats)
@@ -368,7 +1434,7 @@ impl Chat {
 
         let latest_chat = sqlx::query!(
             r#"
-            SELECT * FROM user_chats
+            SELECT id, owner_id, receiver_id, message, created_at FROM user_chats
             WHERE (owner_id = $1 AND receiver_id = $2)
             OR (owner_id = $2 AND receiver_id = $1)
             AND created_at = (
@@ -425,17 +1491,18 @@ impl Chat {
             }
         };
 
-        Ok(Chat::new(
-            latest_chat.id,
-            user_chat_receiver.display_name,
-            user_chat_receiver.id,
-            user_chat_receiver_display_image,
-            user_chat_owner.display_name,
-            user_chat_owner.id,
-            user_chat_owner_display_image,
-            latest_chat.created_at,
-            latest_chat.message,
-        ))
+        Ok(Chat {
+            id: latest_chat.id,
+            receiver_name: user_chat_receiver.display_name,
+            receiver_id: user_chat_receiver.id,
+            receiver_avatar: user_chat_receiver_display_image,
+            sender_name: user_chat_owner.display_name,
+            sender_id: user_chat_owner.id,
+            sender_avatar: user_chat_owner_display_image,
+            created_at: latest_chat.created_at,
+            message: latest_chat.message,
+            unread_count: 0,
+        })
         
     }
 }
@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, PgConnection};
+
+use crate::{user::user_struct::Gender, utils::{get_placeholder_display_image, markdown}};
+
+use super::{attachment::Attachment, chat_struct::{ChatMessage, ChatRoomMessage, MessageCursor, MessageRow, MessageStatus, NewMessage, NewerMessages, Reaction}};
+
+/// Someone who is, or was, part of a conversation.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ConversationMember {
+    pub id: i32,
+    #[serde(rename = "displayName")]
+    pub display_name: String,
+    #[serde(rename = "displayImage")]
+    pub display_image: String,
+}
+
+/// A 1:1 conversation or a named group.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Conversation {
+    pub id: i32,
+    /// Only groups have names
+    pub name: Option<String>,
+    #[serde(rename = "isGroup")]
+    pub is_group: bool,
+    /// Whoever created the group, they can remove other members
+    #[serde(rename = "createdBy")]
+    pub created_by: Option<i32>,
+    /// How long new messages last once they are seen, `None` unless they disappear
+    #[serde(rename = "disappearAfterSeconds")]
+    pub disappear_after_seconds: Option<i32>,
+    pub members: Vec<ConversationMember>,
+}
+
+/// A group as it is listed in the sidebar, with its latest message.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GroupPreview {
+    pub id: i32,
+    pub name: String,
+    /// The latest message as it is shown in previews
+    pub message: Option<String>,
+    #[serde(rename = "isDeleted")]
+    pub is_deleted: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MessagesInGroup {
+    pub conversation: Conversation,
+    pub messages: Vec<ChatMessage>,
+    /// Everyone who sent one of the messages, including members who left
+    pub senders: HashMap<i32, ConversationMember>,
+    /// Where the next page of older messages starts, if there is one.
+    pub before: Option<MessageCursor>,
+    /// Where the next page of newer messages starts, if the latest ones were left out.
+    pub newer: Option<NewerMessages>,
+}
+
+impl Conversation {
+    /// Gets the 1:1 conversation between two users, creating it the first time they talk.
+    pub async fn get_or_create_direct(
+        db: &mut PgConnection,
+        first_user_id: &i32,
+        second_user_id: &i32,
+    ) -> Result<i32, sqlx::Error> {
+        let existing = sqlx::query!(
+            r#"
+            SELECT id FROM conversations
+            WHERE first_user_id = LEAST($1::int, $2::int)
+            AND second_user_id = GREATEST($1::int, $2::int)
+            "#,
+            first_user_id,
+            second_user_id
+        ).fetch_optional(&mut *db).await?;
+
+        if let Some(existing) = existing {
+            return Ok(existing.id);
+        }
+
+        let created = sqlx::query!(
+            r#"
+            WITH conversation AS (
+                INSERT INTO conversations (first_user_id, second_user_id)
+                VALUES (LEAST($1::int, $2::int), GREATEST($1::int, $2::int))
+                ON CONFLICT (first_user_id, second_user_id) DO UPDATE SET first_user_id = EXCLUDED.first_user_id
+                RETURNING id
+            ), members AS (
+                INSERT INTO conversation_members (conversation_id, user_id)
+                SELECT conversation.id, member FROM conversation, UNNEST(ARRAY[$1::int, $2::int]) member
+                ON CONFLICT (conversation_id, user_id) DO NOTHING
+            )
+            SELECT id AS "id!" FROM conversation;
+            "#,
+            first_user_id,
+            second_user_id
+        ).fetch_one(&mut *db).await?;
+
+        Ok(created.id)
+    }
+
+    /// Creates a group with `created_by` and every one of `member_ids` as members.
+    pub async fn create_group(
+        db: &mut PgConnection,
+        created_by: &i32,
+        name: &String,
+        member_ids: &[i32],
+    ) -> Result<i32, sqlx::Error> {
+        let mut transaction = db.begin().await?;
+
+        let conversation = sqlx::query!(
+            r#"
+            INSERT INTO conversations (name, is_group, created_by)
+            VALUES ($1, TRUE, $2)
+            RETURNING id;
+            "#,
+            name,
+            created_by
+        ).fetch_one(&mut *transaction).await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO conversation_members (conversation_id, user_id)
+            SELECT $1, member FROM UNNEST(ARRAY_APPEND($2::int[], $3)) member
+            ON CONFLICT (conversation_id, user_id) DO NOTHING;
+            "#,
+            conversation.id,
+            member_ids,
+            created_by
+        ).execute(&mut *transaction).await?;
+
+        transaction.commit().await?;
+
+        Ok(conversation.id)
+    }
+
+    pub async fn get_by_id(
+        db: &mut PgConnection,
+        id: &i32,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        let conversation = sqlx::query!(
+            r#"
+            SELECT id, name, is_group, created_by, disappear_after_seconds FROM conversations
+            WHERE id = $1
+            "#,
+            id
+        ).fetch_optional(&mut *db).await?;
+
+        let conversation = match conversation {
+            Some(conversation) => conversation,
+            None => return Ok(None)
+        };
+
+        let members = sqlx::query!(
+            r#"
+            SELECT
+            users.id,
+            users.display_name,
+            users.display_image,
+            users.gender as "gender: Gender"
+            FROM conversation_members
+            JOIN users ON users.id = conversation_members.user_id
+            WHERE conversation_members.conversation_id = $1
+            ORDER BY conversation_members.joined_at, users.id
+            "#,
+            id
+        ).fetch_all(&mut *db).await?;
+
+        Ok(Some(Self {
+            id: conversation.id,
+            name: conversation.name,
+            is_group: conversation.is_group,
+            created_by: conversation.created_by,
+            disappear_after_seconds: conversation.disappear_after_seconds,
+            members: members.into_iter().map(|member| ConversationMember {
+                id: member.id,
+                display_image: get_placeholder_display_image(member.display_image.as_ref(), &member.gender),
+                display_name: member.display_name,
+            }).collect(),
+        }))
+    }
+
+    /// How long new messages of a conversation last once they are seen, `None` unless they disappear.
+    pub async fn get_disappear_after(db: &mut PgConnection, id: &i32) -> Result<Option<i32>, sqlx::Error> {
+        let record = sqlx::query!(
+            "SELECT disappear_after_seconds FROM conversations WHERE id = $1",
+            id
+        ).fetch_optional(&mut *db).await?;
+
+        Ok(record.and_then(|record| record.disappear_after_seconds))
+    }
+
+    /// Makes new messages of a conversation disappear `seconds` after they are seen, or not at all if it is `None`.
+    /// Returns `false` if that is already how long they last.
+    pub async fn set_disappear_after(
+        db: &mut PgConnection,
+        id: &i32,
+        seconds: Option<i32>,
+    ) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+            UPDATE conversations
+            SET disappear_after_seconds = $2
+            WHERE id = $1 AND disappear_after_seconds IS DISTINCT FROM $2
+            "#,
+            id,
+            seconds
+        ).execute(&mut *db).await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub fn has_member(&self, user_id: i32) -> bool {
+        self.members.iter().any(|member| member.id == user_id)
+    }
+
+    pub async fn is_member(db: &mut PgConnection, conversation_id: &i32, user_id: &i32) -> bool {
+        sqlx::query!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM conversation_members
+                WHERE conversation_id = $1 AND user_id = $2
+            ) AS "is_member!"
+            "#,
+            conversation_id,
+            user_id
+        )
+        .fetch_one(&mut *db).await.map(|record| record.is_member).unwrap_or(false)
+    }
+
+    /// Returns `false` if they already are a member, or it is not a group.
+    pub async fn add_member(
+        db: &mut PgConnection,
+        conversation_id: &i32,
+        user_id: &i32,
+    ) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO conversation_members (conversation_id, user_id)
+            SELECT id, $2 FROM conversations
+            WHERE id = $1 AND is_group
+            ON CONFLICT (conversation_id, user_id) DO NOTHING;
+            "#,
+            conversation_id,
+            user_id
+        ).execute(&mut *db).await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Returns `false` if they were not a member, or it is not a group.
+    pub async fn remove_member(
+        db: &mut PgConnection,
+        conversation_id: &i32,
+        user_id: &i32,
+    ) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM conversation_members
+            USING conversations
+            WHERE conversations.id = conversation_members.conversation_id
+            AND conversations.is_group
+            AND conversation_members.conversation_id = $1
+            AND conversation_members.user_id = $2;
+            "#,
+            conversation_id,
+            user_id
+        ).execute(&mut *db).await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Every group `user_id` is a member of, the most recently active first.
+    pub async fn get_groups_of_user(
+        db: &mut PgConnection,
+        user_id: &i32,
+    ) -> Result<Vec<GroupPreview>, sqlx::Error> {
+        let groups = sqlx::query!(
+            r#"
+            SELECT
+            conversations.id,
+            conversations.name AS "name!",
+            latest.message AS "message?",
+            latest.deleted_at AS "deleted_at?",
+            latest.is_plain AS "is_plain?"
+            FROM conversations
+            JOIN conversation_members ON conversation_members.conversation_id = conversations.id
+            LEFT JOIN LATERAL (
+                SELECT message, deleted_at, is_plain, created_at FROM user_chats
+                WHERE conversation_id = conversations.id
+                AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                AND NOT EXISTS (
+                    SELECT 1 FROM user_chat_hidden_messages hidden
+                    WHERE hidden.user_id = $1 AND hidden.message_id = user_chats.id
+                )
+                ORDER BY created_at DESC, id DESC
+                LIMIT 1
+            ) latest ON TRUE
+            WHERE conversations.is_group AND conversation_members.user_id = $1
+            ORDER BY COALESCE(latest.created_at, conversations.created_at) DESC
+            "#,
+            user_id
+        ).fetch_all(&mut *db).await?;
+
+        Ok(groups.into_iter().map(|group| GroupPreview {
+            id: group.id,
+            name: group.name,
+            message: group.message.map(|message| markdown::preview(&message, group.is_plain.unwrap_or_default()).into_owned()),
+            is_deleted: group.deleted_at.is_some(),
+        }).collect())
+    }
+
+    /// Everyone `user_id` shares a conversation with.
+    pub async fn get_partner_ids(
+        db: &mut PgConnection,
+        user_id: &i32,
+    ) -> Result<Vec<i32>, sqlx::Error> {
+        let records = sqlx::query!(
+            r#"
+            SELECT DISTINCT partner.user_id FROM conversation_members member
+            JOIN conversation_members partner ON partner.conversation_id = member.conversation_id
+            WHERE member.user_id = $1 AND partner.user_id <> $1
+            "#,
+            user_id
+        ).fetch_all(&mut *db).await?;
+
+        Ok(records.into_iter().map(|record| record.user_id).collect())
+    }
+
+    /// The 1:1 conversation between two users, if they ever sent each other a message.
+    pub async fn get_direct_id(
+        db: &mut PgConnection,
+        first_user_id: &i32,
+        second_user_id: &i32,
+    ) -> Result<Option<i32>, sqlx::Error> {
+        let record = sqlx::query!(
+            r#"
+            SELECT id FROM conversations
+            WHERE first_user_id = LEAST($1::int, $2::int)
+            AND second_user_id = GREATEST($1::int, $2::int)
+            "#,
+            first_user_id,
+            second_user_id
+        ).fetch_optional(&mut *db).await?;
+
+        Ok(record.map(|record| record.id))
+    }
+
+    /// Saves a message every member of the group receives.
+    pub async fn send(
+        db: &mut PgConnection,
+        sender_id: &i32,
+        conversation_id: &i32,
+        new_message: &NewMessage,
+    ) -> Result<ChatRoomMessage, sqlx::Error> {
+        ChatRoomMessage::insert(db, conversation_id, sender_id, None, None, new_message).await
+    }
+
+    /// Gets the latest `limit` messages of a group sent before `before`, oldest first,
+    /// leaving out the ones `user_id` deleted for themselves.
+    pub async fn get_messages(
+        db: &mut PgConnection,
+        user_id: &i32,
+        conversation: Conversation,
+        before: Option<&MessageCursor>,
+        limit: i64,
+    ) -> Result<MessagesInGroup, sqlx::Error> {
+        // One extra row tells us if there is an older page.
+        let mut rows = sqlx::query_as!(
+            MessageRow,
+            r#"
+            SELECT
+            user_chats.id,
+            user_chats.conversation_id,
+            user_chats.owner_id,
+            user_chats.receiver_id,
+            user_chats.message,
+            user_chats.created_at,
+            user_chats.edited_at,
+            user_chats.deleted_at,
+            user_chats.status AS "status: MessageStatus",
+            user_chats.client_id,
+            user_chats.is_plain,
+            user_chats.is_system,
+            quoted.id AS "quoted_id?",
+            quoted.owner_id AS "quoted_owner_id?",
+            quoted.message AS "quoted_message?",
+            quoted.deleted_at AS "quoted_deleted_at?",
+            quoted.is_plain AS "quoted_is_plain?"
+            FROM user_chats
+            LEFT JOIN user_chats quoted ON quoted.id = user_chats.reply_to
+            WHERE user_chats.conversation_id = $1
+            AND ($2::timestamptz IS NULL OR (user_chats.created_at, user_chats.id) < ($2::timestamptz, $3::int))
+            AND (user_chats.expires_at IS NULL OR user_chats.expires_at > CURRENT_TIMESTAMP)
+            AND NOT EXISTS (
+                SELECT 1 FROM user_chat_hidden_messages hidden
+                WHERE hidden.user_id = $5 AND hidden.message_id = user_chats.id
+            )
+            ORDER BY user_chats.created_at DESC, user_chats.id DESC
+            LIMIT $4
+            "#,
+            conversation.id,
+            before.map(|cursor| cursor.created_at),
+            before.map(|cursor| cursor.id),
+            limit + 1,
+            user_id
+        ).fetch_all(&mut *db).await?;
+
+        let has_older_messages = rows.len() as i64 > limit;
+
+        rows.truncate(limit as usize);
+        rows.reverse();
+
+        let before = if has_older_messages {
+            rows.first().map(|row| MessageCursor {
+                created_at: row.created_at,
+                id: row.id,
+            })
+        } else {
+            None
+        };
+
+        let message_ids: Vec<i32> = rows.iter().map(|row| row.id).collect();
+        let mut sender_ids: Vec<i32> = rows.iter().map(|row| row.owner_id).collect();
+
+        sender_ids.sort_unstable();
+        sender_ids.dedup();
+
+        let mut reactions = Reaction::get_by_message_ids(db, &message_ids).await?;
+        let mut attachments = Attachment::get_by_message_ids(db, &message_ids).await?;
+        let senders = Self::get_senders(db, &sender_ids).await?;
+
+        let messages = rows.into_iter().map(|row| {
+            let mut msg = ChatRoomMessage::from(row);
+
+            msg.reactions = reactions.remove(&msg.id.unwrap_or_default()).unwrap_or_default();
+            msg.attachments = attachments.remove(&msg.id.unwrap_or_default()).unwrap_or_default();
+            msg.to_chat_message(*user_id)
+        }).collect();
+
+        Ok(MessagesInGroup {
+            conversation,
+            messages,
+            senders,
+            before,
+            newer: None,
+        })
+    }
+
+    /// The name and image of everyone in `user_ids`, whether or not they are still members.
+    pub async fn get_senders(
+        db: &mut PgConnection,
+        user_ids: &[i32],
+    ) -> Result<HashMap<i32, ConversationMember>, sqlx::Error> {
+        let users = sqlx::query!(
+            r#"
+            SELECT
+            id,
+            display_name,
+            display_image,
+            gender as "gender: Gender"
+            FROM users
+            WHERE id = ANY($1)
+            "#,
+            user_ids
+        ).fetch_all(&mut *db).await?;
+
+        Ok(users.into_iter().map(|user| (user.id, ConversationMember {
+            id: user.id,
+            display_image: get_placeholder_display_image(user.display_image.as_ref(), &user.gender),
+            display_name: user.display_name,
+        })).collect())
+    }
+}
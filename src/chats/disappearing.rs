@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+
+use rocket::{fairing::AdHoc, tokio::{self, time::{interval, Duration, MissedTickBehavior}}};
+use rocket_db_pools::Database;
+use sqlx::PgPool;
+
+use crate::{consts, db::Db, utils::upload};
+
+use super::{chat_struct::{Chat, ExpiredMessage}, event::ChatEvent, pubsub::PubSub};
+
+/// How long a message lasts in its largest unit that fits it exactly, like `(90, "minute")` or `(1, "day")`.
+pub fn split_duration(seconds: i32) -> (i32, &'static str) {
+    match seconds {
+        seconds if seconds % 86400 == 0 => (seconds / 86400, "day"),
+        seconds if seconds % 3600 == 0 => (seconds / 3600, "hour"),
+        seconds if seconds % 60 == 0 => (seconds / 60, "minute"),
+        seconds => (seconds, "second"),
+    }
+}
+
+/// How long a message lasts, like "90 minutes" or "1 day"
+pub fn format_duration(seconds: i32) -> String {
+    let (amount, unit) = split_duration(seconds);
+
+    format!("{} {}{}", amount, unit, if amount == 1 { "" } else { "s" })
+}
+
+/// What the conversation is told when `display_name` changes how long its messages last.
+pub fn setting_changed_message(display_name: &str, seconds: Option<i32>) -> String {
+    match seconds {
+        Some(seconds) => format!(
+            "{} turned on disappearing messages. New messages disappear {} after they are seen.",
+            display_name,
+            format_duration(seconds)
+        ),
+        None => format!("{} turned off disappearing messages.", display_name),
+    }
+}
+
+/// One event for every conversation that had messages expire
+fn expired_events(expired: &[ExpiredMessage]) -> Vec<ChatEvent> {
+    let mut conversations: BTreeMap<i32, ChatEvent> = BTreeMap::new();
+
+    for msg in expired {
+        let event = conversations.entry(msg.conversation_id).or_insert_with(|| ChatEvent::Expired {
+            conversation_id: msg.conversation_id,
+            sender_id: msg.sender_id,
+            receiver_id: msg.receiver_id,
+            message_ids: Vec::new(),
+        });
+
+        if let ChatEvent::Expired { message_ids, .. } = event {
+            message_ids.push(msg.id);
+        }
+    }
+
+    conversations.into_values().collect()
+}
+
+/// Deletes messages that expired every [`consts::EXPIRED_MESSAGES_INTERVAL_SECONDS`],
+/// along with their files, and lets their conversations know.
+async fn delete_expired_messages(pool: PgPool, pubsub: PubSub) {
+    let mut ticks = interval(Duration::from_secs(consts::EXPIRED_MESSAGES_INTERVAL_SECONDS));
+
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticks.tick().await;
+
+        let expired = match pool.acquire().await {
+            Ok(mut conn) => Chat::delete_expired(&mut conn, consts::EXPIRED_MESSAGES_PER_BATCH).await,
+            Err(err) => Err(err),
+        };
+
+        let expired = match expired {
+            Ok(expired) => expired,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                continue;
+            }
+        };
+
+        for object_name in expired.iter().flat_map(|msg| &msg.object_names) {
+            // Already logged, the file is only left behind in storage.
+            let _ = upload::delete_file(object_name).await;
+        }
+
+        for event in expired_events(&expired) {
+            pubsub.publish(event).await;
+        }
+    }
+}
+
+/// Deletes messages once they expired. Must be attached after [`super::pubsub::stage`].
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Disappearing messages", |rocket| Box::pin(async move {
+        let pool = Db::fetch(rocket).map(|db| (**db).clone());
+        let pubsub = rocket.state::<PubSub>().cloned();
+
+        if let (Some(pool), Some(pubsub)) = (pool, pubsub) {
+            tokio::spawn(delete_expired_messages(pool, pubsub));
+        }
+    }))
+}
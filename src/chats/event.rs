@@ -0,0 +1,155 @@
+use rocket::request::{self, FromRequest, Request};
+use serde::{Deserialize, Serialize};
+
+use super::{chat_struct::{format_created_at, ChatRoomMessage, Reaction, ReadReceipt}, topics::Topic};
+
+/// Everything that can happen in a conversation that its participants need to know about.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ChatEvent {
+    Message(ChatRoomMessage),
+    /// A message that was edited, with its new text.
+    Edited(ChatRoomMessage),
+    /// A message that was deleted for everyone, without its text.
+    Deleted(ChatRoomMessage),
+    /// Someone reacted to a message `sender_id` sent, or took their reaction back.
+    /// Has every reaction the message now has.
+    Reacted {
+        message_id: i32,
+        conversation_id: i32,
+        sender_id: i32,
+        /// `None` in groups
+        receiver_id: Option<i32>,
+        reactions: Vec<Reaction>,
+    },
+    /// `user_id` deleted a message of a conversation for themselves.
+    /// Only `user_id` should act on it.
+    Hidden {
+        user_id: i32,
+        conversation_id: i32,
+        /// The other participant of a 1:1 conversation, `None` in groups
+        other_user_id: Option<i32>,
+        message_id: i32,
+    },
+    /// Messages of a conversation disappeared once their timer ran out.
+    /// `sender_id` and `receiver_id` are those of one of them, which in 1:1 conversations are its participants.
+    Expired {
+        conversation_id: i32,
+        sender_id: i32,
+        /// `None` in groups
+        receiver_id: Option<i32>,
+        message_ids: Vec<i32>,
+    },
+    /// `user_id` joined or left a group.
+    Membership {
+        conversation_id: i32,
+        user_id: i32,
+        joined: bool,
+    },
+    /// A message `sender_id` sent was shown to one of its recipients for the first time.
+    Delivered {
+        message_id: i32,
+        conversation_id: i32,
+        sender_id: i32,
+        /// `None` in groups
+        receiver_id: Option<i32>,
+    },
+    /// `reader_id` has read every message `sender_id` sent them up to `last_read_message_id`.
+    Seen {
+        reader_id: i32,
+        sender_id: i32,
+        last_read_message_id: i32,
+        read_at: String,
+        /// The messages that were not seen before
+        message_ids: Vec<i32>,
+    },
+    /// `user_id` came online or went offline.
+    /// Only sent to `partner_ids`, everyone they share a conversation with.
+    Presence {
+        user_id: i32,
+        online: bool,
+        last_seen: String,
+        partner_ids: Vec<i32>,
+    },
+    /// `user_id` is typing a message to `receiver_id`.
+    /// Never saved, subscribers decide when it expires.
+    Typing {
+        user_id: i32,
+        receiver_id: i32,
+    },
+    /// Some events may never have arrived, so whatever they would have changed has to be loaded again.
+    /// Only published on this instance, to every topic, once the pub/sub backend reconnects.
+    Resync,
+}
+
+/// The id of the last message a reconnecting `EventSource` received,
+/// from its `Last-Event-ID` header.
+pub struct LastEventId(pub Option<i32>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<LastEventId, Self::Error> {
+        let id = request.headers()
+            .get_one("Last-Event-ID")
+            .and_then(|id| id.trim().parse::<i32>().ok());
+
+        request::Outcome::Success(LastEventId(id))
+    }
+}
+
+impl ChatEvent {
+    pub fn seen(reader_id: i32, sender_id: i32, receipt: ReadReceipt) -> Self {
+        ChatEvent::Seen {
+            reader_id,
+            sender_id,
+            last_read_message_id: receipt.last_read_message_id,
+            read_at: format_created_at(&receipt.read_at),
+            message_ids: receipt.message_ids,
+        }
+    }
+
+    pub fn delivered(msg: &ChatRoomMessage) -> Self {
+        ChatEvent::Delivered {
+            message_id: msg.id.unwrap_or_default(),
+            conversation_id: msg.conversation_id,
+            sender_id: msg.sender_id,
+            receiver_id: msg.receiver_id,
+        }
+    }
+
+    /// Every topic this event has to be published to:
+    /// the conversation itself and, in 1:1 conversations, both of its participants.
+    pub fn topics(&self) -> Vec<Topic> {
+        match self {
+            ChatEvent::Message(msg) | ChatEvent::Edited(msg) | ChatEvent::Deleted(msg) => msg.topics(),
+            ChatEvent::Seen { reader_id, sender_id, .. } => vec![
+                Topic::conversation(*reader_id, *sender_id),
+                Topic::User(*reader_id),
+                Topic::User(*sender_id),
+            ],
+            ChatEvent::Typing { user_id, receiver_id } => vec![
+                Topic::conversation(*user_id, *receiver_id),
+                Topic::User(*user_id),
+                Topic::User(*receiver_id),
+            ],
+            ChatEvent::Reacted { conversation_id, sender_id, receiver_id, .. }
+            | ChatEvent::Delivered { conversation_id, sender_id, receiver_id, .. }
+            | ChatEvent::Expired { conversation_id, sender_id, receiver_id, .. } => {
+                Topic::for_message(*conversation_id, *sender_id, *receiver_id)
+            },
+            // The other participant has nothing to update.
+            ChatEvent::Hidden { user_id, conversation_id, other_user_id, .. } => match other_user_id {
+                Some(other_user_id) => vec![Topic::conversation(*user_id, *other_user_id), Topic::User(*user_id)],
+                None => vec![Topic::Group(*conversation_id), Topic::User(*user_id)],
+            },
+            ChatEvent::Presence { partner_ids, .. } => partner_ids.iter().map(|partner_id| Topic::User(*partner_id)).collect(),
+            ChatEvent::Membership { conversation_id, user_id, .. } => vec![
+                Topic::Group(*conversation_id),
+                Topic::User(*user_id),
+            ],
+            ChatEvent::Resync => Vec::new(),
+        }
+    }
+}
@@ -1 +1,10 @@
+pub mod attachment;
 pub mod chat_struct;
+pub mod conversation;
+pub mod topics;
+pub mod pubsub;
+pub mod presence;
+pub mod search;
+pub mod event;
+pub mod scheduled;
+pub mod disappearing;
@@ -0,0 +1,369 @@
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Mutex}, time::Instant};
+
+use rocket::{fairing::AdHoc, tokio::{self, time::{interval, sleep, Duration, MissedTickBehavior}}};
+use rocket_db_pools::Database;
+use sqlx::{PgConnection, PgPool};
+use time::OffsetDateTime;
+
+use crate::{consts, db::Db, user::actions::UserActions};
+
+use super::{chat_struct::format_created_at, conversation::Conversation, event::ChatEvent, pubsub::PubSub};
+
+#[derive(Default)]
+struct UserPresence {
+    /// Streams the user has open
+    connections: usize,
+    /// Online on this instance
+    online: bool,
+    /// Goes up every time the user opens a stream, so a user who came back
+    /// is not taken offline by an earlier disconnect.
+    generation: u64,
+    /// Everyone the user shares a conversation with, once it was needed
+    partners: Option<Partners>,
+}
+
+struct Partners {
+    ids: HashSet<i32>,
+    loaded_at: Instant,
+}
+
+/// Who is online, going by the SSE and WebSocket streams they have open.
+///
+/// A user goes offline once their last stream has been closed for
+/// [`consts::PRESENCE_GRACE_PERIOD_SECONDS`], so reloading a page does not
+/// make them flicker.
+///
+/// Streams are counted by the instance they are open on, which saves the users that are online on it
+/// in `user_presence` so every instance knows. A user is online as long as they are on any instance.
+#[derive(Clone)]
+pub struct Presence {
+    users: Arc<Mutex<HashMap<i32, UserPresence>>>,
+    /// Tells the rows of this instance in `user_presence` apart
+    instance_id: Arc<str>,
+    pool: PgPool,
+    pubsub: PubSub,
+}
+
+/// Counts as one of the user's streams until it is dropped.
+pub struct Connected {
+    presence: Presence,
+    user_id: i32,
+}
+
+impl Drop for Connected {
+    fn drop(&mut self) {
+        self.presence.disconnect(self.user_id);
+    }
+}
+
+impl Presence {
+    pub fn new(pool: PgPool, pubsub: PubSub) -> Self {
+        Self {
+            users: Arc::new(Mutex::new(HashMap::new())),
+            instance_id: Arc::from(random_string::generate(16, random_string::charsets::ALPHANUMERIC)),
+            pool,
+            pubsub,
+        }
+    }
+
+    /// Whether the user has a stream open on any instance.
+    pub async fn is_online(&self, db: &mut PgConnection, user_id: i32) -> bool {
+        if self.users.lock().unwrap().get(&user_id).is_some_and(|user| user.online) {
+            return true;
+        }
+
+        Self::is_online_anywhere(db, &user_id).await.unwrap_or_else(|err| {
+            println!("Error: {:?}", err);
+            false
+        })
+    }
+
+    /// Whether the two users share a conversation.
+    /// Only goes to the database for users without a stream on this instance,
+    /// or if `other_user_id` was not a partner for [`consts::PARTNERS_REFRESH_SECONDS`].
+    pub async fn are_partners(&self, user_id: i32, other_user_id: i32) -> bool {
+        if let Some(partners) = self.users.lock().unwrap().get(&user_id).and_then(|user| user.partners.as_ref()) {
+            if partners.ids.contains(&other_user_id) {
+                return true;
+            }
+
+            if partners.loaded_at.elapsed() < Duration::from_secs(consts::PARTNERS_REFRESH_SECONDS) {
+                return false;
+            }
+        }
+
+        let partner_ids = match self.pool.acquire().await {
+            Ok(mut conn) => Conversation::get_partner_ids(&mut conn, &user_id).await,
+            Err(err) => Err(err),
+        };
+
+        let partner_ids: HashSet<i32> = match partner_ids {
+            Ok(partner_ids) => partner_ids.into_iter().collect(),
+            Err(err) => {
+                println!("Error: {:?}", err);
+                return false;
+            }
+        };
+
+        let are_partners = partner_ids.contains(&other_user_id);
+
+        if let Some(user) = self.users.lock().unwrap().get_mut(&user_id) {
+            user.partners = Some(Partners { ids: partner_ids, loaded_at: Instant::now() });
+        }
+
+        are_partners
+    }
+
+    /// Keep the returned [`Connected`] for as long as the stream is open.
+    pub async fn connect(&self, user_id: i32) -> Connected {
+        let came_online = {
+            let mut users = self.users.lock().unwrap();
+            let user = users.entry(user_id).or_default();
+
+            user.connections += 1;
+            user.generation += 1;
+
+            !std::mem::replace(&mut user.online, true)
+        };
+
+        if came_online {
+            self.go_online(user_id).await;
+        }
+
+        Connected {
+            presence: self.clone(),
+            user_id,
+        }
+    }
+
+    fn disconnect(&self, user_id: i32) {
+        let generation = match self.users.lock().unwrap().get_mut(&user_id) {
+            Some(user) => {
+                user.connections = user.connections.saturating_sub(1);
+
+                if user.connections > 0 {
+                    return;
+                }
+
+                user.generation
+            },
+            None => return,
+        };
+
+        let presence = self.clone();
+
+        tokio::spawn(async move {
+            sleep(Duration::from_secs(consts::PRESENCE_GRACE_PERIOD_SECONDS)).await;
+            presence.go_offline(user_id, generation).await;
+        });
+    }
+
+    /// Lets everyone know unless the user already was online on another instance.
+    async fn go_online(&self, user_id: i32) {
+        let went_online: Result<bool, sqlx::Error> = async {
+            let mut conn = self.pool.acquire().await?;
+            let was_online = Self::is_online_anywhere(&mut conn, &user_id).await?;
+
+            Self::keep_online(&mut conn, &self.instance_id, &[user_id]).await?;
+
+            Ok(!was_online)
+        }.await;
+
+        match went_online {
+            Ok(true) => self.publish(user_id, true).await,
+            Ok(false) => (),
+            Err(err) => println!("Error: {:?}", err),
+        }
+    }
+
+    /// Unless the user opened another stream since `generation`.
+    /// Only lets everyone know if the user is not online on another instance either.
+    async fn go_offline(&self, user_id: i32, generation: u64) {
+        let went_offline_here = {
+            let mut users = self.users.lock().unwrap();
+
+            match users.get(&user_id) {
+                Some(user) if user.generation == generation => users.remove(&user_id).is_some_and(|user| user.online),
+                _ => false,
+            }
+        };
+
+        if !went_offline_here {
+            return;
+        }
+
+        let went_offline: Result<bool, sqlx::Error> = async {
+            let mut conn = self.pool.acquire().await?;
+
+            sqlx::query!(
+                "DELETE FROM user_presence WHERE instance_id = $1 AND user_id = $2",
+                &*self.instance_id,
+                user_id
+            ).execute(&mut *conn).await?;
+
+            Ok(!Self::is_online_anywhere(&mut conn, &user_id).await?)
+        }.await;
+
+        match went_offline {
+            Ok(true) => self.publish(user_id, false).await,
+            Ok(false) => (),
+            Err(err) => println!("Error: {:?}", err),
+        }
+    }
+
+    /// Keeps the users that are online on this instance online for every other instance,
+    /// and takes offline the ones whose instance stopped doing the same.
+    async fn refresh(&self) -> Result<(), sqlx::Error> {
+        let online_here: Vec<i32> = self.users.lock().unwrap()
+            .iter()
+            .filter(|(_, user)| user.online)
+            .map(|(user_id, _)| *user_id)
+            .collect();
+
+        let mut conn = self.pool.acquire().await?;
+
+        Self::keep_online(&mut conn, &self.instance_id, &online_here).await?;
+
+        let expired = sqlx::query!(
+            "DELETE FROM user_presence WHERE online_until < CURRENT_TIMESTAMP RETURNING user_id"
+        ).fetch_all(&mut *conn).await?;
+
+        for user_id in expired.into_iter().map(|record| record.user_id) {
+            if !Self::is_online_anywhere(&mut conn, &user_id).await? {
+                self.publish(user_id, false).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn keep_online(db: &mut PgConnection, instance_id: &str, user_ids: &[i32]) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO user_presence (instance_id, user_id, online_until)
+            SELECT $1, user_id, CURRENT_TIMESTAMP + make_interval(secs => $3)
+            FROM UNNEST($2::int[]) AS user_id
+            ON CONFLICT (instance_id, user_id) DO UPDATE
+            SET online_until = EXCLUDED.online_until
+            "#,
+            instance_id,
+            user_ids,
+            consts::PRESENCE_EXPIRY_SECONDS as f64
+        ).execute(&mut *db).await?;
+
+        Ok(())
+    }
+
+    async fn is_online_anywhere(db: &mut PgConnection, user_id: &i32) -> Result<bool, sqlx::Error> {
+        let record = sqlx::query!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM user_presence
+                WHERE user_id = $1 AND online_until >= CURRENT_TIMESTAMP
+            ) AS "is_online!"
+            "#,
+            user_id
+        ).fetch_one(&mut *db).await?;
+
+        Ok(record.is_online)
+    }
+
+    /// Saves when the user was last seen and lets everyone they talk with know.
+    async fn publish(&self, user_id: i32, online: bool) {
+        let mut conn = match self.pool.acquire().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                return;
+            }
+        };
+
+        let last_seen = match UserActions::update_last_seen(&mut conn, &user_id).await {
+            Ok(last_seen) => last_seen,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                return;
+            }
+        };
+
+        let partner_ids = match Conversation::get_partner_ids(&mut conn, &user_id).await {
+            Ok(partner_ids) => partner_ids,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                return;
+            }
+        };
+
+        self.pubsub.publish(ChatEvent::Presence {
+            user_id,
+            online,
+            last_seen: format_created_at(&last_seen),
+            partner_ids,
+        }).await;
+    }
+
+    /// "online", "last seen 5 minutes ago", or nothing if the user was never seen.
+    pub async fn describe(&self, db: &mut PgConnection, user_id: i32) -> String {
+        if self.is_online(db, user_id).await {
+            return "online".to_string();
+        }
+
+        match UserActions::get_last_seen(db, &user_id).await {
+            Ok(Some(last_seen)) => format!("last seen {}", format_ago(&last_seen)),
+            Ok(None) => String::new(),
+            Err(err) => {
+                println!("Error: {:?}", err);
+                String::new()
+            }
+        }
+    }
+}
+
+/// How long ago something happened, in its largest whole unit.
+pub fn format_ago(at: &OffsetDateTime) -> String {
+    let seconds = (OffsetDateTime::now_utc() - *at).whole_seconds().max(0);
+    let (amount, unit) = match seconds {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+
+    format!("{} {}{} ago", amount, unit, if amount == 1 { "" } else { "s" })
+}
+
+/// Refreshes who is online every [`consts::PRESENCE_REFRESH_SECONDS`].
+async fn refresh_presence(presence: Presence) {
+    let mut ticks = interval(Duration::from_secs(consts::PRESENCE_REFRESH_SECONDS));
+
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticks.tick().await;
+
+        if let Err(err) = presence.refresh().await {
+            println!("Error: {:?}", err);
+        }
+    }
+}
+
+/// Manages a [`Presence`]. Must be attached after [`super::pubsub::stage`].
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Presence", |rocket| async {
+        rocket
+            .attach(AdHoc::try_on_ignite("Presence tracker", |rocket| async {
+                let pool = Db::fetch(&rocket).map(|db| (**db).clone());
+                let pubsub = rocket.state::<PubSub>().cloned();
+
+                match (pool, pubsub) {
+                    (Some(pool), Some(pubsub)) => Ok(rocket.manage(Presence::new(pool, pubsub))),
+                    _ => Err(rocket)
+                }
+            }))
+            .attach(AdHoc::on_liftoff("Presence refresher", |rocket| Box::pin(async move {
+                if let Some(presence) = rocket.state::<Presence>().cloned() {
+                    tokio::spawn(refresh_presence(presence));
+                }
+            })))
+    })
+}
@@ -0,0 +1,389 @@
+use std::sync::Arc;
+
+use rocket::{fairing::AdHoc, tokio::{self, time::{sleep, Duration}}};
+use rocket_db_pools::Database;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgListener, PgConnection, PgPool};
+
+use crate::{db::Db, utils::env};
+
+use super::{chat_struct::{Chat, ChatRoomMessage, MessageStatus, Reaction}, conversation::Conversation, event::ChatEvent, topics::{Subscription, Topic, Topics}};
+
+/// Postgres channel the `user_chats_insert_notify` trigger notifies with the id of every new message.
+const USER_CHATS_INSERT_CHANNEL: &str = "user_chats_insert";
+/// Postgres channel every other [`ChatEvent`] is sent through as a JSON [`Notification`].
+const CHAT_EVENTS_CHANNEL: &str = "chat_events";
+
+/// How long [`listen`] waits before connecting again after it lost its connection.
+const RECONNECT_DELAY_SECONDS: u64 = 1;
+
+/// What is sent through [`CHAT_EVENTS_CHANNEL`] for an event.
+/// NOTIFY payloads must be shorter than 8000 bytes, so events that carry a message
+/// or a list that can grow only send ids, like the insert trigger does, and [`listen`] loads the rest again.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Notification {
+    /// Sent by the insert trigger as the bare id
+    Message(i32),
+    Edited(i32),
+    Deleted(i32),
+    Reacted {
+        message_id: i32,
+        conversation_id: i32,
+        sender_id: i32,
+        receiver_id: Option<i32>,
+    },
+    /// `first_message_id` is the first of the messages that were seen, `None` if there were none.
+    Seen {
+        reader_id: i32,
+        sender_id: i32,
+        last_read_message_id: i32,
+        read_at: String,
+        first_message_id: Option<i32>,
+    },
+    Presence {
+        user_id: i32,
+        online: bool,
+        last_seen: String,
+    },
+    /// Events that are small enough to be sent as they are
+    Event(Box<ChatEvent>),
+}
+
+impl Notification {
+    fn new(event: ChatEvent) -> Self {
+        match event {
+            ChatEvent::Edited(msg) => Notification::Edited(msg.id.unwrap_or_default()),
+            ChatEvent::Deleted(msg) => Notification::Deleted(msg.id.unwrap_or_default()),
+            ChatEvent::Reacted { message_id, conversation_id, sender_id, receiver_id, .. } => Notification::Reacted {
+                message_id,
+                conversation_id,
+                sender_id,
+                receiver_id,
+            },
+            // Every message up to `last_read_message_id` is seen, so the ones that were not are all of them after the first.
+            ChatEvent::Seen { reader_id, sender_id, last_read_message_id, read_at, message_ids } => Notification::Seen {
+                reader_id,
+                sender_id,
+                last_read_message_id,
+                read_at,
+                first_message_id: message_ids.first().copied(),
+            },
+            ChatEvent::Presence { user_id, online, last_seen, .. } => Notification::Presence { user_id, online, last_seen },
+            event => Notification::Event(Box::new(event)),
+        }
+    }
+
+    /// The event as it was published, with what was left out loaded from the database.
+    async fn into_event(self, db: &mut PgConnection) -> Result<ChatEvent, sqlx::Error> {
+        match self {
+            Notification::Message(id) => Ok(ChatEvent::Message(ChatRoomMessage::get_by_id(db, &id).await?)),
+            Notification::Edited(id) => Ok(ChatEvent::Edited(ChatRoomMessage::get_by_id(db, &id).await?)),
+            Notification::Deleted(id) => Ok(ChatEvent::Deleted(ChatRoomMessage::get_by_id(db, &id).await?)),
+            Notification::Reacted { message_id, conversation_id, sender_id, receiver_id } => Ok(ChatEvent::Reacted {
+                message_id,
+                conversation_id,
+                sender_id,
+                receiver_id,
+                reactions: Reaction::get_by_message_ids(db, &[message_id]).await?.remove(&message_id).unwrap_or_default(),
+            }),
+            Notification::Seen { reader_id, sender_id, last_read_message_id, read_at, first_message_id } => Ok(ChatEvent::Seen {
+                reader_id,
+                sender_id,
+                last_read_message_id,
+                read_at,
+                message_ids: match first_message_id {
+                    Some(first_message_id) => Chat::get_read_ids(db, &reader_id, &sender_id, &first_message_id, &last_read_message_id).await?,
+                    None => Vec::new(),
+                },
+            }),
+            Notification::Presence { user_id, online, last_seen } => Ok(ChatEvent::Presence {
+                user_id,
+                online,
+                last_seen,
+                partner_ids: Conversation::get_partner_ids(db, &user_id).await?,
+            }),
+            Notification::Event(event) => Ok(*event),
+        }
+    }
+}
+
+/// How an event reaches the subscribers of every server instance.
+#[rocket::async_trait]
+pub trait Backend: Send + Sync {
+    async fn publish(&self, event: ChatEvent);
+}
+
+/// Delivers events straight to the subscribers of this instance.
+/// Only suitable when a single instance of the server is running.
+pub struct InMemory {
+    topics: Topics<ChatEvent>,
+}
+
+#[rocket::async_trait]
+impl Backend for InMemory {
+    async fn publish(&self, event: ChatEvent) {
+        self.topics.publish_to_all(&event.topics(), event);
+    }
+}
+
+/// Sends events through NOTIFY. Every instance runs a [`listen`] task
+/// that delivers them to its own subscribers.
+///
+/// New messages are already announced by the `user_chats_insert_notify`
+/// trigger once they are saved, so they are not sent again.
+/// Other events that carry a message are sent as its id, see [`Notification`].
+pub struct Postgres {
+    pool: PgPool,
+}
+
+#[rocket::async_trait]
+impl Backend for Postgres {
+    async fn publish(&self, event: ChatEvent) {
+        if let ChatEvent::Message(_) = event {
+            return;
+        }
+
+        let payload = match serde_json::to_string(&Notification::new(event)) {
+            Ok(payload) => payload,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                return;
+            }
+        };
+
+        let res = sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(CHAT_EVENTS_CHANNEL)
+            .bind(payload)
+            .execute(&self.pool).await;
+
+        if let Err(err) = res {
+            println!("Error: {:?}", err);
+        }
+    }
+}
+
+/// The chat queue handlers publish to and subscribe from.
+#[derive(Clone)]
+pub struct PubSub {
+    topics: Topics<ChatEvent>,
+    backend: Arc<dyn Backend>,
+}
+
+impl PubSub {
+    pub fn in_memory(capacity: usize) -> Self {
+        let topics = Topics::new(capacity);
+
+        Self {
+            topics: topics.clone(),
+            backend: Arc::new(InMemory { topics }),
+        }
+    }
+
+    pub fn postgres(capacity: usize, pool: PgPool) -> Self {
+        Self {
+            topics: Topics::new(capacity),
+            backend: Arc::new(Postgres { pool }),
+        }
+    }
+
+    pub fn subscribe(&self, topic: Topic) -> Subscription<ChatEvent> {
+        self.topics.subscribe(topic)
+    }
+
+    pub async fn publish(&self, event: ChatEvent) {
+        self.backend.publish(event).await;
+    }
+
+    /// Marks a message as delivered once a stream of one of its recipients has sent it,
+    /// and lets its sender know the first time it happens.
+    pub async fn mark_as_delivered(&self, pool: &PgPool, msg: &ChatRoomMessage) {
+        if msg.status != MessageStatus::Sent {
+            return;
+        }
+
+        let delivered = match pool.acquire().await {
+            Ok(mut conn) => Chat::mark_as_delivered(&mut conn, &msg.id.unwrap_or_default()).await,
+            Err(err) => Err(err),
+        };
+
+        match delivered {
+            Ok(true) => self.publish(ChatEvent::delivered(msg)).await,
+            Ok(false) => (),
+            Err(err) => println!("Error: {:?}", err),
+        }
+    }
+
+    /// Publishes only to the subscribers of this instance, skipping the backend.
+    /// For short-lived events that are not worth a round trip to the database.
+    pub fn publish_local(&self, event: ChatEvent) {
+        self.topics.publish_to_all(&event.topics(), event);
+    }
+}
+
+/// Forwards every event announced through NOTIFY to the subscribers of this instance.
+///
+/// Whatever is announced while the connection is lost never arrives,
+/// so once it is back every subscriber gets a [`ChatEvent::Resync`].
+async fn listen(pool: PgPool, topics: Topics<ChatEvent>) {
+    let listener = PgListener::connect_with(&pool).await;
+
+    let mut listener = match listener {
+        Ok(listener) => listener,
+        Err(err) => {
+            println!("Error: {:?}", err);
+            return;
+        }
+    };
+
+    if let Err(err) = listener.listen_all([USER_CHATS_INSERT_CHANNEL, CHAT_EVENTS_CHANNEL]).await {
+        println!("Error: {:?}", err);
+        return;
+    }
+
+    let mut connected = true;
+
+    loop {
+        if !connected {
+            // Listening again connects again.
+            match listener.listen_all([USER_CHATS_INSERT_CHANNEL, CHAT_EVENTS_CHANNEL]).await {
+                Ok(()) => {
+                    connected = true;
+                    topics.publish_everywhere(ChatEvent::Resync);
+                },
+                Err(sqlx::Error::PoolClosed) => break,
+                Err(err) => {
+                    println!("Error: {:?}", err);
+                    sleep(Duration::from_secs(RECONNECT_DELAY_SECONDS)).await;
+                    continue;
+                }
+            }
+        }
+
+        let notification = match listener.try_recv().await {
+            Ok(Some(notification)) => notification,
+            // The connection was lost.
+            Ok(None) => {
+                connected = false;
+                continue;
+            },
+            // The server is shutting down.
+            Err(sqlx::Error::PoolClosed) => break,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                connected = false;
+                sleep(Duration::from_secs(RECONNECT_DELAY_SECONDS)).await;
+                continue;
+            }
+        };
+
+        let notification = if notification.channel() == CHAT_EVENTS_CHANNEL {
+            match serde_json::from_str::<Notification>(notification.payload()) {
+                Ok(notification) => notification,
+                Err(err) => {
+                    println!("Error: {:?}", err);
+                    continue;
+                }
+            }
+        } else {
+            match notification.payload().parse::<i32>() {
+                Ok(id) => Notification::Message(id),
+                Err(_) => continue,
+            }
+        };
+
+        // Nothing was left out of it.
+        if let Notification::Event(event) = notification {
+            topics.publish_to_all(&event.topics(), *event);
+            continue;
+        }
+
+        let mut conn = match pool.acquire().await {
+            Ok(conn) => conn,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                continue;
+            }
+        };
+
+        match notification.into_event(&mut conn).await {
+            Ok(event) => topics.publish_to_all(&event.topics(), event),
+            Err(err) => println!("Error: {:?}", err),
+        }
+    }
+}
+
+/// Manages a [`PubSub`] using the backend set in `PUBSUB_BACKEND`.
+/// Must be attached after [`crate::db::stage`].
+pub fn stage() -> AdHoc {
+    AdHoc::on_ignite("Chat pub/sub", |rocket| async {
+        match env::load_pubsub_backend().as_str() {
+            "memory" => rocket.manage(PubSub::in_memory(1024)),
+            _ => rocket
+                .attach(AdHoc::try_on_ignite("Chat pub/sub backend", |rocket| async {
+                    match Db::fetch(&rocket) {
+                        Some(db) => {
+                            let pubsub = PubSub::postgres(1024, (**db).clone());
+
+                            Ok(rocket.manage(pubsub))
+                        }
+                        None => Err(rocket)
+                    }
+                }))
+                .attach(AdHoc::on_liftoff("Chat pub/sub listener", |rocket| Box::pin(async move {
+                    let pool = Db::fetch(rocket).map(|db| (**db).clone());
+                    let topics = rocket.state::<PubSub>().map(|pubsub| pubsub.topics.clone());
+
+                    if let (Some(pool), Some(topics)) = (pool, topics) {
+                        tokio::spawn(listen(pool, topics));
+                    }
+                })))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Postgres refuses NOTIFY payloads of 8000 bytes or more.
+    const MAX_PAYLOAD_BYTES: usize = 7999;
+
+    fn payload(event: ChatEvent) -> String {
+        serde_json::to_string(&Notification::new(event)).unwrap()
+    }
+
+    #[test]
+    fn seen_with_thousands_of_ids_fits_in_a_notification() {
+        let payload = payload(ChatEvent::Seen {
+            reader_id: 2,
+            sender_id: 1,
+            last_read_message_id: 105_000,
+            read_at: "Feb 12, 2024 at 12:00 AM".to_string(),
+            message_ids: (100_001..=105_000).collect(),
+        });
+
+        assert!(payload.len() <= MAX_PAYLOAD_BYTES);
+
+        match serde_json::from_str::<Notification>(&payload).unwrap() {
+            Notification::Seen { first_message_id, last_read_message_id, .. } => {
+                assert_eq!(first_message_id, Some(100_001));
+                assert_eq!(last_read_message_id, 105_000);
+            },
+            notification => panic!("Expected a seen notification, got {:?}", notification),
+        }
+    }
+
+    #[test]
+    fn presence_leaves_out_partners() {
+        let payload = payload(ChatEvent::Presence {
+            user_id: 1,
+            online: true,
+            last_seen: "Feb 12, 2024 at 12:00 AM".to_string(),
+            partner_ids: (2..5_000).collect(),
+        });
+
+        assert!(payload.len() <= MAX_PAYLOAD_BYTES);
+        assert!(matches!(serde_json::from_str::<Notification>(&payload).unwrap(), Notification::Presence { user_id: 1, online: true, .. }));
+    }
+}
@@ -0,0 +1,255 @@
+use rocket::{fairing::AdHoc, tokio::{self, time::{interval, Duration, MissedTickBehavior}}};
+use rocket_db_pools::Database;
+use sqlx::{Acquire, PgConnection, PgPool};
+use time::OffsetDateTime;
+
+use crate::{consts, db::Db, user::user_struct::User};
+
+use super::{chat_struct::{Chat, ChatRoomMessage, NewMessage}, event::ChatEvent, pubsub::PubSub};
+
+/// Scheduled messages are sent with this followed by their id as their `client_id`,
+/// so the sender's conversation knows which one was sent.
+const CLIENT_ID_PREFIX: &str = "scheduled_";
+
+/// A message a user wrote to be sent to `receiver_id` at `send_at`.
+#[derive(Debug, Clone)]
+pub struct ScheduledMessage {
+    pub id: i32,
+    pub owner_id: i32,
+    pub receiver_id: i32,
+    pub message: String,
+    /// Shown as it was typed instead of as markdown
+    pub is_plain: bool,
+    pub send_at: OffsetDateTime,
+    /// When it was given up on after failing to be sent [`consts::SCHEDULED_MESSAGE_MAX_ATTEMPTS`] times.
+    /// It stays scheduled for its owner to edit, which tries again, or cancel.
+    pub failed_at: Option<OffsetDateTime>,
+}
+
+impl ScheduledMessage {
+    pub async fn insert(
+        db: &mut PgConnection,
+        owner_id: &i32,
+        receiver_id: &i32,
+        message: &str,
+        is_plain: bool,
+        send_at: &OffsetDateTime,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            INSERT INTO scheduled_messages (owner_id, receiver_id, message, is_plain, send_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, owner_id, receiver_id, message, is_plain, send_at, failed_at
+            "#,
+            owner_id,
+            receiver_id,
+            message,
+            is_plain,
+            send_at
+        ).fetch_one(&mut *db).await
+    }
+
+    /// The messages `owner_id` has yet to send to `receiver_id`, the next one first.
+    pub async fn get_pending(
+        db: &mut PgConnection,
+        owner_id: &i32,
+        receiver_id: &i32,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            SELECT id, owner_id, receiver_id, message, is_plain, send_at, failed_at
+            FROM scheduled_messages
+            WHERE owner_id = $1 AND receiver_id = $2
+            ORDER BY send_at, id
+            "#,
+            owner_id,
+            receiver_id
+        ).fetch_all(&mut *db).await
+    }
+
+    /// How many messages `owner_id` has yet to send, to everyone.
+    pub async fn count_pending(db: &mut PgConnection, owner_id: &i32) -> Result<i64, sqlx::Error> {
+        let record = sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!" FROM scheduled_messages WHERE owner_id = $1"#,
+            owner_id
+        ).fetch_one(&mut *db).await?;
+
+        Ok(record.count)
+    }
+
+    /// A message `owner_id` has yet to send. `None` once it was sent or cancelled.
+    pub async fn get_by_id(
+        db: &mut PgConnection,
+        owner_id: &i32,
+        id: &i32,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            SELECT id, owner_id, receiver_id, message, is_plain, send_at, failed_at
+            FROM scheduled_messages
+            WHERE id = $1 AND owner_id = $2
+            "#,
+            id,
+            owner_id
+        ).fetch_optional(&mut *db).await
+    }
+
+    /// Changes what a pending message says and when it is sent, and tries to send it again if it failed.
+    /// `None` once it was sent or cancelled, as it cannot be changed anymore.
+    pub async fn edit(
+        db: &mut PgConnection,
+        owner_id: &i32,
+        id: &i32,
+        message: &str,
+        send_at: &OffsetDateTime,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"
+            UPDATE scheduled_messages
+            SET message = $3, send_at = $4, attempts = 0, failed_at = NULL
+            WHERE id = $1 AND owner_id = $2
+            RETURNING id, owner_id, receiver_id, message, is_plain, send_at, failed_at
+            "#,
+            id,
+            owner_id,
+            message,
+            send_at
+        ).fetch_optional(&mut *db).await
+    }
+
+    /// Keeps a pending message from being sent. Returns whether there was one to cancel.
+    pub async fn cancel(db: &mut PgConnection, owner_id: &i32, id: &i32) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "DELETE FROM scheduled_messages WHERE id = $1 AND owner_id = $2",
+            id,
+            owner_id
+        ).execute(&mut *db).await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The `client_id` the message is sent with
+    pub fn client_id(&self) -> String {
+        format!("{}{}", CLIENT_ID_PREFIX, self.id)
+    }
+
+    /// The id of the scheduled message a message was sent from, if it was.
+    pub fn id_from_client_id(client_id: &str) -> Option<i32> {
+        client_id.strip_prefix(CLIENT_ID_PREFIX)?.parse().ok()
+    }
+
+    /// Sends the messages that are due as if their owners sent them just now, and lets everyone know.
+    /// Rows being sent are locked, so other instances skip them instead of sending them twice.
+    /// Messages that fail are put off and tried again later, so they do not hold up the others.
+    pub async fn send_due(db: &mut PgConnection, pubsub: &PubSub) -> Result<(), sqlx::Error> {
+        let mut transaction = db.begin().await?;
+        let due = sqlx::query_as!(
+            Self,
+            r#"
+            SELECT id, owner_id, receiver_id, message, is_plain, send_at, failed_at
+            FROM scheduled_messages
+            WHERE send_at <= CURRENT_TIMESTAMP AND failed_at IS NULL
+            ORDER BY send_at, id
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            "#,
+            consts::SCHEDULED_MESSAGES_PER_BATCH
+        ).fetch_all(&mut *transaction).await?;
+
+        let mut sent = Vec::new();
+
+        for scheduled in due {
+            match scheduled.send(&mut transaction).await {
+                Ok(Some(msg)) => sent.push(msg),
+                Ok(None) => {},
+                Err(err) => {
+                    println!("Error: {:?}", err);
+                    scheduled.record_failure(&mut transaction).await?;
+                },
+            }
+        }
+
+        transaction.commit().await?;
+
+        for msg in sent {
+            pubsub.publish(ChatEvent::Message(msg)).await;
+        }
+
+        Ok(())
+    }
+
+    /// Saves the message and takes it off the schedule, or neither if it fails.
+    /// `None` if it was already saved.
+    async fn send(&self, db: &mut PgConnection) -> Result<Option<ChatRoomMessage>, sqlx::Error> {
+        let mut transaction = db.begin().await?;
+        let receiver_name = User::get_display_name(&mut transaction, &self.receiver_id).await?.ok_or(sqlx::Error::RowNotFound)?;
+        let new_message = NewMessage::new(self.message.clone(), None, Some(self.client_id()), self.is_plain);
+
+        let msg = match Chat::save_chat(&mut transaction, &self.owner_id, &self.receiver_id, &receiver_name, &new_message, &[]).await {
+            Ok(msg) => Some(msg),
+            Err(err) if ChatRoomMessage::is_duplicate(&err) => None,
+            Err(err) => return Err(err),
+        };
+
+        sqlx::query!("DELETE FROM scheduled_messages WHERE id = $1", self.id)
+            .execute(&mut *transaction).await?;
+        transaction.commit().await?;
+
+        Ok(msg)
+    }
+
+    /// Puts the message off for a little longer every time it fails, so it does not keep others from being sent,
+    /// and gives up on it once it failed [`consts::SCHEDULED_MESSAGE_MAX_ATTEMPTS`] times.
+    async fn record_failure(&self, db: &mut PgConnection) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE scheduled_messages
+            SET attempts = attempts + 1,
+            send_at = CURRENT_TIMESTAMP + make_interval(secs => $2 * (attempts + 1)),
+            failed_at = CASE WHEN attempts + 1 >= $3 THEN CURRENT_TIMESTAMP END
+            WHERE id = $1
+            "#,
+            self.id,
+            consts::SCHEDULED_MESSAGE_RETRY_SECONDS as f64,
+            consts::SCHEDULED_MESSAGE_MAX_ATTEMPTS
+        ).execute(&mut *db).await?;
+
+        Ok(())
+    }
+}
+
+/// Checks for messages that are due every [`consts::SCHEDULED_MESSAGES_INTERVAL_SECONDS`].
+async fn send_due_messages(pool: PgPool, pubsub: PubSub) {
+    let mut ticks = interval(Duration::from_secs(consts::SCHEDULED_MESSAGES_INTERVAL_SECONDS));
+
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticks.tick().await;
+
+        let sent = match pool.acquire().await {
+            Ok(mut conn) => ScheduledMessage::send_due(&mut conn, &pubsub).await,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = sent {
+            println!("Error: {:?}", err);
+        }
+    }
+}
+
+/// Sends scheduled messages once they are due. Must be attached after [`super::pubsub::stage`].
+pub fn stage() -> AdHoc {
+    AdHoc::on_liftoff("Scheduled messages", |rocket| Box::pin(async move {
+        let pool = Db::fetch(rocket).map(|db| (**db).clone());
+        let pubsub = rocket.state::<PubSub>().cloned();
+
+        if let (Some(pool), Some(pubsub)) = (pool, pubsub) {
+            tokio::spawn(send_due_messages(pool, pubsub));
+        }
+    }))
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use crate::consts;
+
+use super::chat_struct::format_created_at;
+
+/// Put around the words that matched in a snippet, as the message itself could contain any markup
+pub const MATCH_START: char = '\u{E000}';
+pub const MATCH_END: char = '\u{E001}';
+
+/// A message that matched a search, with the words that matched highlighted.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MessageSearchResult {
+    pub id: i32,
+    #[serde(rename = "conversationId")]
+    pub conversation_id: i32,
+    #[serde(rename = "isGroup")]
+    pub is_group: bool,
+    /// The name of the group, or of whoever the user talks with
+    #[serde(rename = "conversationName")]
+    pub conversation_name: String,
+    /// Whoever the user talks with, `None` in groups
+    #[serde(rename = "otherUserId")]
+    pub other_user_id: Option<i32>,
+    #[serde(rename = "senderName")]
+    pub sender_name: String,
+    /// Fragments of the message with the matching words between [`MATCH_START`] and [`MATCH_END`]
+    pub snippet: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MessageSearchResults {
+    pub results: Vec<MessageSearchResult>,
+    /// The page after this one, if there is one.
+    #[serde(rename = "nextPage")]
+    pub next_page: Option<i64>,
+}
+
+impl MessageSearchResult {
+    /// Searches the messages of every conversation `user_id` is a member of, the best matches first.
+    /// `query` can use quotes, `or` and `-` like a web search. `page` starts at 0.
+    pub async fn search(
+        db: &mut PgConnection,
+        user_id: &i32,
+        query: &String,
+        page: i64,
+        limit: i64,
+    ) -> Result<MessageSearchResults, sqlx::Error> {
+        // One extra row tells us if there is a next page.
+        let mut records = sqlx::query!(
+            r#"
+            SELECT
+            user_chats.id,
+            user_chats.conversation_id,
+            conversations.is_group,
+            COALESCE(conversations.name, other_user.display_name) AS "conversation_name!",
+            other_user.id AS "other_user_id?",
+            sender.display_name AS sender_name,
+            ts_headline(
+                'english',
+                user_chats.message,
+                query,
+                $5
+            ) AS "snippet!",
+            user_chats.created_at
+            FROM user_chats
+            CROSS JOIN websearch_to_tsquery('english', $2) query
+            JOIN conversation_members ON conversation_members.conversation_id = user_chats.conversation_id
+            AND conversation_members.user_id = $1
+            JOIN conversations ON conversations.id = user_chats.conversation_id
+            JOIN users sender ON sender.id = user_chats.owner_id
+            LEFT JOIN users other_user ON NOT conversations.is_group AND other_user.id = (
+                CASE WHEN conversations.first_user_id = $1 THEN conversations.second_user_id ELSE conversations.first_user_id END
+            )
+            WHERE to_tsvector('english', user_chats.message) @@ query
+            AND user_chats.deleted_at IS NULL
+            AND (user_chats.expires_at IS NULL OR user_chats.expires_at > CURRENT_TIMESTAMP)
+            AND NOT EXISTS (
+                SELECT 1 FROM user_chat_hidden_messages hidden
+                WHERE hidden.user_id = $1 AND hidden.message_id = user_chats.id
+            )
+            ORDER BY ts_rank(to_tsvector('english', user_chats.message), query) DESC, user_chats.created_at DESC, user_chats.id DESC
+            LIMIT $3 OFFSET $4
+            "#,
+            user_id,
+            query,
+            limit + 1,
+            page * limit,
+            format!("StartSel={}, StopSel={}, MaxWords=20, MinWords=5, MaxFragments=2", MATCH_START, MATCH_END)
+        ).fetch_all(&mut *db).await?;
+
+        let has_next_page = records.len() as i64 > limit;
+
+        records.truncate(limit as usize);
+
+        Ok(MessageSearchResults {
+            results: records.into_iter().map(|record| Self {
+                id: record.id,
+                conversation_id: record.conversation_id,
+                is_group: record.is_group,
+                conversation_name: record.conversation_name,
+                other_user_id: record.other_user_id,
+                sender_name: record.sender_name,
+                snippet: record.snippet,
+                created_at: format_created_at(&record.created_at),
+            }).collect(),
+            next_page: if has_next_page && page + 1 < consts::MAX_SEARCH_PAGES { Some(page + 1) } else { None },
+        })
+    }
+}
@@ -0,0 +1,195 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use rocket::{futures::future::select_all, tokio::sync::broadcast::{channel, error::RecvError, Receiver, Sender}};
+
+/// What a subscriber is listening to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    /// A 1:1 conversation. Use [`Topic::conversation`] so both
+    /// participants end up on the same topic.
+    Conversation(i32, i32),
+    /// A group conversation, by its id.
+    Group(i32),
+    /// Everything that involves a user, regardless of conversation.
+    User(i32),
+}
+
+impl Topic {
+    pub fn conversation(first_user_id: i32, second_user_id: i32) -> Self {
+        Topic::Conversation(
+            first_user_id.min(second_user_id),
+            first_user_id.max(second_user_id)
+        )
+    }
+
+    /// Where anything that happens to a message is published.
+    /// A 1:1 message goes to its conversation and both participants,
+    /// a group message only goes to its group.
+    pub fn for_message(conversation_id: i32, sender_id: i32, receiver_id: Option<i32>) -> Vec<Self> {
+        match receiver_id {
+            Some(receiver_id) => vec![
+                Topic::conversation(sender_id, receiver_id),
+                Topic::User(sender_id),
+                Topic::User(receiver_id),
+            ],
+            None => vec![Topic::Group(conversation_id)],
+        }
+    }
+}
+
+/// A registry of broadcast channels, one per [`Topic`].
+///
+/// Channels are created when the first subscriber of a topic arrives
+/// and are removed once the last one leaves, so publishing to a topic
+/// nobody listens to costs nothing.
+pub struct Topics<T> {
+    channels: Arc<Mutex<HashMap<Topic, Sender<T>>>>,
+    capacity: usize,
+}
+
+impl<T> Clone for Topics<T> {
+    fn clone(&self) -> Self {
+        Self {
+            channels: self.channels.clone(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl<T: Clone> Topics<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            channels: Arc::new(Mutex::new(HashMap::new())),
+            capacity,
+        }
+    }
+
+    pub fn subscribe(&self, topic: Topic) -> Subscription<T> {
+        let mut channels = self.channels.lock().unwrap();
+        let rx = match channels.get(&topic) {
+            Some(tx) => tx.subscribe(),
+            None => {
+                let (tx, rx) = channel(self.capacity);
+
+                channels.insert(topic, tx);
+                rx
+            }
+        };
+
+        Subscription {
+            topic,
+            rx: Some(rx),
+            channels: self.channels.clone(),
+        }
+    }
+
+    /// Returns how many subscribers received the message.
+    pub fn publish(&self, topic: Topic, msg: T) -> usize {
+        let channels = self.channels.lock().unwrap();
+
+        match channels.get(&topic) {
+            Some(tx) => tx.send(msg).unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// Publishes to every topic anyone is subscribed to.
+    pub fn publish_everywhere(&self, msg: T) {
+        let channels = self.channels.lock().unwrap();
+
+        for tx in channels.values() {
+            let _ = tx.send(msg.clone());
+        }
+    }
+
+    /// Publishes to every topic once, even if a topic is listed twice.
+    pub fn publish_to_all(&self, topics: &[Topic], msg: T) {
+        for (i, topic) in topics.iter().enumerate() {
+            if !topics[..i].contains(topic) {
+                self.publish(*topic, msg.clone());
+            }
+        }
+    }
+}
+
+/// A subscriber of a single [`Topic`].
+/// Dropping the last subscription of a topic removes its channel.
+pub struct Subscription<T> {
+    topic: Topic,
+    rx: Option<Receiver<T>>,
+    channels: Arc<Mutex<HashMap<Topic, Sender<T>>>>,
+}
+
+impl<T: Clone> Subscription<T> {
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        match self.rx.as_mut() {
+            Some(rx) => rx.recv().await,
+            None => Err(RecvError::Closed),
+        }
+    }
+}
+
+/// Subscriptions to several topics, received from as if they were one.
+pub struct Subscriptions<T> {
+    subscriptions: Vec<Subscription<T>>,
+}
+
+impl<T: Clone> Subscriptions<T> {
+    pub fn new(subscriptions: Vec<Subscription<T>>) -> Self {
+        Self { subscriptions }
+    }
+
+    pub fn contains(&self, topic: Topic) -> bool {
+        self.subscriptions.iter().any(|subscription| subscription.topic == topic)
+    }
+
+    /// Does nothing if there already is a subscription to its topic.
+    pub fn add(&mut self, subscription: Subscription<T>) {
+        if !self.contains(subscription.topic) {
+            self.subscriptions.push(subscription);
+        }
+    }
+
+    pub fn remove(&mut self, topic: Topic) {
+        self.subscriptions.retain(|subscription| subscription.topic != topic);
+    }
+
+    /// Keeps only the subscriptions to topics `keep` returns `true` for.
+    pub fn retain(&mut self, mut keep: impl FnMut(Topic) -> bool) {
+        self.subscriptions.retain(|subscription| keep(subscription.topic));
+    }
+
+    /// The next message of whichever topic has one first.
+    pub async fn recv(&mut self) -> Result<T, RecvError> {
+        match self.recv_from().await {
+            Some((_, msg)) => msg,
+            None => Err(RecvError::Closed),
+        }
+    }
+
+    /// The next message of whichever topic has one first, along with that topic.
+    /// `None` if there are no subscriptions.
+    pub async fn recv_from(&mut self) -> Option<(Topic, Result<T, RecvError>)> {
+        if self.subscriptions.is_empty() {
+            return None;
+        }
+
+        let (msg, index, _) = select_all(
+            self.subscriptions.iter_mut().map(|subscription| Box::pin(subscription.recv()))
+        ).await;
+
+        Some((self.subscriptions[index].topic, msg))
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        let mut channels = self.channels.lock().unwrap();
+
+        drop(self.rx.take());
+
+        if channels.get(&self.topic).is_some_and(|tx| tx.receiver_count() == 0) {
+            channels.remove(&self.topic);
+        }
+    }
+}
@@ -2,12 +2,12 @@ use rocket::{response::{content::RawHtml, Redirect}, Responder};
 use serde::{Deserialize, Serialize};
 use rocket_dyn_templates::Template;
 
-use crate::utils::custom_redirect::Redirect as HtmxRedirect;
+use crate::utils::{custom_redirect::Redirect as HtmxRedirect, html::Html};
 
 #[derive(Responder)]
 pub enum TemplateOrHtml {
     Template(Template),
-    Html(RawHtml<String>)
+    Html(RawHtml<Html>)
 }
 
 #[derive(Responder)]
@@ -37,3 +37,115 @@ pub const METADATA: Metadata = Metadata {
     title: "Chat App",
     description: "Chat app while practicing Rocket",
 };
+
+/// How many messages of a conversation are loaded at a time
+pub const MESSAGES_PER_PAGE: i64 = 30;
+
+/// The longest id a client can give a message it sends
+pub const MAX_CLIENT_ID_LENGTH: usize = 64;
+
+/// How many messages that matched a search are shown at a time
+pub const SEARCH_RESULTS_PER_PAGE: i64 = 20;
+
+/// How many pages of messages that matched a search can be gone through
+pub const MAX_SEARCH_PAGES: i64 = 50;
+
+/// How long someone is shown as typing after they last typed
+pub const TYPING_INDICATOR_SECONDS: u64 = 5;
+
+/// How long someone stays online after they closed their last chat
+pub const PRESENCE_GRACE_PERIOD_SECONDS: u64 = 15;
+
+/// How long the partners of a connected user are trusted before they are loaded again
+/// for someone who is not one of them, like a user they just started a conversation with
+pub const PARTNERS_REFRESH_SECONDS: u64 = 30;
+
+/// How often an instance tells the others which users are still connected to it
+pub const PRESENCE_REFRESH_SECONDS: u64 = 30;
+
+/// How long a user stays online on an instance that stopped telling the others, e.g. because it crashed
+pub const PRESENCE_EXPIRY_SECONDS: i32 = 90;
+
+/// Shown in place of a message that was deleted for everyone
+pub const DELETED_MESSAGE_PLACEHOLDER: &str = "Message deleted.";
+
+/// How many characters of a message are quoted in its replies
+pub const QUOTE_SNIPPET_LENGTH: usize = 80;
+
+/// The emojis users can react to messages with
+pub const REACTION_EMOJIS: [&str; 6] = ["👍", "❤️", "😂", "😮", "😢", "🙏"];
+
+/// Where uploaded files are stored
+pub const STORAGE_BUCKET: &str = "chat_server_local_development";
+
+/// Image types users can upload
+pub const ALLOWED_IMAGE_TYPES: [&str; 5] = ["image/jpg", "image/jpeg", "image/png", "image/webp", "image/avif"];
+
+/// The largest file that can be attached to a message, in bytes,
+/// unless `MAX_ATTACHMENT_SIZE` is set
+pub const DEFAULT_MAX_ATTACHMENT_SIZE: u64 = 25 * 1024 * 1024;
+
+/// Extensions of files that cannot be attached to messages, as they could run on whoever opens them
+pub const DENIED_ATTACHMENT_EXTENSIONS: [&str; 16] = [
+    "exe", "msi", "bat", "cmd", "com", "scr", "pif", "dll", "vbs", "js", "jar", "ps1", "sh", "apk", "html", "svg"
+];
+
+/// Content types of files that cannot be attached to messages, whatever their extension
+pub const DENIED_ATTACHMENT_TYPES: [&str; 9] = [
+    "application/x-msdownload",
+    "application/x-msdos-program",
+    "application/x-msi",
+    "application/x-sh",
+    "application/java-archive",
+    "application/vnd.android.package-archive",
+    "application/javascript",
+    "text/html",
+    "image/svg+xml"
+];
+
+/// How many bytes of a file are sent at a time when it is downloaded
+pub const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How many files can be attached to a single message
+pub const MAX_ATTACHMENTS_PER_MESSAGE: usize = 4;
+
+/// Room for the text fields of a message on top of its files
+pub const MAX_MESSAGE_FORM_TEXT_BYTES: u64 = 1024 * 1024;
+
+/// How many bytes of attachments each user can have in total
+pub const ATTACHMENT_QUOTA: i64 = 100 * 1024 * 1024;
+
+/// How many requests a client can make to a rate limited route, and in how many seconds,
+/// unless `RATE_LIMITS` sets a limit for the route
+pub const DEFAULT_RATE_LIMIT: (u32, u64) = (30, 60);
+
+/// How many rate limit buckets are kept before the full ones are dropped
+pub const RATE_LIMIT_MAX_BUCKETS: usize = 10_000;
+
+/// How many lines a code block in a message can have before it is collapsed,
+/// unless `CODE_BLOCK_COLLAPSE_LINES` is set
+pub const DEFAULT_CODE_BLOCK_COLLAPSE_LINES: usize = 15;
+
+/// How often scheduled messages that are due are sent
+pub const SCHEDULED_MESSAGES_INTERVAL_SECONDS: u64 = 5;
+
+/// How many scheduled messages are sent at a time
+pub const SCHEDULED_MESSAGES_PER_BATCH: i64 = 100;
+
+/// How many times sending a scheduled message can fail before it is given up on
+pub const SCHEDULED_MESSAGE_MAX_ATTEMPTS: i32 = 5;
+
+/// How long a scheduled message that failed waits before it is tried again, times how many times it failed
+pub const SCHEDULED_MESSAGE_RETRY_SECONDS: i32 = 60;
+
+/// How many messages a user can have scheduled at once
+pub const MAX_SCHEDULED_MESSAGES: i64 = 50;
+
+/// How often messages that expired are deleted
+pub const EXPIRED_MESSAGES_INTERVAL_SECONDS: u64 = 10;
+
+/// How many expired messages are deleted at a time
+pub const EXPIRED_MESSAGES_PER_BATCH: i64 = 100;
+
+/// The longest messages can be set to last before they disappear
+pub const MAX_DISAPPEAR_AFTER_SECONDS: i64 = 365 * 24 * 60 * 60;
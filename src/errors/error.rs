@@ -13,7 +13,10 @@ pub enum ErrorReason {
     InvalidFileName,
     IncompleteData,
     ALreadyLoggedIn,
-    InvalidRequest
+    InvalidRequest,
+    FileTooLarge,
+    QuotaExceeded,
+    TooManyRequests
 }
 
 pub struct Error {
@@ -79,6 +82,9 @@ impl ErrorReason {
             "incomplete_data" => ErrorReason::IncompleteData,
             "already_logged_in" => ErrorReason::ALreadyLoggedIn,
             "invalid_request" => ErrorReason::InvalidRequest,
+            "file_too_large" => ErrorReason::FileTooLarge,
+            "quota_exceeded" => ErrorReason::QuotaExceeded,
+            "too_many_requests" => ErrorReason::TooManyRequests,
             _ => ErrorReason::SomethingWentWrong,
         }
     }
@@ -97,6 +103,9 @@ impl ErrorReason {
             ErrorReason::IncompleteData => "incomplete_data",
             ErrorReason::ALreadyLoggedIn => "already_logged_in",
             ErrorReason::InvalidRequest => "invalid_request",
+            ErrorReason::FileTooLarge => "file_too_large",
+            ErrorReason::QuotaExceeded => "quota_exceeded",
+            ErrorReason::TooManyRequests => "too_many_requests",
         }
     }
 }
@@ -1,184 +1,252 @@
 #[macro_use] extern crate rocket;
 
-use chat_server::{api, catchers, chats::chat_struct::Chat, db::{self, Db}, pages::{auth, chats, homepage}, user::user_struct::User, utils::get_placeholder_display_image};
-use rocket::{form::Form, fs::FileServer, http::Status, response::{content::RawHtml, status, stream::{Event, EventStream}}, tokio::sync::broadcast::{channel, error::RecvError, Sender}, Shutdown, State};
+use chat_server::{api, catchers, html, chats::{attachment::{Attachment, NewAttachment}, chat_struct::{Chat, ChatRoomMessage, NewMessage}, event::ChatEvent, disappearing, presence, pubsub::{self, PubSub}, scheduled}, db::{self, Db}, consts, errors::error::{Error, ErrorReason}, pages::{auth, chats, homepage}, user::user_struct::User, utils::{env, html::Html, rate_limit::{RateLimit, RateLimiter}, upload}};
+use rocket::{fs::FileServer, http::{ContentType, Status}, response::{content::RawHtml, status}, Data, State};
+use rocket_multipart_form_data::{multer, FileField, MultipartFormData, MultipartFormDataError, MultipartFormDataField, MultipartFormDataOptions, Repetition};
 use rocket_csrf_token::{CsrfConfig, Fairing};
 use rocket_db_pools::Connection;
 use rocket_dyn_templates::{handlebars::handlebars_helper, Template};
-use serde::{Deserialize, Serialize};
-use rocket::tokio::select;
 
 handlebars_helper!(eq_str: |first_arg: String, second_arg: String| first_arg == second_arg);
 handlebars_helper!(eq_num: |first_arg: isize, second_arg: isize| first_arg == second_arg);
 
-#[derive(Debug, Clone, FromForm, Serialize, Deserialize)]
-#[serde(crate = "rocket::serde")]
-struct ChatRoomMessage {
-    sender_id: i32,
-    receiver_id: i32,
-    message: String,
-    created_at: Option<String>,
+/// The first value of a text field of a multipart form
+fn text_field<'a>(data: &'a MultipartFormData, name: &str) -> Option<&'a str> {
+    data.texts.get(name).and_then(|fields| fields.first()).map(|field| field.text.as_str())
 }
 
-#[get("/events/chats?<sender_id>&<receiver_id>")]
-async fn chats_sse(
-    mut db: Connection<Db>,
-    queue: &State<Sender<ChatRoomMessage>>,
-    sender_id: i32,
-    receiver_id: i32,
-    user: User,
-    mut end: Shutdown
-) -> Result<EventStream![], status::Custom<String>> {
-    if user.id != sender_id && receiver_id != user.id {
-        return Err(status::Custom(Status::Unauthorized, "Unauthorized".to_string()));
+/// Checks the files attached to a message against the limits and the user's quota, then uploads them.
+async fn upload_attachments(
+    db: &mut Connection<Db>,
+    user: &User,
+    files: &[FileField]
+) -> Result<Vec<NewAttachment>, status::Custom<String>> {
+    if files.len() > consts::MAX_ATTACHMENTS_PER_MESSAGE {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(
+                ErrorReason::Invalid,
+                format!("Only {} files can be sent at a time.", consts::MAX_ATTACHMENTS_PER_MESSAGE)
+            ))
+        ));
     }
 
-    let receiver: Option<User>;
+    let mut total_size = 0;
+
+    for file in files {
+        let mime_type = file.content_type.as_ref().map(|mime_type| mime_type.essence_str()).unwrap_or_default();
+        let file_name = file.file_name.as_deref().unwrap_or_default();
+
+        if file_name.is_empty() {
+            return Err(status::Custom(
+                Status::NotAcceptable,
+                Error::to_string(Error::new(ErrorReason::InvalidFileName, "File name is required.".to_string()))
+            ));
+        }
+
+        if upload::is_denied_file(file_name, mime_type) {
+            return Err(status::Custom(
+                Status::NotAcceptable,
+                Error::to_string(Error::new(ErrorReason::InvalidMimeType, format!("{} cannot be sent, that type of file is not allowed.", file_name)))
+            ));
+        }
 
-    if receiver_id == user.id {
-        receiver = Some(user.clone());
-    } else {
-        receiver = User::get_by_id(&mut db, &receiver_id).await;
+        total_size += upload::file_size(file).await as i64;
     }
-    
-    if receiver.is_none() {
-        return Err(status::Custom(Status::NotFound, "User to chat with not found.".to_string()));
+
+    let used = Attachment::get_total_size_of_user(db, &user.id).await.map_err(|err| {
+        println!("Error: {:?}", err);
+
+        status::Custom(
+            Status::InternalServerError,
+            Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Something went wrong. Please try again.".to_string()))
+        )
+    })?;
+
+    if used + total_size > consts::ATTACHMENT_QUOTA {
+        return Err(status::Custom(
+            Status::PayloadTooLarge,
+            Error::to_string(Error::new(
+                ErrorReason::QuotaExceeded,
+                format!("You can only have {} of files in your chats.", upload::format_file_size(consts::ATTACHMENT_QUOTA as u64))
+            ))
+        ));
     }
 
-    let receiver = receiver.unwrap();
-    let mut rx = queue.subscribe();
-
-    Ok(EventStream! {
-        loop {
-            let msg = select! {
-                msg = rx.recv() => match msg {
-                    Ok(msg) => {
-                        println!("{}: {:?}", user.id, msg);
-                        msg
-                    },
-                    Err(RecvError::Closed) => break,
-                    Err(RecvError::Lagged(_)) => continue
-                },
-                _ = &mut end => break
-            };
-
-            if (msg.sender_id == sender_id && msg.receiver_id == receiver_id) || (msg.sender_id == receiver_id && msg.receiver_id == sender_id) {
-                let name = if msg.sender_id == user.id {
-                    &user.display_name
-                } else {
-                    &receiver.display_name
-                };
-
-                let display_image = if msg.sender_id == user.id {
-                    get_placeholder_display_image(user.display_image.as_ref(), &user.gender)
-                } else {
-                    get_placeholder_display_image(receiver.display_image.as_ref(), &receiver.gender)
-                };
-
-                let is_receiver = msg.sender_id != user.id;
-                let mut html: String;
-
-                if is_receiver {
-                    html = format!(
-                        r#"<li data-isreceiver="{}">
-                            <div class="chats__message">
-                                <div>
-                                <img
-                                    src="{}"
-                                    alt="{}'s Profile picture"
-                                    width="28"
-                                    height="28"
-                                    loading="lazy"
-                                    class="profile"
-                                />
-                                    <div>
-                                        <small>{}</small>
-                                        <p>{}</p>
-                                    </div>
-                                </div>
-                                <time>{}</time>
-                            </div>
-                        </li>"#,
-                        is_receiver,
-                        display_image,
-                        name,
-                        name,
-                        msg.message,
-                        msg.created_at.unwrap()
-                    );
-                } else {
-                    html = format!(
-                        r#"<li data-isreceiver="{}">
-                            <div class="chats__message">
-                                <div>
-                                    <p>{}</p>
-                                </div>
-                                <time>{}</time>
-                            </div>
-                        </li>"#,
-                        is_receiver,
-                        msg.message,
-                        msg.created_at.unwrap()
-                    );
-                }
-
-                let id = format!("msg_{}{}", sender_id, receiver_id);
-
-                html.push_str(format!(
-                    "
-                        <p hx-swap-oob=\"true\" id=\"{}\">{}</p>
-                    ",
-                    id,
-                    msg.message
-                ).as_str());
-
-                yield Event::data(
-                    html
-                ).event("message")
+    let mut attachments = Vec::new();
+
+    for file in files {
+        let content_type = file.content_type.as_ref()
+            .map(|mime_type| mime_type.essence_str().to_string())
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+
+        let object_name = match upload::upload_file(file, &content_type).await {
+            Ok(object_name) => object_name,
+            Err(err) => {
+                delete_uploads(&attachments).await;
+                return Err(err);
             }
-        }
-    })
+        };
+
+        attachments.push(NewAttachment {
+            object_name,
+            file_name: file.file_name.clone().unwrap_or_default(),
+            size: upload::file_size(file).await as i64,
+            content_type,
+        });
+    }
+
+    Ok(attachments)
+}
+
+/// Deletes files that were uploaded for a message that was not saved.
+async fn delete_uploads(attachments: &[NewAttachment]) {
+    for attachment in attachments {
+        // Already logged, the file is only left behind in storage.
+        let _ = upload::delete_file(&attachment.object_name).await;
+    }
 }
 
+/// Sends a message along with the files attached to it, if any.
+/// Expects a multipart form. Sending a `client_id` again does nothing.
 #[post("/chats/send", data = "<data>")]
 async fn send_msg(
     mut db: Connection<Db>,
-    data: Form<ChatRoomMessage>,
+    content_type: &ContentType,
+    data: Data<'_>,
     user: User,
-    queue: &State<Sender<ChatRoomMessage>>
-) -> Result<RawHtml<String>, status::Custom<String>>{
-    let receiver_id = &data.receiver_id;
-    let sender_id = &data.sender_id;
-    let message = &data.message;
+    _rate_limit: RateLimit,
+    pubsub: &State<PubSub>
+) -> Result<RawHtml<Html>, status::Custom<String>>{
+    let max_attachment_size = env::load_max_attachment_size();
+    let mut options = MultipartFormDataOptions::with_multipart_form_data_fields(
+        vec![
+            MultipartFormDataField::text("sender_id"),
+            MultipartFormDataField::text("receiver_id"),
+            MultipartFormDataField::text("message"),
+            MultipartFormDataField::text("reply_to"),
+            MultipartFormDataField::text("client_id"),
+            MultipartFormDataField::text("plain"),
+            // One more than allowed, so too many files are refused instead of silently left out.
+            MultipartFormDataField::file("attachments")
+                .size_limit(max_attachment_size)
+                .repetition(Repetition::fixed(consts::MAX_ATTACHMENTS_PER_MESSAGE as u32 + 1))
+        ]
+    );
+
+    options.max_data_bytes = consts::MAX_ATTACHMENTS_PER_MESSAGE as u64 * max_attachment_size + consts::MAX_MESSAGE_FORM_TEXT_BYTES;
+
+    let data = match MultipartFormData::parse(content_type, data, options).await {
+        Ok(data) => data,
+        Err(MultipartFormDataError::DataTooLargeError(_)) => return Err(status::Custom(
+            Status::PayloadTooLarge,
+            Error::to_string(Error::new(
+                ErrorReason::FileTooLarge,
+                format!("Files cannot be larger than {}.", upload::format_file_size(max_attachment_size))
+            ))
+        )),
+        // Cut off at `max_data_bytes`.
+        Err(MultipartFormDataError::MulterError(multer::Error::IncompleteStream | multer::Error::IncompleteFieldData { .. })) => return Err(status::Custom(
+            Status::PayloadTooLarge,
+            Error::to_string(Error::new(
+                ErrorReason::FileTooLarge,
+                format!(
+                    "Only {} files of up to {} each can be sent at a time.",
+                    consts::MAX_ATTACHMENTS_PER_MESSAGE,
+                    upload::format_file_size(max_attachment_size)
+                )
+            ))
+        )),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(
+                Status::BadRequest,
+                Error::to_string(Error::new(ErrorReason::InvalidRequest, "Failed to process information".to_string()))
+            ));
+        }
+    };
+
+    let sender_id = text_field(&data, "sender_id").and_then(|id| id.parse::<i32>().ok());
+    let receiver_id = text_field(&data, "receiver_id").and_then(|id| id.parse::<i32>().ok());
+    let message = text_field(&data, "message").unwrap_or_default().to_string();
+    let reply_to = text_field(&data, "reply_to").and_then(|id| id.parse::<i32>().ok());
+    let client_id = text_field(&data, "client_id").map(str::to_string);
+    let is_plain = matches!(text_field(&data, "plain"), Some("true" | "on"));
+    let files = data.files.get("attachments").map(Vec::as_slice).unwrap_or_default();
 
-    if sender_id != &user.id {
+    let (sender_id, receiver_id) = match (sender_id, receiver_id) {
+        (Some(sender_id), Some(receiver_id)) => (sender_id, receiver_id),
+        _ => return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::IncompleteData, "Who the message is from and to is required.".to_string()))
+        ))
+    };
+
+    if sender_id != user.id {
         return Err(status::Custom(Status::Unauthorized, "Unauthorized".to_string()));
     }
 
-    let receiver_name = User::get_display_name(&mut db, receiver_id).await;
+    if message.trim().is_empty() && files.is_empty() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::Required, "Message cannot be empty.".to_string()))
+        ));
+    }
+
+    let new_message = NewMessage::new(message, reply_to, client_id, is_plain);
 
-    if receiver_name.is_none() {
-        return Err(status::Custom(Status::NotFound, "User to chat with not found.".to_string()));
+    if !new_message.has_valid_client_id() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::Invalid, "The id of the message is too long.".to_string()))
+        ));
     }
 
-    let res = Chat::save_chat(&mut db, sender_id, receiver_id, &receiver_name.unwrap(), message).await;
+    let sent = RawHtml(html!(
+        "{}{}{}{}",
+        chats::components::message_input(),
+        chats::components::reply_preview(None, true),
+        chats::components::attachment_input(true),
+        chats::components::client_id_input(true)
+    ));
 
-    if res.is_err() {
-        return Err(status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string()));
+    // Checked before uploading the files again.
+    if let Some(client_id) = &new_message.client_id {
+        match ChatRoomMessage::get_by_client_id(&mut db, &user.id, client_id).await {
+            Ok(Some(_)) => return Ok(sent),
+            Ok(None) => {},
+            Err(err) => {
+                println!("Error: {:?}", err);
+                return Err(status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string()));
+            }
+        }
     }
 
-    let created_at = res.unwrap();
+    let receiver_name = match User::get_display_name(&mut db, &receiver_id).await {
+        Ok(Some(receiver_name)) => receiver_name,
+        Ok(None) => return Err(status::Custom(Status::NotFound, "User to chat with not found.".to_string())),
+        Err(err) => {
+            println!("Error: {:?}", err);
+            return Err(status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string()));
+        }
+    };
 
-    let _res = queue.send(ChatRoomMessage {
-        sender_id: *sender_id,
-        receiver_id: *receiver_id,
-        message: message.clone(),
-        created_at: Some(format!("{}-{}-{} at {}:{}:{}", created_at.year(), created_at.month(), created_at.day(), created_at.hour(), created_at.minute(), created_at.second()))
-    });
+    let attachments = upload_attachments(&mut db, &user, files).await?;
+    let res = Chat::save_chat(&mut db, &sender_id, &receiver_id, &receiver_name, &new_message, &attachments).await;
 
-    Ok(RawHtml(r#"
-        <input id="message_input" type="text" required name="message" placeholder="Type a message" />
-    "#.to_string()))
+    match res {
+        Ok(msg) => pubsub.publish(ChatEvent::Message(msg)).await,
+        // The same message was sent at the same time, and that one was saved with its own files.
+        Err(err) if ChatRoomMessage::is_duplicate(&err) => delete_uploads(&attachments).await,
+        Err(err) => {
+            println!("Error: {:?}", err);
+            delete_uploads(&attachments).await;
+            return Err(status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string()));
+        }
+    }
+
+    Ok(sent)
 }
 
 #[launch]
@@ -186,7 +254,7 @@ fn rocket() -> _  {
     dotenv::dotenv().ok();
 
     rocket::build()
-        .mount("/", routes![homepage::page, chats_sse, send_msg])
+        .mount("/", routes![homepage::page, chats::events::chats_sse, chats::events::groups_sse, chats::events::me_sse, send_msg])
         .mount("/auth", routes![
             auth::login::page,
             auth::login::redirect_if_logged_in,
@@ -200,14 +268,64 @@ fn rocket() -> _  {
         ])
         .mount("/chats", routes! [
             chats::api::chats_of_user::chats_of_user,
+            chats::api::messages::messages,
+            chats::api::typing::typing,
+            chats::api::edit::editor,
+            chats::api::edit::edit,
+            chats::api::delete::delete,
+            chats::api::messages::message,
+            chats::api::reply::reply,
+            chats::api::react::react,
+            chats::api::groups::groups,
+            chats::api::groups::group,
+            chats::api::groups::messages,
+            chats::api::groups::create,
+            chats::api::groups::send,
+            chats::api::groups::add_member,
+            chats::api::groups::remove_member,
+            chats::api::attachments::download,
+            chats::api::search::search,
+            chats::api::scheduled::schedule,
+            chats::api::scheduled::scheduled,
+            chats::api::scheduled::editor,
+            chats::api::scheduled::edit,
+            chats::api::scheduled::cancel,
+            chats::api::disappearing::set,
             chats::index::page,
             chats::api::chats_of_user::error_if_logged_out,
+            chats::api::messages::error_if_logged_out,
+            chats::api::typing::error_if_logged_out,
+            chats::api::edit::editor_if_logged_out,
+            chats::api::edit::error_if_logged_out,
+            chats::api::delete::error_if_logged_out,
+            chats::api::messages::message_if_logged_out,
+            chats::api::reply::error_if_logged_out,
+            chats::api::react::error_if_logged_out,
+            chats::api::groups::groups_if_logged_out,
+            chats::api::groups::group_if_logged_out,
+            chats::api::groups::messages_if_logged_out,
+            chats::api::groups::create_if_logged_out,
+            chats::api::groups::send_if_logged_out,
+            chats::api::groups::add_member_if_logged_out,
+            chats::api::groups::remove_member_if_logged_out,
+            chats::api::attachments::error_if_logged_out,
+            chats::api::search::error_if_logged_out,
+            chats::api::scheduled::schedule_if_logged_out,
+            chats::api::scheduled::scheduled_if_logged_out,
+            chats::api::scheduled::editor_if_logged_out,
+            chats::api::scheduled::edit_if_logged_out,
+            chats::api::scheduled::cancel_if_logged_out,
+            chats::api::disappearing::error_if_logged_out,
             chats::index::rederirect_if_logged_out,
         ])
         .mount("/search", routes! [
             api::search::search,
             api::search::unauthorized_search
         ])
+        .mount("/ws", routes! [
+            api::ws::chats,
+            api::ws::unauthorized_chats
+        ])
         .attach(Template::custom(|engines| {
             engines
                 .handlebars
@@ -271,7 +389,11 @@ fn rocket() -> _  {
         }))
         .attach(Fairing::new(CsrfConfig::default()))
         .attach(db::stage())
-        .register("/", catchers![catchers::internal_error, catchers::not_found, catchers::unauthorized])
+        .attach(pubsub::stage())
+        .attach(presence::stage())
+        .attach(scheduled::stage())
+        .attach(disappearing::stage())
+        .manage(RateLimiter::from_env())
+        .register("/", catchers![catchers::internal_error, catchers::not_found, catchers::unauthorized, catchers::too_many_requests])
         .mount("/assets", FileServer::from("assets"))
-        .manage(channel::<ChatRoomMessage>(1024).0)
 }
@@ -1,11 +1,8 @@
-use std::{fs::File, io::Read};
-
-use cloud_storage::Client;
 use rocket::{http::{ContentType, CookieJar, Status}, post, response::status, Data};
 use rocket_db_pools::Connection;
 use rocket_multipart_form_data::{MultipartFormData, MultipartFormDataField, MultipartFormDataOptions};
 
-use crate::{db::Db, errors::error::{Error, ErrorReason}, pages::auth::_utils::{self, create_user}, utils};
+use crate::{db::Db, errors::error::{Error, ErrorReason}, pages::auth::_utils::{self, create_user}, utils::{self, upload}};
 
 #[post("/register", data = "<data>")]
 pub async fn register_user(
@@ -96,80 +93,16 @@ pub async fn register_user(
                                                 Some(mime_type) => {
                                                     let mime_type = mime_type.essence_str();
 
-                                                    match mime_type {
-                                                        "image/jpg" | "image/jpeg" | "image/png" | "image/webp" | "image/avif" => {
-                                                            let file_name = &display_image.file_name;
-                                                            let file_path = &display_image.path;
-
-                                                            match file_name {
-                                                                Some(file_name) => {
-                                                                    let read_file = File::open(file_path);
-
-                                                                    match read_file {
-                                                                        Ok(buf) => {
-                                                                            let mut bytes: Vec<u8> = Vec::new();
-
-                                                                            for byte in buf.bytes() {
-                                                                                match byte {
-                                                                                    Ok(byte) => {
-                                                                                        bytes.push(byte);
-                                                                                    }
-                                                                                    Err(err) => {
-                                                                                        println!("Error: {:?}", err);
-
-                                                                                        return Err(status::Custom(
-                                                                                            Status::InternalServerError,
-                                                                                            Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to process image. File might be corrupted.".to_string()))
-                                                                                        ));
-                                                                                    }
-                                                                                }
-                                                                            }
-
-                                                                            let random_name = random_string::generate(12,random_string::charsets::ALPHANUMERIC);
-                                                                            let name = format!("{}-chat_server-{}", random_name, file_name);
-                                                                            let result = Client::default().object().create("chat_server_local_development", bytes, &name, mime_type).await;
-
-                                                                            match result {
-                                                                                Ok(_obj) => {
-                                                                                    let display_image = format!("https://storage.cloud.google.com/chat_server_local_development/{}", name);
-                                                                                    
-                                                                                    create_user(&mut db, cookies, display_name, &display_image, password, &gender.clone().into()).await
-                                                                                }
-                                                                                Err(err) => {
-                                                                                    println!("Error: {:?}", err);
-
-                                                                                    Err(status::Custom(
-                                                                                        Status::InternalServerError,
-                                                                                        Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to process image.".to_string()))
-                                                                                    ))
-                                                                                }
-                                                                            }
-                                                                        }
-                                                                        Err(err) => {
-                                                                            println!("Error: {:?}", err);
-
-                                                                            Err(status::Custom(
-                                                                                Status::InternalServerError,
-                                                                                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to process image.".to_string()))
-                                                                            ))
-                                                                        }
-                                                                    }
-                                                                }
-                                                                None => {
-                                                                    Err(status::Custom(
-                                                                        Status::NotAcceptable,
-                                                                        Error::to_string(Error::new(ErrorReason::Invalid, "File name is required.".to_string()))
-                                                                    ))
-                                                                }
-                                                            }
-                                                        }
-                                                        _ => {
-                                                            Err(status::Custom(
-                                                                Status::NotAcceptable,
-                                                                Error::to_string(Error::new(ErrorReason::InvalidMimeType, "Only Jpeg, Webp, Png, and Avif images are allowed.".to_string()))
-                                                            ))
-                                                        }
+                                                    if !upload::is_allowed_image(mime_type) {
+                                                        return Err(status::Custom(
+                                                            Status::NotAcceptable,
+                                                            Error::to_string(Error::new(ErrorReason::InvalidMimeType, "Only Jpeg, Webp, Png, and Avif images are allowed.".to_string()))
+                                                        ));
                                                     }
+
+                                                    let display_image = upload::public_url(&upload::upload_file(display_image, mime_type).await?);
+
+                                                    create_user(&mut db, cookies, display_name, &display_image, password, &gender.clone().into()).await
                                                 }
                                                 None => {
                                                     Err(status::Custom(
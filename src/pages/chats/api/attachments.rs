@@ -0,0 +1,66 @@
+use rocket::{futures::Stream, get, http::{ContentType, Header, Status}, response::{status, stream::ByteStream}, Responder};
+use rocket_db_pools::Connection;
+
+use crate::{chats::{attachment::Attachment, conversation::Conversation}, consts, db::Db, errors::error::{Error, ErrorReason}, user::user_struct::User, utils::upload};
+
+/// The contents of an attachment, under the name it was uploaded with.
+/// The browser never guesses another type for it, and never runs it as a page of the app.
+#[derive(Responder)]
+pub struct AttachmentFile<S> {
+    inner: (ContentType, ByteStream<S>),
+    content_disposition: Header<'static>,
+    content_type_options: Header<'static>,
+    content_security_policy: Header<'static>,
+}
+
+/// Downloads a file attached to a message of one of the user's conversations.
+/// Images are shown in the browser, every other file is saved.
+#[get("/attachments/<attachment_id>")]
+pub async fn download(
+    mut db: Connection<Db>,
+    user: User,
+    attachment_id: i32
+) -> Result<AttachmentFile<impl Stream<Item = Vec<u8>> + Send>, status::Custom<String>> {
+    let attachment = match Attachment::get_stored(&mut db, &attachment_id).await {
+        Ok(Some(attachment)) => attachment,
+        Ok(None) => return Err(status::Custom(
+            Status::NotFound,
+            Error::to_string(Error::new(ErrorReason::InvalidRequest, "File not found.".to_string()))
+        )),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to get the file.".to_string()))
+            ));
+        }
+    };
+
+    if !Conversation::is_member(&mut db, &attachment.conversation_id, &user.id).await {
+        return Err(status::Custom(
+            Status::Unauthorized,
+            Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+        ));
+    }
+
+    let chunks = upload::download_file(&attachment.object_name).await?;
+    let content_type = ContentType::parse_flexible(&attachment.content_type).unwrap_or(ContentType::Binary);
+    let disposition = if consts::ALLOWED_IMAGE_TYPES.contains(&attachment.content_type.as_str()) { "inline" } else { "attachment" };
+    let file_name = attachment.file_name.replace(['"', '\\', '\r', '\n'], "");
+
+    Ok(AttachmentFile {
+        inner: (content_type, ByteStream(chunks)),
+        content_disposition: Header::new("Content-Disposition", format!("{}; filename=\"{}\"", disposition, file_name)),
+        content_type_options: Header::new("X-Content-Type-Options", "nosniff"),
+        content_security_policy: Header::new("Content-Security-Policy", "sandbox"),
+    })
+}
+
+#[get("/attachments/<_attachment_id>", rank = 2)]
+pub fn error_if_logged_out(_attachment_id: i32) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+    )
+}
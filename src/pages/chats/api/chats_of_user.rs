@@ -1,7 +1,7 @@
 use rocket::{form::Form, post, response::content::RawHtml, FromForm};
 use rocket_db_pools::Connection;
 
-use crate::{chats::chat_struct::Chat, db::Db, errors::error::{Error, ErrorReason}, user::user_struct::User};
+use crate::{chats::chat_struct::Chat, db::Db, errors::error::{Error, ErrorReason}, pages::chats::components, html, user::user_struct::User, utils::html::Html};
 
 #[derive(FromForm)]
 pub struct SeachFormData {
@@ -14,7 +14,7 @@ pub async fn chats_of_user(
     user: User,
     user_id: i32,
     data: Form<SeachFormData>
-) -> Result<RawHtml<String>, String> {
+) -> Result<RawHtml<Html>, String> {
     if user.id != user_id {
         return Err(Error::to_string(Error::new(ErrorReason::Unauthorized, format!("You are not authorized to view the chats of User: {}.", user_id))));
     }
@@ -24,10 +24,10 @@ pub async fn chats_of_user(
     match user_chats {
         Ok(user_chats) => {
             if user_chats.is_empty() {
-                return Ok(RawHtml("<li><p>No chats found</p></li>".to_string()));
+                return Ok(RawHtml(Html::trusted("<li><p>No chats found</p></li>")));
             }
 
-            let mut html = String::new();
+            let mut html = Html::new();
 
             for chat in user_chats {
                 let receiver_name = if chat.sender_id == user_id {
@@ -51,7 +51,7 @@ pub async fn chats_of_user(
                     &chat.receiver_id
                 };
 
-                html.push_str(&format!(
+                html.push(&html!(
                     "
                     <li data-iscurrent=\"\">
                         <button
@@ -75,6 +75,7 @@ pub async fn chats_of_user(
                                 <span>{}</span>
                                 <p>{}</p>
                             </div>
+                            {}
                         </button>
                         <hr>
                     </li>
@@ -85,7 +86,8 @@ pub async fn chats_of_user(
                     receiver_avatar,
                     receiver_name,
                     receiver_name,
-                    chat.message
+                    chat.message,
+                    components::unread_badge(user_id, *receiver_id, chat.unread_count, false)
                 ));
             }
 
@@ -95,7 +97,7 @@ pub async fn chats_of_user(
             println!("Error: {:?}", err);
 
             Ok(
-                RawHtml("<li><p>Something went wrong in fetching chats.</p></li>".to_string())
+                RawHtml(Html::trusted("<li><p>Something went wrong in fetching chats.</p></li>"))
             )
         }
     }
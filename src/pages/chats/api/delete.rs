@@ -0,0 +1,111 @@
+use rocket::{form::Form, http::Status, post, response::{content::RawHtml, status}, FromForm, State};
+use rocket_db_pools::Connection;
+
+use crate::{chats::{chat_struct::{Chat, ChatRoomMessage}, conversation::Conversation, event::ChatEvent, pubsub::PubSub}, db::Db, errors::error::{Error, ErrorReason}, pages::chats::components, user::user_struct::User, utils::{get_placeholder_display_image, html::Html, upload}};
+
+#[derive(FromForm)]
+pub struct DeleteFormData {
+    message_id: i32,
+    /// Otherwise the message is only deleted for the user
+    for_everyone: bool
+}
+
+/// Deletes a message either for the user only, or for everyone in the conversation.
+/// Returns what takes the place of the message: nothing, or a placeholder.
+#[post("/delete", data = "<data>")]
+pub async fn delete(
+    mut db: Connection<Db>,
+    user: User,
+    pubsub: &State<PubSub>,
+    data: Form<DeleteFormData>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let msg = match ChatRoomMessage::get_by_id(&mut db, &data.message_id).await {
+        Ok(msg) => msg,
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(
+                Status::NotFound,
+                Error::to_string(Error::new(ErrorReason::InvalidRequest, "Message not found.".to_string()))
+            ));
+        }
+    };
+
+    if !Conversation::is_member(&mut db, &msg.conversation_id, &user.id).await {
+        return Err(status::Custom(
+            Status::Unauthorized,
+            Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+        ));
+    }
+
+    // Groups have no single other user.
+    let other_user_id = msg.receiver_id.map(|receiver_id| if msg.sender_id == user.id { receiver_id } else { msg.sender_id });
+
+    if !data.for_everyone {
+        return match Chat::delete_for_me(&mut db, &user.id, &data.message_id).await {
+            Ok(_) => {
+                pubsub.publish(ChatEvent::Hidden {
+                    user_id: user.id,
+                    conversation_id: msg.conversation_id,
+                    other_user_id,
+                    message_id: data.message_id,
+                }).await;
+
+                Ok(RawHtml(Html::new()))
+            },
+            Err(err) => {
+                println!("Error: {:?}", err);
+
+                Err(status::Custom(
+                    Status::InternalServerError,
+                    Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to delete the message.".to_string()))
+                ))
+            }
+        };
+    }
+
+    if msg.sender_id != user.id {
+        return Err(status::Custom(
+            Status::Forbidden,
+            Error::to_string(Error::new(ErrorReason::Unauthorized, "You can only delete your own messages for everyone.".to_string()))
+        ));
+    }
+
+    match Chat::delete_for_everyone(&mut db, &user.id, &data.message_id).await {
+        Ok(deleted) => {
+            // Already deleted, nothing changes.
+            let deleted = match deleted {
+                Some((deleted, object_names)) => {
+                    pubsub.publish(ChatEvent::Deleted(deleted.clone())).await;
+
+                    for object_name in &object_names {
+                        // Already logged, the file is only left behind in storage.
+                        let _ = upload::delete_file(object_name).await;
+                    }
+
+                    deleted
+                },
+                None => msg
+            };
+            let display_image = get_placeholder_display_image(user.display_image.as_ref(), &user.gender);
+
+            Ok(RawHtml(components::message(&deleted.to_chat_message(user.id), &user.display_name, &display_image, false)))
+        },
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to delete the message.".to_string()))
+            ))
+        }
+    }
+}
+
+#[post("/delete", data = "<_data>", rank = 2)]
+pub fn error_if_logged_out(_data: Form<DeleteFormData>) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to delete messages.".to_string()))
+    )
+}
@@ -0,0 +1,125 @@
+use rocket::{form::Form, http::Status, post, response::{content::RawHtml, status}, FromForm, FromFormField, State};
+use rocket_db_pools::Connection;
+use sqlx::Acquire;
+
+use crate::{chats::{chat_struct::{ChatRoomMessage, NewMessage}, conversation::Conversation, disappearing, event::ChatEvent, pubsub::PubSub}, consts, db::Db, errors::error::{Error, ErrorReason}, pages::chats::components, user::user_struct::User, utils::html::Html};
+
+#[derive(FromFormField, Clone, Copy)]
+pub enum DisappearUnit {
+    Minutes,
+    Hours,
+    Days,
+}
+
+impl DisappearUnit {
+    fn seconds(&self) -> i64 {
+        match self {
+            DisappearUnit::Minutes => 60,
+            DisappearUnit::Hours => 60 * 60,
+            DisappearUnit::Days => 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(FromForm)]
+pub struct DisappearingFormData {
+    conversation_id: i32,
+    /// Messages stop disappearing if it is 0
+    amount: u32,
+    unit: DisappearUnit
+}
+
+/// Sets how long new messages of a 1:1 conversation last once they are seen, for both users.
+/// Lets the conversation know with a system message if it changed.
+/// Groups cannot have disappearing messages, as every member sees a message at a different time.
+#[post("/disappearing", data = "<data>")]
+pub async fn set(
+    mut db: Connection<Db>,
+    user: User,
+    pubsub: &State<PubSub>,
+    data: Form<DisappearingFormData>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let seconds = match data.amount as i64 * data.unit.seconds() {
+        0 => None,
+        seconds if seconds > consts::MAX_DISAPPEAR_AFTER_SECONDS => return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(
+                ErrorReason::Invalid,
+                format!("Messages can last at most {} before they disappear.", disappearing::format_duration(consts::MAX_DISAPPEAR_AFTER_SECONDS as i32))
+            ))
+        )),
+        seconds => Some(seconds as i32),
+    };
+
+    let conversation = match Conversation::get_by_id(&mut db, &data.conversation_id).await {
+        Ok(Some(conversation)) if conversation.has_member(user.id) => conversation,
+        Ok(_) => return Err(status::Custom(
+            Status::Unauthorized,
+            Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+        )),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Something went wrong. Please try again.".to_string()))
+            ));
+        }
+    };
+
+    if conversation.is_group {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::Invalid, "Disappearing messages are only available in 1:1 chats.".to_string()))
+        ));
+    }
+
+    // The other user is the receiver of the system message.
+    let receiver = conversation.members.iter().find(|member| member.id != user.id).or(conversation.members.first());
+    let message = NewMessage::system(disappearing::setting_changed_message(&user.display_name, seconds));
+
+    let res: Result<Option<ChatRoomMessage>, sqlx::Error> = async {
+        let mut transaction = db.begin().await?;
+
+        if !Conversation::set_disappear_after(&mut transaction, &conversation.id, seconds).await? {
+            return Ok(None);
+        }
+
+        let msg = ChatRoomMessage::insert(
+            &mut transaction,
+            &conversation.id,
+            &user.id,
+            receiver.map(|receiver| &receiver.id),
+            receiver.map(|receiver| &receiver.display_name),
+            &message
+        ).await?;
+
+        transaction.commit().await?;
+
+        Ok(Some(msg))
+    }.await;
+
+    match res {
+        Ok(Some(msg)) => pubsub.publish(ChatEvent::Message(msg)).await,
+        // Nothing changed, so there is nothing to tell.
+        Ok(None) => {},
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to change disappearing messages.".to_string()))
+            ));
+        }
+    }
+
+    Ok(RawHtml(components::disappearing_setting(conversation.id, seconds, false)))
+}
+
+#[post("/disappearing", data = "<_data>", rank = 2)]
+pub fn error_if_logged_out(_data: Form<DisappearingFormData>) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to change disappearing messages.".to_string()))
+    )
+}
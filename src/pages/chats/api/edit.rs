@@ -0,0 +1,117 @@
+use rocket::{form::Form, get, http::Status, post, response::{content::RawHtml, status}, FromForm, State};
+use rocket_db_pools::Connection;
+
+use crate::{chats::{chat_struct::{Chat, ChatRoomMessage}, event::ChatEvent, pubsub::PubSub}, db::Db, errors::error::{Error, ErrorReason}, pages::chats::components, user::user_struct::User, utils::{get_placeholder_display_image, html::Html}};
+
+#[derive(FromForm)]
+pub struct EditFormData {
+    message_id: i32,
+    message: String
+}
+
+/// Gets a message the user sent, so only its owner can edit it.
+async fn get_own_message(
+    db: &mut Connection<Db>,
+    user: &User,
+    message_id: &i32
+) -> Result<ChatRoomMessage, status::Custom<String>> {
+    let msg = ChatRoomMessage::get_by_id(db, message_id).await.map_err(|err| {
+        println!("Error: {:?}", err);
+
+        status::Custom(
+            Status::NotFound,
+            Error::to_string(Error::new(ErrorReason::InvalidRequest, "Message not found.".to_string()))
+        )
+    })?;
+
+    if msg.deleted_at.is_some() {
+        return Err(status::Custom(
+            Status::NotFound,
+            Error::to_string(Error::new(ErrorReason::InvalidRequest, "Message not found.".to_string()))
+        ));
+    }
+
+    if msg.sender_id != user.id {
+        return Err(status::Custom(
+            Status::Forbidden,
+            Error::to_string(Error::new(ErrorReason::Unauthorized, "You can only edit your own messages.".to_string()))
+        ));
+    }
+
+    Ok(msg)
+}
+
+/// The form to edit a message, in place of the message itself.
+#[get("/edit?<message_id>")]
+pub async fn editor(
+    mut db: Connection<Db>,
+    user: User,
+    message_id: i32
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let msg = get_own_message(&mut db, &user, &message_id).await?;
+
+    Ok(RawHtml(components::message_editor(&msg.to_chat_message(user.id))))
+}
+
+/// Saves the new text of a message and lets both participants know.
+#[post("/edit", data = "<data>")]
+pub async fn edit(
+    mut db: Connection<Db>,
+    user: User,
+    pubsub: &State<PubSub>,
+    data: Form<EditFormData>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    if data.message.trim().is_empty() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::Required, "Message cannot be empty.".to_string()))
+        ));
+    }
+
+    let msg = get_own_message(&mut db, &user, &data.message_id).await?;
+
+    // Nothing changed, so there is nothing to keep a revision of.
+    let msg = if msg.message == data.message {
+        msg
+    } else {
+        match Chat::edit_chat(&mut db, &user.id, &data.message_id, &data.message).await {
+            Ok(Some(msg)) => {
+                pubsub.publish(ChatEvent::Edited(msg.clone())).await;
+
+                msg
+            },
+            Ok(None) => return Err(status::Custom(
+                Status::NotFound,
+                Error::to_string(Error::new(ErrorReason::InvalidRequest, "Message not found.".to_string()))
+            )),
+            Err(err) => {
+                println!("Error: {:?}", err);
+
+                return Err(status::Custom(
+                    Status::InternalServerError,
+                    Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to edit the message.".to_string()))
+                ));
+            }
+        }
+    };
+
+    let display_image = get_placeholder_display_image(user.display_image.as_ref(), &user.gender);
+
+    Ok(RawHtml(components::message(&msg.to_chat_message(user.id), &user.display_name, &display_image, false)))
+}
+
+#[get("/edit?<_message_id>", rank = 2)]
+pub fn editor_if_logged_out(_message_id: i32) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to edit messages.".to_string()))
+    )
+}
+
+#[post("/edit", data = "<_data>", rank = 2)]
+pub fn error_if_logged_out(_data: Form<EditFormData>) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to edit messages.".to_string()))
+    )
+}
@@ -0,0 +1,452 @@
+use rocket::{form::Form, get, http::Status, post, response::{content::RawHtml, status}, FromForm, State};
+use rocket_db_pools::Connection;
+
+use crate::{chats::{chat_struct::{Chat, ChatRoomMessage, MessageCursor, MessageWindow, NewMessage, NewerMessages}, conversation::Conversation, event::ChatEvent, pubsub::PubSub}, consts, db::Db, errors::error::{Error, ErrorReason}, pages::chats::components, user::user_struct::User, html, utils::{html::Html, rate_limit::RateLimit}};
+
+#[derive(FromForm)]
+pub struct NewGroupFormData {
+    name: String,
+    /// Display names of everyone else to add, separated by commas
+    members: String
+}
+
+#[derive(FromForm)]
+pub struct GroupSendFormData {
+    conversation_id: i32,
+    message: String,
+    /// The id of the message this one replies to
+    reply_to: Option<i32>,
+    /// See [`NewMessage::client_id`]
+    client_id: Option<String>,
+    /// Sends the message as it is instead of as markdown
+    plain: bool
+}
+
+#[derive(FromForm)]
+pub struct AddMemberFormData {
+    conversation_id: i32,
+    display_name: String
+}
+
+#[derive(FromForm)]
+pub struct RemoveMemberFormData {
+    conversation_id: i32,
+    user_id: i32
+}
+
+/// Gets a group the user is a member of.
+async fn get_group_of_member(
+    db: &mut Connection<Db>,
+    user: &User,
+    conversation_id: &i32
+) -> Result<Conversation, status::Custom<String>> {
+    let conversation = match Conversation::get_by_id(db, conversation_id).await {
+        Ok(Some(conversation)) if conversation.is_group => conversation,
+        Ok(_) => return Err(status::Custom(
+            Status::NotFound,
+            Error::to_string(Error::new(ErrorReason::InvalidRequest, "Group not found.".to_string()))
+        )),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to get the group.".to_string()))
+            ));
+        }
+    };
+
+    if !conversation.has_member(user.id) {
+        return Err(status::Custom(
+            Status::Unauthorized,
+            Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+        ));
+    }
+
+    Ok(conversation)
+}
+
+/// Renders a group with its latest messages, or opens it at `message_id`
+async fn render_group(
+    db: &mut Connection<Db>,
+    user: &User,
+    conversation: Conversation,
+    message_id: Option<i32>
+) -> Result<Html, status::Custom<String>> {
+    let window = match message_id {
+        Some(message_id) => Chat::messages_around(db, &user.id, &conversation.id, &message_id).await.unwrap_or_else(|err| {
+            println!("Error: {:?}", err);
+            None
+        }),
+        None => None,
+    };
+    // Messages that are not in the group are not scrolled to.
+    let focus = window.and(message_id).map(components::focus_message).unwrap_or_default();
+    let window = window.unwrap_or(MessageWindow { before: None, limit: consts::MESSAGES_PER_PAGE, newer: None });
+
+    match Conversation::get_messages(db, &user.id, conversation, window.before.as_ref(), window.limit).await {
+        Ok(mut group) => {
+            group.newer = window.newer;
+
+            Ok(html!("{}{}", components::group_chat(&group, user.id), focus))
+        },
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to get chats.".to_string()))
+            ))
+        }
+    }
+}
+
+/// Every group of the user, for the sidebar.
+#[get("/groups/list")]
+pub async fn groups(
+    mut db: Connection<Db>,
+    user: User
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    match Conversation::get_groups_of_user(&mut db, &user.id).await {
+        Ok(groups) => Ok(RawHtml(components::group_list(&groups, false))),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to get groups.".to_string()))
+            ))
+        }
+    }
+}
+
+/// Opens a group, at `message_id` if it is set.
+#[get("/groups?<conversation_id>&<message_id>")]
+pub async fn group(
+    mut db: Connection<Db>,
+    user: User,
+    conversation_id: i32,
+    message_id: Option<i32>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let conversation = get_group_of_member(&mut db, &user, &conversation_id).await?;
+
+    Ok(RawHtml(render_group(&mut db, &user, conversation, message_id).await?))
+}
+
+/// A page of older messages of a group, or of newer ones if it was opened at an older message.
+#[get("/groups/messages?<conversation_id>&<before>&<limit>&<newer..>")]
+pub async fn messages(
+    mut db: Connection<Db>,
+    user: User,
+    conversation_id: i32,
+    before: Option<MessageCursor>,
+    limit: Option<i64>,
+    newer: Option<NewerMessages>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let conversation = get_group_of_member(&mut db, &user, &conversation_id).await?;
+    let limit = limit.unwrap_or(consts::MESSAGES_PER_PAGE).clamp(1, 100);
+    let window = match &newer {
+        Some(newer) => Chat::messages_after(&mut db, &user.id, &conversation_id, &newer.after, newer.until, limit).await,
+        None => Ok(MessageWindow { before, limit, newer: None }),
+    };
+    let group = match window {
+        Ok(window) => Conversation::get_messages(&mut db, &user.id, conversation, window.before.as_ref(), window.limit).await.map(|mut group| {
+            // The older messages are already shown above a page of newer ones.
+            if newer.is_some() {
+                group.before = None;
+            }
+
+            group.newer = window.newer;
+            group
+        }),
+        Err(err) => Err(err),
+    };
+
+    match group {
+        Ok(group) => Ok(RawHtml(components::group_messages(&group))),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to get messages.".to_string()))
+            ))
+        }
+    }
+}
+
+/// Creates a group with the user and everyone in `members`, then opens it.
+#[post("/groups", data = "<data>")]
+pub async fn create(
+    mut db: Connection<Db>,
+    user: User,
+    pubsub: &State<PubSub>,
+    data: Form<NewGroupFormData>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let name = data.name.trim().to_string();
+
+    if name.is_empty() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::Required, "Group name is required.".to_string()))
+        ));
+    }
+
+    let mut member_ids = Vec::new();
+
+    for display_name in data.members.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        match User::get_by_display_name(&mut db, &display_name.to_string()).await {
+            Some(member) if member.id != user.id => member_ids.push(member.id),
+            Some(_) => continue,
+            None => return Err(status::Custom(
+                Status::NotFound,
+                Error::to_string(Error::new(ErrorReason::InvalidRequest, format!("User {} not found.", display_name)))
+            ))
+        }
+    }
+
+    if member_ids.is_empty() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::Required, "Add at least one other member.".to_string()))
+        ));
+    }
+
+    let conversation_id = match Conversation::create_group(&mut db, &user.id, &name, &member_ids).await {
+        Ok(conversation_id) => conversation_id,
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to create the group.".to_string()))
+            ));
+        }
+    };
+
+    for member_id in member_ids.iter().chain([user.id].iter()) {
+        pubsub.publish(ChatEvent::Membership {
+            conversation_id,
+            user_id: *member_id,
+            joined: true,
+        }).await;
+    }
+
+    let conversation = get_group_of_member(&mut db, &user, &conversation_id).await?;
+    let mut html = render_group(&mut db, &user, conversation, None).await?;
+
+    if let Ok(groups) = Conversation::get_groups_of_user(&mut db, &user.id).await {
+        html.push(&components::group_list(&groups, true));
+    }
+
+    Ok(RawHtml(html))
+}
+
+/// Sends a message to every member of a group.
+#[post("/groups/send", data = "<data>")]
+pub async fn send(
+    mut db: Connection<Db>,
+    user: User,
+    _rate_limit: RateLimit,
+    pubsub: &State<PubSub>,
+    data: Form<GroupSendFormData>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    if data.message.trim().is_empty() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::Required, "Message cannot be empty.".to_string()))
+        ));
+    }
+
+    let GroupSendFormData { conversation_id, message, reply_to, client_id, plain } = data.into_inner();
+    let new_message = NewMessage::new(message, reply_to, client_id, plain);
+
+    if !new_message.has_valid_client_id() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::Invalid, "The id of the message is too long.".to_string()))
+        ));
+    }
+
+    get_group_of_member(&mut db, &user, &conversation_id).await?;
+
+    let sent = RawHtml(html!(
+        "{}{}{}",
+        components::group_message_input(),
+        components::reply_preview(None, true),
+        components::client_id_input(true)
+    ));
+
+    match Conversation::send(&mut db, &user.id, &conversation_id, &new_message).await {
+        Ok(msg) => {
+            pubsub.publish(ChatEvent::Message(msg)).await;
+
+            Ok(sent)
+        },
+        // Sent before, so everyone already has it.
+        Err(err) if ChatRoomMessage::is_duplicate(&err) => Ok(sent),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Something went wrong. Please try again.".to_string()))
+            ))
+        }
+    }
+}
+
+/// Adds someone to a group. Any member can add people.
+#[post("/groups/members", data = "<data>")]
+pub async fn add_member(
+    mut db: Connection<Db>,
+    user: User,
+    pubsub: &State<PubSub>,
+    data: Form<AddMemberFormData>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    get_group_of_member(&mut db, &user, &data.conversation_id).await?;
+
+    let member = match User::get_by_display_name(&mut db, &data.display_name.trim().to_string()).await {
+        Some(member) => member,
+        None => return Err(status::Custom(
+            Status::NotFound,
+            Error::to_string(Error::new(ErrorReason::InvalidRequest, "User not found.".to_string()))
+        ))
+    };
+
+    match Conversation::add_member(&mut db, &data.conversation_id, &member.id).await {
+        Ok(true) => {
+            pubsub.publish(ChatEvent::Membership {
+                conversation_id: data.conversation_id,
+                user_id: member.id,
+                joined: true,
+            }).await;
+        },
+        Ok(false) => return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::AlreadyExists, format!("{} is already a member.", member.display_name)))
+        )),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to add the member.".to_string()))
+            ));
+        }
+    }
+
+    let conversation = get_group_of_member(&mut db, &user, &data.conversation_id).await?;
+
+    Ok(RawHtml(components::group_members(&conversation, user.id, false)))
+}
+
+/// Removes someone from a group. Only whoever created it can remove others,
+/// but every member can leave.
+#[post("/groups/members/remove", data = "<data>")]
+pub async fn remove_member(
+    mut db: Connection<Db>,
+    user: User,
+    pubsub: &State<PubSub>,
+    data: Form<RemoveMemberFormData>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let conversation = get_group_of_member(&mut db, &user, &data.conversation_id).await?;
+    let is_leaving = data.user_id == user.id;
+
+    if !is_leaving && conversation.created_by != Some(user.id) {
+        return Err(status::Custom(
+            Status::Forbidden,
+            Error::to_string(Error::new(ErrorReason::Unauthorized, "Only whoever created the group can remove its members.".to_string()))
+        ));
+    }
+
+    match Conversation::remove_member(&mut db, &data.conversation_id, &data.user_id).await {
+        Ok(true) => {
+            pubsub.publish(ChatEvent::Membership {
+                conversation_id: data.conversation_id,
+                user_id: data.user_id,
+                joined: false,
+            }).await;
+        },
+        Ok(false) => return Err(status::Custom(
+            Status::NotFound,
+            Error::to_string(Error::new(ErrorReason::InvalidRequest, "They are not a member of this group.".to_string()))
+        )),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to remove the member.".to_string()))
+            ));
+        }
+    }
+
+    if is_leaving {
+        return Ok(RawHtml(html!(
+            "<div><p>You left {}.</p></div><li id=\"group_{}\" hx-swap-oob=\"delete\"></li>",
+            conversation.name.unwrap_or_default(),
+            data.conversation_id
+        )));
+    }
+
+    let conversation = get_group_of_member(&mut db, &user, &data.conversation_id).await?;
+
+    Ok(RawHtml(components::group_members(&conversation, user.id, false)))
+}
+
+#[get("/groups/list", rank = 2)]
+pub fn groups_if_logged_out() -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+    )
+}
+
+#[get("/groups?<_conversation_id>", rank = 2)]
+pub fn group_if_logged_out(_conversation_id: i32) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+    )
+}
+
+#[get("/groups/messages?<_conversation_id>", rank = 2)]
+pub fn messages_if_logged_out(_conversation_id: i32) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+    )
+}
+
+#[post("/groups", data = "<_data>", rank = 2)]
+pub fn create_if_logged_out(_data: Form<NewGroupFormData>) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to create groups.".to_string()))
+    )
+}
+
+#[post("/groups/send", data = "<_data>", rank = 2)]
+pub fn send_if_logged_out(_data: Form<GroupSendFormData>) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to chat without being logged in.".to_string()))
+    )
+}
+
+#[post("/groups/members", data = "<_data>", rank = 2)]
+pub fn add_member_if_logged_out(_data: Form<AddMemberFormData>) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to change groups.".to_string()))
+    )
+}
+
+#[post("/groups/members/remove", data = "<_data>", rank = 2)]
+pub fn remove_member_if_logged_out(_data: Form<RemoveMemberFormData>) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to change groups.".to_string()))
+    )
+}
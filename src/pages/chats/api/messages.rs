@@ -0,0 +1,130 @@
+use rocket::{get, http::Status, response::{content::RawHtml, status}};
+use rocket_db_pools::Connection;
+
+use crate::{chats::{chat_struct::{Chat, ChatRoomMessage, MessageCursor, MessageWindow, NewerMessages}, conversation::Conversation}, consts, db::Db, errors::error::{Error, ErrorReason}, pages::chats::components, user::user_struct::User, utils::{get_placeholder_display_image, html::Html}};
+
+/// A page of older messages, requested by the loader at the top of a conversation,
+/// or of newer ones, by the loader at the bottom of one opened at an older message.
+#[get("/messages?<sender_id>&<receiver_id>&<before>&<limit>&<newer..>")]
+pub async fn messages(
+    mut db: Connection<Db>,
+    user: User,
+    sender_id: i32,
+    receiver_id: i32,
+    before: Option<MessageCursor>,
+    limit: Option<i64>,
+    newer: Option<NewerMessages>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    if sender_id != user.id && receiver_id != user.id {
+        return Err(status::Custom(
+            Status::Unauthorized,
+            Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+        ));
+    }
+
+    let limit = limit.unwrap_or(consts::MESSAGES_PER_PAGE).clamp(1, 100);
+    let window = match &newer {
+        Some(newer) => newer_messages(&mut db, &user.id, &sender_id, &receiver_id, newer, limit).await,
+        None => Ok(MessageWindow { before, limit, newer: None }),
+    };
+    let user_chats = match window {
+        Ok(window) => Chat::get_messages(&mut db, &user.id, &sender_id, &receiver_id, window.before.as_ref(), window.limit).await.map(|mut user_chats| {
+            // The older messages are already shown above a page of newer ones.
+            if newer.is_some() {
+                user_chats.before = None;
+            }
+
+            user_chats.newer = window.newer;
+            user_chats
+        }),
+        Err(err) => Err(err),
+    };
+
+    match user_chats {
+        Ok(user_chats) => Ok(RawHtml(components::messages(&user_chats))),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to get messages.".to_string()))
+            ))
+        }
+    }
+}
+
+/// The page of messages from `newer.after` on in the conversation between two users.
+async fn newer_messages(
+    db: &mut Connection<Db>,
+    user_id: &i32,
+    sender_id: &i32,
+    receiver_id: &i32,
+    newer: &NewerMessages,
+    limit: i64
+) -> Result<MessageWindow, sqlx::Error> {
+    match Conversation::get_direct_id(db, sender_id, receiver_id).await? {
+        Some(conversation_id) => Chat::messages_after(db, user_id, &conversation_id, &newer.after, newer.until, limit).await,
+        None => Ok(MessageWindow { before: None, limit: 0, newer: None }),
+    }
+}
+
+/// A single message, for ex. to put it back after cancelling an edit.
+#[get("/message?<message_id>")]
+pub async fn message(
+    mut db: Connection<Db>,
+    user: User,
+    message_id: i32
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let msg = match ChatRoomMessage::get_by_id(&mut db, &message_id).await {
+        Ok(msg) => msg,
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(
+                Status::NotFound,
+                Error::to_string(Error::new(ErrorReason::InvalidRequest, "Message not found.".to_string()))
+            ));
+        }
+    };
+
+    if !Conversation::is_member(&mut db, &msg.conversation_id, &user.id).await {
+        return Err(status::Custom(
+            Status::Unauthorized,
+            Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+        ));
+    }
+
+    let sender = if msg.sender_id == user.id {
+        Some(user.clone())
+    } else {
+        User::get_by_id(&mut db, &msg.sender_id).await
+    };
+
+    match sender {
+        Some(sender) => {
+            let display_image = get_placeholder_display_image(sender.display_image.as_ref(), &sender.gender);
+
+            Ok(RawHtml(components::message(&msg.to_chat_message(user.id), &sender.display_name, &display_image, false)))
+        },
+        None => Err(status::Custom(
+            Status::NotFound,
+            Error::to_string(Error::new(ErrorReason::InvalidRequest, "Message not found.".to_string()))
+        ))
+    }
+}
+
+#[get("/message?<_message_id>", rank = 2)]
+pub fn message_if_logged_out(_message_id: i32) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+    )
+}
+
+#[get("/messages?<_sender_id>&<_receiver_id>", rank = 2)]
+pub fn error_if_logged_out(_sender_id: i32, _receiver_id: i32) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+    )
+}
@@ -1 +1,12 @@
+pub mod attachments;
 pub mod chats_of_user;
+pub mod delete;
+pub mod disappearing;
+pub mod edit;
+pub mod groups;
+pub mod messages;
+pub mod react;
+pub mod reply;
+pub mod scheduled;
+pub mod search;
+pub mod typing;
@@ -0,0 +1,89 @@
+use rocket::{form::Form, http::Status, post, response::{content::RawHtml, status}, FromForm, State};
+use rocket_db_pools::Connection;
+
+use crate::{chats::{chat_struct::{ChatRoomMessage, Reaction}, conversation::Conversation, event::ChatEvent, pubsub::PubSub}, consts, db::Db, errors::error::{Error, ErrorReason}, pages::chats::components, user::user_struct::User, utils::html::Html};
+
+#[derive(FromForm)]
+pub struct ReactFormData {
+    message_id: i32,
+    emoji: String
+}
+
+/// Reacts to a message with an emoji, or takes the reaction back if the user already reacted with it.
+#[post("/react", data = "<data>")]
+pub async fn react(
+    mut db: Connection<Db>,
+    user: User,
+    pubsub: &State<PubSub>,
+    data: Form<ReactFormData>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    if !consts::REACTION_EMOJIS.contains(&data.emoji.as_str()) {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::Invalid, "You cannot react with that emoji.".to_string()))
+        ));
+    }
+
+    let msg = match ChatRoomMessage::get_by_id(&mut db, &data.message_id).await {
+        Ok(msg) if msg.deleted_at.is_none() => msg,
+        Ok(_) => {
+            return Err(status::Custom(
+                Status::NotFound,
+                Error::to_string(Error::new(ErrorReason::InvalidRequest, "Message not found.".to_string()))
+            ));
+        },
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(
+                Status::NotFound,
+                Error::to_string(Error::new(ErrorReason::InvalidRequest, "Message not found.".to_string()))
+            ));
+        }
+    };
+
+    if !Conversation::is_member(&mut db, &msg.conversation_id, &user.id).await {
+        return Err(status::Custom(
+            Status::Unauthorized,
+            Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+        ));
+    }
+
+    let reactions = match Reaction::toggle(&mut db, &user.id, &data.message_id, &data.emoji).await {
+        Ok(_) => Reaction::get_by_message_ids(&mut db, &[data.message_id]).await,
+        Err(err) => Err(err)
+    };
+
+    match reactions {
+        Ok(mut reactions) => {
+            let reactions = reactions.remove(&data.message_id).unwrap_or_default();
+            let html = components::reactions(data.message_id, &reactions, user.id, false);
+
+            pubsub.publish(ChatEvent::Reacted {
+                message_id: data.message_id,
+                conversation_id: msg.conversation_id,
+                sender_id: msg.sender_id,
+                receiver_id: msg.receiver_id,
+                reactions,
+            }).await;
+
+            Ok(RawHtml(html))
+        },
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to react to the message.".to_string()))
+            ))
+        }
+    }
+}
+
+#[post("/react", data = "<_data>", rank = 2)]
+pub fn error_if_logged_out(_data: Form<ReactFormData>) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to react to messages.".to_string()))
+    )
+}
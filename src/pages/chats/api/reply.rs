@@ -0,0 +1,55 @@
+use rocket::{get, http::Status, response::{content::RawHtml, status}};
+use rocket_db_pools::Connection;
+
+use crate::{chats::{chat_struct::{ChatRoomMessage, QuotedMessage}, conversation::Conversation}, db::Db, errors::error::{Error, ErrorReason}, pages::chats::components, user::user_struct::User, utils::html::Html};
+
+/// Starts replying to a message of one of the user's conversations.
+#[get("/reply?<message_id>")]
+pub async fn reply(
+    mut db: Connection<Db>,
+    user: User,
+    message_id: i32
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let msg = match ChatRoomMessage::get_by_id(&mut db, &message_id).await {
+        Ok(msg) if msg.deleted_at.is_none() => msg,
+        Ok(_) => {
+            return Err(status::Custom(
+                Status::NotFound,
+                Error::to_string(Error::new(ErrorReason::InvalidRequest, "Message not found.".to_string()))
+            ));
+        },
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            return Err(status::Custom(
+                Status::NotFound,
+                Error::to_string(Error::new(ErrorReason::InvalidRequest, "Message not found.".to_string()))
+            ));
+        }
+    };
+
+    if !Conversation::is_member(&mut db, &msg.conversation_id, &user.id).await {
+        return Err(status::Custom(
+            Status::Unauthorized,
+            Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+        ));
+    }
+
+    let quoted = QuotedMessage {
+        id: message_id,
+        sender_id: msg.sender_id,
+        message: msg.message,
+        deleted: false,
+        is_plain: msg.is_plain,
+    };
+
+    Ok(RawHtml(components::reply_preview(Some(&quoted), false)))
+}
+
+#[get("/reply?<_message_id>", rank = 2)]
+pub fn error_if_logged_out(_message_id: i32) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view this chat.".to_string()))
+    )
+}
@@ -0,0 +1,225 @@
+use rocket::{form::Form, get, http::Status, post, response::{content::RawHtml, status}, FromForm};
+use rocket_db_pools::Connection;
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+
+use crate::{chats::scheduled::ScheduledMessage, consts, db::Db, errors::error::{Error, ErrorReason}, html, pages::chats::components, user::user_struct::User, utils::{html::Html, rate_limit::RateLimit}};
+
+#[derive(FromForm)]
+pub struct ScheduleFormData {
+    receiver_id: i32,
+    message: String,
+    plain: bool,
+    /// In the user's timezone, as a `datetime-local` input has it
+    send_at: Option<PrimitiveDateTime>,
+    timezone_offset: Option<i32>
+}
+
+#[derive(FromForm)]
+pub struct EditScheduledFormData {
+    id: i32,
+    message: String,
+    send_at: Option<PrimitiveDateTime>,
+    timezone_offset: Option<i32>
+}
+
+#[derive(FromForm)]
+pub struct CancelScheduledFormData {
+    id: i32
+}
+
+fn something_went_wrong(err: sqlx::Error) -> status::Custom<String> {
+    println!("Error: {:?}", err);
+
+    status::Custom(
+        Status::InternalServerError,
+        Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Something went wrong. Please try again.".to_string()))
+    )
+}
+
+fn already_sent() -> status::Custom<String> {
+    status::Custom(
+        Status::NotFound,
+        Error::to_string(Error::new(ErrorReason::InvalidRequest, "The message was already sent or cancelled.".to_string()))
+    )
+}
+
+/// The timezone of the user, from how many minutes their clock is behind UTC
+/// like `Date.getTimezoneOffset` has it, see `chats.js`. UTC if it is not known.
+fn timezone(timezone_offset: Option<i32>) -> UtcOffset {
+    timezone_offset
+        .and_then(|minutes| minutes.checked_mul(-60))
+        .and_then(|seconds| UtcOffset::from_whole_seconds(seconds).ok())
+        .unwrap_or(UtcOffset::UTC)
+}
+
+/// Checks the message is not empty and is sent in the future.
+fn validate(message: &str, send_at: Option<PrimitiveDateTime>, timezone: UtcOffset) -> Result<OffsetDateTime, status::Custom<String>> {
+    if message.trim().is_empty() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::Required, "Message cannot be empty.".to_string()))
+        ));
+    }
+
+    let send_at = match send_at {
+        Some(send_at) => send_at.assume_offset(timezone),
+        None => return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::Required, "Pick when to send the message.".to_string()))
+        )),
+    };
+
+    if send_at <= OffsetDateTime::now_utc() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            Error::to_string(Error::new(ErrorReason::Invalid, "Messages can only be scheduled for later.".to_string()))
+        ));
+    }
+
+    Ok(send_at)
+}
+
+/// Schedules the message in the form to be sent later, instead of sending it now.
+/// Clears the form and shows the messages scheduled in the conversation.
+#[post("/scheduled", data = "<data>")]
+pub async fn schedule(
+    mut db: Connection<Db>,
+    user: User,
+    _rate_limit: RateLimit,
+    data: Form<ScheduleFormData>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let timezone = timezone(data.timezone_offset);
+    let send_at = validate(&data.message, data.send_at, timezone)?;
+
+    if User::get_display_name(&mut db, &data.receiver_id).await.map_err(something_went_wrong)?.is_none() {
+        return Err(status::Custom(Status::NotFound, "User to chat with not found.".to_string()));
+    }
+
+    let pending = ScheduledMessage::count_pending(&mut db, &user.id).await.map_err(something_went_wrong)?;
+
+    if pending >= consts::MAX_SCHEDULED_MESSAGES {
+        return Err(status::Custom(
+            Status::Forbidden,
+            Error::to_string(Error::new(
+                ErrorReason::QuotaExceeded,
+                format!("You can only have {} messages scheduled.", consts::MAX_SCHEDULED_MESSAGES)
+            ))
+        ));
+    }
+
+    ScheduledMessage::insert(&mut db, &user.id, &data.receiver_id, &data.message, data.plain, &send_at)
+        .await
+        .map_err(something_went_wrong)?;
+
+    let scheduled = ScheduledMessage::get_pending(&mut db, &user.id, &data.receiver_id)
+        .await
+        .map_err(something_went_wrong)?;
+
+    Ok(RawHtml(html!(
+        "{}{}{}",
+        components::message_input(),
+        components::schedule_input(true),
+        components::scheduled_messages(&scheduled, timezone, true)
+    )))
+}
+
+/// The messages the user scheduled in the conversation with `receiver_id`.
+#[get("/scheduled?<receiver_id>&<timezone_offset>")]
+pub async fn scheduled(
+    mut db: Connection<Db>,
+    user: User,
+    receiver_id: i32,
+    timezone_offset: Option<i32>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let scheduled = ScheduledMessage::get_pending(&mut db, &user.id, &receiver_id)
+        .await
+        .map_err(something_went_wrong)?;
+
+    Ok(RawHtml(components::scheduled_messages(&scheduled, timezone(timezone_offset), false)))
+}
+
+/// The form to edit a scheduled message, in place of the message itself.
+#[get("/scheduled/edit?<id>&<timezone_offset>")]
+pub async fn editor(
+    mut db: Connection<Db>,
+    user: User,
+    id: i32,
+    timezone_offset: Option<i32>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    match ScheduledMessage::get_by_id(&mut db, &user.id, &id).await {
+        Ok(Some(scheduled)) => Ok(RawHtml(components::scheduled_message_editor(&scheduled, timezone(timezone_offset)))),
+        Ok(None) => Err(already_sent()),
+        Err(err) => Err(something_went_wrong(err)),
+    }
+}
+
+/// Changes what a scheduled message says and when it is sent, unless it was already sent.
+#[post("/scheduled/edit", data = "<data>")]
+pub async fn edit(
+    mut db: Connection<Db>,
+    user: User,
+    data: Form<EditScheduledFormData>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let timezone = timezone(data.timezone_offset);
+    let send_at = validate(&data.message, data.send_at, timezone)?;
+
+    match ScheduledMessage::edit(&mut db, &user.id, &data.id, &data.message, &send_at).await {
+        Ok(Some(scheduled)) => Ok(RawHtml(components::scheduled_message(&scheduled, timezone))),
+        Ok(None) => Err(already_sent()),
+        Err(err) => Err(something_went_wrong(err)),
+    }
+}
+
+/// Cancels a scheduled message, which is then removed from the list.
+#[post("/scheduled/cancel", data = "<data>")]
+pub async fn cancel(
+    mut db: Connection<Db>,
+    user: User,
+    data: Form<CancelScheduledFormData>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    match ScheduledMessage::cancel(&mut db, &user.id, &data.id).await {
+        Ok(true) => Ok(RawHtml(Html::new())),
+        Ok(false) => Err(already_sent()),
+        Err(err) => Err(something_went_wrong(err)),
+    }
+}
+
+#[post("/scheduled", data = "<_data>", rank = 2)]
+pub fn schedule_if_logged_out(_data: Form<ScheduleFormData>) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to schedule messages.".to_string()))
+    )
+}
+
+#[get("/scheduled?<_receiver_id>", rank = 2)]
+pub fn scheduled_if_logged_out(_receiver_id: i32) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to view scheduled messages.".to_string()))
+    )
+}
+
+#[get("/scheduled/edit?<_id>", rank = 2)]
+pub fn editor_if_logged_out(_id: i32) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to edit scheduled messages.".to_string()))
+    )
+}
+
+#[post("/scheduled/edit", data = "<_data>", rank = 2)]
+pub fn edit_if_logged_out(_data: Form<EditScheduledFormData>) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to edit scheduled messages.".to_string()))
+    )
+}
+
+#[post("/scheduled/cancel", data = "<_data>", rank = 2)]
+pub fn cancel_if_logged_out(_data: Form<CancelScheduledFormData>) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to cancel scheduled messages.".to_string()))
+    )
+}
@@ -0,0 +1,43 @@
+use rocket::{get, http::Status, response::{content::RawHtml, status}};
+use rocket_db_pools::Connection;
+
+use crate::{chats::search::MessageSearchResult, consts, db::Db, errors::error::{Error, ErrorReason}, pages::chats::components, user::user_struct::User, utils::{html::Html, rate_limit::RateLimit}};
+
+/// Searches the messages of every conversation of the user.
+/// `page` starts at 0 and stops at [`consts::MAX_SEARCH_PAGES`], and nothing is returned for an empty `q`.
+#[get("/search?<q>&<page>")]
+pub async fn search(
+    mut db: Connection<Db>,
+    user: User,
+    _rate_limit: RateLimit,
+    q: Option<String>,
+    page: Option<i64>
+) -> Result<RawHtml<Html>, status::Custom<String>> {
+    let query = q.unwrap_or_default().trim().to_string();
+
+    if query.is_empty() {
+        return Ok(RawHtml(Html::new()));
+    }
+
+    let page = page.unwrap_or(0).clamp(0, consts::MAX_SEARCH_PAGES - 1);
+
+    match MessageSearchResult::search(&mut db, &user.id, &query, page, consts::SEARCH_RESULTS_PER_PAGE).await {
+        Ok(search) => Ok(RawHtml(components::message_search_results(&search, user.id))),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to search messages.".to_string()))
+            ))
+        }
+    }
+}
+
+#[get("/search?<_q>&<_page>", rank = 2)]
+pub fn error_if_logged_out(_q: Option<String>, _page: Option<i64>) -> status::Custom<String> {
+    status::Custom(
+        Status::Unauthorized,
+        Error::to_string(Error::new(ErrorReason::Unauthorized, "You are not authorized to search messages.".to_string()))
+    )
+}
@@ -0,0 +1,35 @@
+use rocket::{form::Form, http::Status, post, FromForm, State};
+
+use crate::{chats::{event::ChatEvent, presence::Presence, pubsub::PubSub}, user::user_struct::User, utils::rate_limit::RateLimit};
+
+#[derive(FromForm)]
+pub struct TypingFormData {
+    receiver_id: i32
+}
+
+/// Lets `receiver_id` know the user is typing, if they share a conversation.
+/// Never publishes through the database, so it only reaches subscribers of this instance.
+#[post("/typing", data = "<data>")]
+pub async fn typing(
+    user: User,
+    _rate_limit: RateLimit,
+    presence: &State<Presence>,
+    pubsub: &State<PubSub>,
+    data: Form<TypingFormData>
+) -> Status {
+    if !presence.are_partners(user.id, data.receiver_id).await {
+        return Status::Forbidden;
+    }
+
+    pubsub.publish_local(ChatEvent::Typing {
+        user_id: user.id,
+        receiver_id: data.receiver_id,
+    });
+
+    Status::NoContent
+}
+
+#[post("/typing", data = "<_data>", rank = 2)]
+pub fn error_if_logged_out(_data: Form<TypingFormData>) -> Status {
+    Status::Unauthorized
+}
@@ -0,0 +1,1346 @@
+use time::{OffsetDateTime, UtcOffset};
+
+use crate::{chats::{chat_struct::{format_created_at, ChatMessage, MessageCursor, MessageStatus, MessagesInChat, NewerMessages, QuotedMessage, Reaction}, conversation::{Conversation, GroupPreview, MessagesInGroup}, disappearing, scheduled::ScheduledMessage, search::{self, MessageSearchResults}}, consts, html, utils::{html::Html, markdown, upload}};
+
+/// Makes an element replace the one with the same id when it is swapped in from another response
+fn oob_attribute(oob: bool) -> Html {
+    if oob { Html::trusted("hx-swap-oob=\"true\"") } else { Html::new() }
+}
+
+/// A search snippet with the words that matched in `<mark>`
+fn highlighted_snippet(snippet: &str) -> Html {
+    let mut html = Html::new();
+
+    for (i, part) in snippet.split(search::MATCH_START).enumerate() {
+        // Everything before the first match
+        if i == 0 {
+            html.push(part);
+            continue;
+        }
+
+        let (matched, rest) = part.split_once(search::MATCH_END).unwrap_or((part, ""));
+
+        html.push(&html!("<mark>{}</mark>{}", matched, rest));
+    }
+
+    html
+}
+
+/// Shown next to the time a message was sent once it has been edited
+fn edited_marker(chat: &ChatMessage) -> Html {
+    match &chat.edited_at {
+        Some(edited_at) if chat.deleted_at.is_none() => html!(
+            "<small class=\"chats__edited\" title=\"Edited {}\">edited</small>",
+            edited_at
+        ),
+        _ => Html::new(),
+    }
+}
+
+/// The text of a message, or a placeholder once it is deleted for everyone
+fn message_text(chat: &ChatMessage) -> Html {
+    match chat.deleted_at {
+        Some(_) => html!("<p class=\"chats__deleted\">{}</p>", consts::DELETED_MESSAGE_PLACEHOLDER),
+        // Only images were sent
+        None if chat.message.is_empty() => Html::new(),
+        None if chat.is_plain => html!("<p class=\"chats__plain\">{}</p>", chat.message),
+        None => html!("<div class=\"chats__text\">{}</div>", markdown::render(&chat.message)),
+    }
+}
+
+/// The files attached to a message.
+/// Images are shown as thumbnails that open the full image, every other file as a download link.
+fn attachments(chat: &ChatMessage) -> Html {
+    if chat.deleted_at.is_some() || chat.attachments.is_empty() {
+        return Html::new();
+    }
+
+    let mut html = Html::trusted("<div class=\"chats__attachments\">");
+
+    for attachment in &chat.attachments {
+        if attachment.is_image() {
+            html.push(&html!(
+                "
+                <a href=\"{}\" target=\"_blank\" rel=\"noopener noreferrer\" title=\"Open {}\">
+                    <img src=\"{}\" alt=\"{}\" loading=\"lazy\" class=\"chats__attachment\" />
+                </a>
+                ",
+                attachment.url,
+                attachment.file_name,
+                attachment.url,
+                attachment.file_name
+            ));
+        } else {
+            html.push(&html!(
+                "
+                <a href=\"{}\" download class=\"chats__file\" title=\"Download {}\">
+                    <small>{}</small>
+                    <small>{}</small>
+                </a>
+                ",
+                attachment.url,
+                attachment.file_name,
+                attachment.file_name,
+                upload::format_file_size(attachment.size as u64)
+            ));
+        }
+    }
+
+    html.push_markup("</div>");
+    html
+}
+
+/// The start of a quoted message, or a placeholder once it is deleted for everyone
+fn quote_snippet(quoted: &QuotedMessage) -> String {
+    if quoted.deleted {
+        return consts::DELETED_MESSAGE_PLACEHOLDER.to_string();
+    }
+
+    let message = markdown::preview(&quoted.message, quoted.is_plain);
+    let mut snippet: String = message.chars().take(consts::QUOTE_SNIPPET_LENGTH).collect();
+
+    if snippet.len() < message.len() {
+        snippet.push_str("...");
+    }
+
+    snippet
+}
+
+/// The message a message replies to, linking back to it
+fn quote(chat: &ChatMessage) -> Html {
+    match &chat.quoted {
+        Some(quoted) => html!(
+            "<a class=\"chats__quote {}\" href=\"#message_{}\" title=\"Go to the original message\"><small>{}</small></a>",
+            if quoted.deleted { "chats__deleted" } else { "" },
+            quoted.id,
+            quote_snippet(quoted)
+        ),
+        None => Html::new(),
+    }
+}
+
+/// A button that reacts to a message with `emoji`, or takes the reaction back
+fn reaction_button(message_id: i32, emoji: &str, label: &Html, title: &str, reacted: bool) -> Html {
+    html!(
+        "
+        <button
+            type=\"button\"
+            class=\"ghost chats__reaction\"
+            title=\"{}\"
+            aria-pressed=\"{}\"
+            hx-post=\"/chats/react\"
+            hx-vals=\"{}\"
+            hx-target=\"closest .chats__reactions\"
+            hx-swap=\"outerHTML\"
+        >{}</button>
+        ",
+        title,
+        reacted,
+        serde_json::json!({ "message_id": message_id, "emoji": emoji }).to_string(),
+        label
+    )
+}
+
+/// The reactions to a message as `user_id` sees them, and the emojis they can react with.
+/// Set `oob` when swapping it into the conversation from another response.
+pub fn reactions(message_id: i32, reactions: &[Reaction], user_id: i32, oob: bool) -> Html {
+    let mut html = html!(
+        "<div id=\"reactions_{}\" class=\"chats__reactions\" {}>",
+        message_id,
+        oob_attribute(oob)
+    );
+
+    for reaction in reactions {
+        html.push(&reaction_button(
+            message_id,
+            &reaction.emoji,
+            &html!("{} <small>{}</small>", reaction.emoji, reaction.count),
+            &format!("{} reacted with {}", reaction.count, reaction.emoji),
+            reaction.has_reacted(user_id)
+        ));
+    }
+
+    html.push_markup("<details class=\"chats__reaction_picker\"><summary title=\"React\"><small>React</small></summary>");
+
+    for emoji in consts::REACTION_EMOJIS {
+        let reacted = reactions.iter().any(|reaction| reaction.emoji == emoji && reaction.has_reacted(user_id));
+
+        html.push(&reaction_button(message_id, emoji, &Html::text(emoji), &format!("React with {}", emoji), reacted));
+    }
+
+    html.push_markup("</details></div>");
+    html
+}
+
+/// What the user can do with a message.
+/// Anyone can delete a message for themselves, but only its sender can edit it or delete it for everyone.
+fn message_actions(chat: &ChatMessage) -> Html {
+    let delete_for_me = html!(
+        "
+        <button
+            type=\"button\"
+            class=\"ghost chats__message_action\"
+            title=\"Delete for me\"
+            hx-post=\"/chats/delete\"
+            hx-vals='{{\"message_id\": {}, \"for_everyone\": false}}'
+            hx-target=\"closest li\"
+            hx-swap=\"outerHTML\"
+        >
+            <small>Delete for me</small>
+        </button>
+        ",
+        chat.id
+    );
+
+    if chat.deleted_at.is_some() {
+        return delete_for_me;
+    }
+
+    let reply = html!(
+        "
+        <button
+            type=\"button\"
+            class=\"ghost chats__message_action\"
+            title=\"Reply\"
+            hx-get=\"/chats/reply?message_id={}\"
+            hx-target=\"#chats__reply\"
+            hx-swap=\"outerHTML\"
+        >
+            <small>Reply</small>
+        </button>
+        ",
+        chat.id
+    );
+
+    if chat.is_receiver_message {
+        return html!("{}{}", reply, delete_for_me);
+    }
+
+    html!(
+        "
+        {}
+        <button
+            type=\"button\"
+            class=\"ghost chats__message_action\"
+            title=\"Edit message\"
+            hx-get=\"/chats/edit?message_id={}\"
+            hx-target=\"closest li\"
+            hx-swap=\"outerHTML\"
+        >
+            <small>Edit</small>
+        </button>
+        {}
+        <button
+            type=\"button\"
+            class=\"ghost chats__message_action\"
+            title=\"Delete for everyone\"
+            hx-post=\"/chats/delete\"
+            hx-vals='{{\"message_id\": {}, \"for_everyone\": true}}'
+            hx-confirm=\"Delete this message for everyone?\"
+            hx-target=\"closest li\"
+            hx-swap=\"outerHTML\"
+        >
+            <small>Delete for everyone</small>
+        </button>
+        ",
+        reply,
+        chat.id,
+        delete_for_me,
+        chat.id
+    )
+}
+
+/// Nothing is left to deliver of a message deleted for everyone
+fn own_message_status(chat: &ChatMessage) -> Html {
+    match chat.deleted_at {
+        Some(_) => Html::new(),
+        None => message_status(chat.id, chat.status, false),
+    }
+}
+
+/// Messages deleted for everyone cannot be reacted to
+fn message_reactions(chat: &ChatMessage) -> Html {
+    match chat.deleted_at {
+        Some(_) => Html::new(),
+        None => reactions(chat.id, &chat.reactions, chat.viewer_id, false),
+    }
+}
+
+/// A single message in a conversation.
+/// Set `oob` to replace a message that is already in the conversation.
+pub fn message(chat: &ChatMessage, display_name: &str, display_image: &str, oob: bool) -> Html {
+    if chat.is_system {
+        html!(
+            "<li id=\"message_{}\" class=\"chats__system_message\" {}><small>{}</small></li>",
+            chat.id,
+            oob_attribute(oob),
+            chat.message
+        )
+    } else if chat.is_receiver_message {
+        html!(
+            "
+            <li id=\"message_{}\" data-isreceiver=\"{}\" {}>
+                <div class=\"chats__message\">
+                    <div>
+                        <img
+                            src=\"{}\"
+                            alt=\"{}'s Profile picture\"
+                            width=\"28\"
+                            height=\"28\"
+                            loading=\"lazy\"
+                            class=\"profile\"
+                        />
+                        <div>
+                            <small>{}</small>
+                            {}
+                            {}
+                            {}
+                        </div>
+                    </div>
+                    <time>{}</time>
+                    {}
+                    {}
+                    {}
+                </div>
+            </li>
+            ",
+            chat.id,
+            chat.is_receiver_message,
+            oob_attribute(oob),
+            display_image,
+            display_name,
+            display_name,
+            quote(chat),
+            message_text(chat),
+            attachments(chat),
+            chat.created_at,
+            edited_marker(chat),
+            message_actions(chat),
+            message_reactions(chat)
+        )
+    } else {
+        html!(
+            "
+            <li id=\"message_{}\" data-isreceiver=\"{}\" {}>
+                <div class=\"chats__message\">
+                    <div>
+                        {}
+                        {}
+                        {}
+                    </div>
+                    <time>{}</time>
+                    {}
+                    {}
+                    {}
+                    {}
+                </div>
+            </li>
+            ",
+            chat.id,
+            chat.is_receiver_message,
+            oob_attribute(oob),
+            quote(chat),
+            message_text(chat),
+            attachments(chat),
+            chat.created_at,
+            edited_marker(chat),
+            own_message_status(chat),
+            message_actions(chat),
+            message_reactions(chat)
+        )
+    }
+}
+
+/// Ticks under a message the user sent: one once it is sent, two once delivered, highlighted once seen.
+/// Set `oob` to update the ticks of a message that is already in the conversation.
+pub fn message_status(message_id: i32, status: MessageStatus, oob: bool) -> Html {
+    let (ticks, title) = match status {
+        MessageStatus::Sent => (Html::trusted("&#10003;"), "Sent"),
+        MessageStatus::Delivered => (Html::trusted("&#10003;&#10003;"), "Delivered"),
+        MessageStatus::Seen => (Html::trusted("&#10003;&#10003;"), "Seen"),
+    };
+
+    html!(
+        "<small id=\"message_status_{}\" class=\"chats__status\" data-status=\"{}\" title=\"{}\" {}>{}</small>",
+        message_id,
+        title.to_lowercase(),
+        title,
+        oob_attribute(oob),
+        ticks
+    )
+}
+
+/// Appends a message to its conversation, if it is the one that is open
+pub fn message_in_conversation(conversation_id: i32, message: &Html) -> Html {
+    html!(
+        "<ul hx-swap-oob=\"beforeend:#chat_info_container[data-conversation-id='{}']\">{}</ul>",
+        conversation_id,
+        message
+    )
+}
+
+/// Lets the user know someone sent them a message, and opens its conversation when clicked
+pub fn notification(conversation_id: i32, url: &str, sender_name: &str, message: &str) -> Html {
+    html!(
+        "
+        <div id=\"chat_notification\" hx-swap-oob=\"innerHTML\">
+            <button
+                type=\"button\"
+                class=\"ghost\"
+                title=\"Open the chat\"
+                data-conversation-id=\"{}\"
+                hx-get=\"{}\"
+                hx-target=\"#chat_container\"
+                hx-sync=\"button[hx-target='#chat_container']:replace\"
+            >
+                <small>New message from {}</small>
+                <p>{}</p>
+            </button>
+        </div>
+        ",
+        conversation_id,
+        url,
+        sender_name,
+        message
+    )
+}
+
+/// Removes a message that is already in the conversation
+pub fn removed_message(message_id: i32) -> Html {
+    html!("<li id=\"message_{}\" hx-swap-oob=\"delete\"></li>", message_id)
+}
+
+/// The preview of the latest message of a conversation in the sidebar
+pub fn sidebar_preview(user_id: i32, other_user_id: i32, message: &str) -> Html {
+    html!("<p hx-swap-oob=\"true\" id=\"msg_{}{}\">{}</p>", user_id, other_user_id, message)
+}
+
+/// Takes the place of a message the user is editing
+pub fn message_editor(chat: &ChatMessage) -> Html {
+    html!(
+        "
+        <li id=\"message_{}\" data-isreceiver=\"false\">
+            <form
+                class=\"chats__message chats__message_editor\"
+                hx-post=\"/chats/edit\"
+                hx-target=\"closest li\"
+                hx-swap=\"outerHTML\"
+            >
+                <input name=\"message_id\" value=\"{}\" hidden>
+                <input name=\"message\" type=\"text\" value=\"{}\" required autofocus>
+                <button
+                    type=\"button\"
+                    class=\"ghost\"
+                    title=\"Cancel\"
+                    hx-get=\"/chats/message?message_id={}\"
+                    hx-target=\"closest li\"
+                    hx-swap=\"outerHTML\"
+                >
+                    <small>Cancel</small>
+                </button>
+                <button type=\"submit\" class=\"primary\" title=\"Save\"><small>Save</small></button>
+            </form>
+        </li>
+        ",
+        chat.id,
+        chat.id,
+        chat.message,
+        chat.id
+    )
+}
+
+/// How many messages from `other_user_id` that `user_id` has not read yet.
+/// Set `oob` when swapping it into the sidebar from another response.
+pub fn unread_badge(user_id: i32, other_user_id: i32, unread_count: i64, oob: bool) -> Html {
+    html!(
+        "<span id=\"unread_{}_{}\" class=\"chats__unread_badge\" {} {}>{}</span>",
+        user_id,
+        other_user_id,
+        oob_attribute(oob),
+        if unread_count == 0 { "hidden" } else { "" },
+        unread_count
+    )
+}
+
+/// Whether the user someone is chatting with is online, shown in the header of the conversation.
+/// Set `oob` when swapping it into the conversation from another response.
+pub fn presence(user_id: i32, text: &str, oob: bool) -> Html {
+    html!(
+        "<small id=\"presence_{}\" class=\"chats__presence\" data-online=\"{}\" {}>{}</small>",
+        user_id,
+        text == "online",
+        oob_attribute(oob),
+        text
+    )
+}
+
+/// Replaces itself with what `url` returns once the user scrolls up to it
+fn loader(url: &str, before: &MessageCursor) -> Html {
+    html!(
+        "
+        <li
+            class=\"chats__older_messages\"
+            hx-get=\"{}&before={}\"
+            hx-trigger=\"intersect once\"
+            hx-target=\"this\"
+            hx-swap=\"outerHTML\"
+        >
+            <small>Loading older messages...</small>
+        </li>
+        ",
+        url,
+        before.to_param()
+    )
+}
+
+/// Replaces itself with the page of messages before `before`
+/// once the user scrolls up to it
+pub fn older_messages_loader(sender_id: i32, receiver_id: i32, before: &MessageCursor) -> Html {
+    loader(&format!("/chats/messages?sender_id={}&receiver_id={}", sender_id, receiver_id), before)
+}
+
+/// Replaces itself with the page of group messages before `before`
+/// once the user scrolls up to it
+pub fn older_group_messages_loader(conversation_id: i32, before: &MessageCursor) -> Html {
+    loader(&format!("/chats/groups/messages?conversation_id={}", conversation_id), before)
+}
+
+/// Replaces itself with what `url` returns once the user scrolls down to it
+fn newer_loader(url: &str, newer: &NewerMessages) -> Html {
+    html!(
+        "
+        <li
+            class=\"chats__newer_messages\"
+            hx-get=\"{}&after={}&until={}\"
+            hx-trigger=\"intersect once\"
+            hx-target=\"this\"
+            hx-swap=\"outerHTML\"
+        >
+            <small>Loading newer messages...</small>
+        </li>
+        ",
+        url,
+        newer.after.to_param(),
+        newer.until
+    )
+}
+
+/// Replaces itself with the page of messages after `newer.after`
+/// once the user scrolls down to it
+pub fn newer_messages_loader(sender_id: i32, receiver_id: i32, newer: &NewerMessages) -> Html {
+    newer_loader(&format!("/chats/messages?sender_id={}&receiver_id={}", sender_id, receiver_id), newer)
+}
+
+/// Replaces itself with the page of group messages after `newer.after`
+/// once the user scrolls down to it
+pub fn newer_group_messages_loader(conversation_id: i32, newer: &NewerMessages) -> Html {
+    newer_loader(&format!("/chats/groups/messages?conversation_id={}", conversation_id), newer)
+}
+
+/// A page of messages, between the loaders of the pages before and after it
+pub fn messages(user_chats: &MessagesInChat) -> Html {
+    let mut html = Html::new();
+
+    if let Some(before) = &user_chats.before {
+        html.push(&older_messages_loader(user_chats.sender_id, user_chats.receiver_id, before));
+    }
+
+    for chat in &user_chats.messages {
+        let (display_name, display_image) = if chat.is_receiver_message {
+            (&user_chats.receiver_name, &user_chats.receiver_avatar)
+        } else {
+            (&user_chats.sender_name, &user_chats.sender_avatar)
+        };
+
+        html.push(&message(chat, display_name, display_image, false));
+    }
+
+    if let Some(newer) = &user_chats.newer {
+        html.push(&newer_messages_loader(user_chats.sender_id, user_chats.receiver_id, newer));
+    }
+
+    html
+}
+
+/// The input messages are typed in. Lets the other participant know
+/// the user is typing, at most once every couple of seconds.
+/// It can be left empty when sending files.
+pub fn message_input() -> Html {
+    Html::trusted("
+    <textarea
+        id=\"message_input\"
+        name=\"message\"
+        rows=\"1\"
+        placeholder=\"Type a message...\"
+        hx-post=\"/chats/typing\"
+        hx-trigger=\"input changed throttle:2s\"
+        hx-swap=\"none\"
+    ></textarea>
+    ")
+}
+
+/// Sends the next messages as they are typed instead of as markdown
+pub fn plain_input() -> Html {
+    Html::trusted("
+    <label class=\"chats__plain_input\" title=\"Send messages as they are, without formatting\">
+        <input name=\"plain\" type=\"checkbox\" value=\"true\">
+        <small>Plain</small>
+    </label>
+    ")
+}
+
+/// A new id for the next message sent with the form, so sending it again after
+/// a failed request does not save it twice. Set `oob` to replace it from another response.
+pub fn client_id_input(oob: bool) -> Html {
+    html!(
+        "<input id=\"client_id_input\" name=\"client_id\" value=\"{}\" hidden {}>",
+        random_string::generate(32, random_string::charsets::ALPHANUMERIC),
+        oob_attribute(oob)
+    )
+}
+
+/// Picks files to attach to the next message.
+/// Set `oob` to clear it from another response.
+pub fn attachment_input(oob: bool) -> Html {
+    html!(
+        "<input id=\"attachment_input\" name=\"attachments\" type=\"file\" title=\"Attach files\" multiple {}>",
+        oob_attribute(oob)
+    )
+}
+
+/// Shows which message the user is replying to above the input, and sends its id along.
+/// Empty when the user is not replying to anything.
+pub fn reply_preview(quoted: Option<&QuotedMessage>, oob: bool) -> Html {
+    let oob = oob_attribute(oob);
+
+    match quoted {
+        Some(quoted) => html!(
+            "
+            <div id=\"chats__reply\" class=\"chats__reply\" {}>
+                <input name=\"reply_to\" value=\"{}\" hidden>
+                <small>Replying to: {}</small>
+                <button type=\"button\" class=\"ghost\" title=\"Cancel reply\" onclick=\"this.parentElement.replaceChildren()\">
+                    <small>Cancel</small>
+                </button>
+            </div>
+            ",
+            oob,
+            quoted.id,
+            quote_snippet(quoted)
+        ),
+        None => html!("<div id=\"chats__reply\" class=\"chats__reply\" {}></div>", oob),
+    }
+}
+
+/// Lets members choose how long new messages of a conversation last before they disappear.
+/// `seconds` is the current setting, `None` if messages do not disappear.
+/// Set `oob` to replace it from another response.
+pub fn disappearing_setting(conversation_id: i32, seconds: Option<i32>, oob: bool) -> Html {
+    let (amount, unit) = seconds.map(disappearing::split_duration).unwrap_or((0, "hour"));
+    let mut units = Html::new();
+
+    for option in ["minute", "hour", "day"] {
+        units.push(&html!(
+            "<option value=\"{}s\" {}>{}s</option>",
+            option,
+            if option == unit { "selected" } else { "" },
+            option
+        ));
+    }
+
+    html!(
+        "
+        <details id=\"disappearing_{}\" class=\"chats__disappearing\" {}>
+            <summary title=\"Disappearing messages\"><small>Disappearing: {}</small></summary>
+            <form
+                hx-post=\"/chats/disappearing\"
+                hx-target=\"closest details\"
+                hx-swap=\"outerHTML\"
+            >
+                <input name=\"conversation_id\" value=\"{}\" hidden>
+                <input name=\"amount\" type=\"number\" min=\"0\" value=\"{}\" title=\"0 turns it off\" required>
+                <select name=\"unit\" title=\"Unit\">{}</select>
+                <button type=\"submit\" class=\"primary\" title=\"Save\"><small>Save</small></button>
+            </form>
+        </details>
+        ",
+        conversation_id,
+        oob_attribute(oob),
+        seconds.map(disappearing::format_duration).unwrap_or_else(|| "off".to_string()),
+        conversation_id,
+        amount,
+        units
+    )
+}
+
+/// Picks when to send the message in the form instead of sending it now.
+/// Set `oob` to clear it from another response.
+pub fn schedule_input(oob: bool) -> Html {
+    html!(
+        "
+        <details id=\"chats__schedule\" class=\"chats__schedule\" {}>
+            <summary title=\"Send later\"><small>Later</small></summary>
+            <input name=\"send_at\" type=\"datetime-local\" title=\"When to send the message\">
+            <button
+                type=\"button\"
+                class=\"ghost\"
+                title=\"Schedule message\"
+                hx-post=\"/chats/scheduled\"
+                hx-encoding=\"application/x-www-form-urlencoded\"
+                hx-target=\"#message_input\"
+                hx-swap=\"outerHTML\"
+            >
+                <small>Schedule</small>
+            </button>
+        </details>
+        ",
+        oob_attribute(oob)
+    )
+}
+
+/// The value of a `datetime-local` input, in the user's `timezone`
+fn datetime_local_value(at: &OffsetDateTime, timezone: UtcOffset) -> String {
+    let at = at.to_offset(timezone);
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}", at.year(), u8::from(at.month()), at.day(), at.hour(), at.minute())
+}
+
+/// A message the user scheduled, with buttons to edit or cancel it.
+/// When it is sent is shown in the user's `timezone`, unless it could not be sent.
+pub fn scheduled_message(scheduled: &ScheduledMessage, timezone: UtcOffset) -> Html {
+    let when = match scheduled.failed_at {
+        Some(_) => "Could not be sent. Edit it to try again.".to_string(),
+        None => format!("Sends {}", format_created_at(&scheduled.send_at.to_offset(timezone))),
+    };
+
+    html!(
+        "
+        <li id=\"scheduled_{}\" class=\"chats__scheduled_message\">
+            <small>{}</small>
+            <p>{}</p>
+            <button
+                type=\"button\"
+                class=\"ghost\"
+                title=\"Edit scheduled message\"
+                hx-get=\"/chats/scheduled/edit?id={}\"
+                hx-target=\"closest li\"
+                hx-swap=\"outerHTML\"
+            >
+                <small>Edit</small>
+            </button>
+            <button
+                type=\"button\"
+                class=\"ghost\"
+                title=\"Cancel scheduled message\"
+                hx-post=\"/chats/scheduled/cancel\"
+                hx-vals='{{\"id\": {}}}'
+                hx-target=\"closest li\"
+                hx-swap=\"outerHTML\"
+            >
+                <small>Cancel</small>
+            </button>
+        </li>
+        ",
+        scheduled.id,
+        when,
+        markdown::preview(&scheduled.message, scheduled.is_plain).as_ref(),
+        scheduled.id,
+        scheduled.id
+    )
+}
+
+/// Takes the place of a scheduled message the user is editing
+pub fn scheduled_message_editor(scheduled: &ScheduledMessage, timezone: UtcOffset) -> Html {
+    html!(
+        "
+        <li id=\"scheduled_{}\" class=\"chats__scheduled_message\">
+            <form
+                class=\"chats__message_editor\"
+                hx-post=\"/chats/scheduled/edit\"
+                hx-target=\"closest li\"
+                hx-swap=\"outerHTML\"
+            >
+                <input name=\"id\" value=\"{}\" hidden>
+                <input name=\"send_at\" type=\"datetime-local\" value=\"{}\" required>
+                <input name=\"message\" type=\"text\" value=\"{}\" required autofocus>
+                <button
+                    type=\"button\"
+                    class=\"ghost\"
+                    title=\"Cancel\"
+                    hx-get=\"/chats/scheduled?receiver_id={}\"
+                    hx-target=\"#scheduled_messages\"
+                    hx-swap=\"outerHTML\"
+                >
+                    <small>Cancel</small>
+                </button>
+                <button type=\"submit\" class=\"primary\" title=\"Save\"><small>Save</small></button>
+            </form>
+        </li>
+        ",
+        scheduled.id,
+        scheduled.id,
+        datetime_local_value(&scheduled.send_at, timezone),
+        scheduled.message,
+        scheduled.receiver_id
+    )
+}
+
+/// The messages the user scheduled in a conversation, the next one first.
+/// Set `oob` to replace them from another response.
+pub fn scheduled_messages(scheduled: &[ScheduledMessage], timezone: UtcOffset, oob: bool) -> Html {
+    html!(
+        "<ul id=\"scheduled_messages\" class=\"chats__scheduled\" {}>{}</ul>",
+        oob_attribute(oob),
+        scheduled.iter().map(|scheduled| scheduled_message(scheduled, timezone)).collect::<Html>()
+    )
+}
+
+/// Loads the messages the user scheduled in the conversation with `receiver_id`.
+/// Their times are shown in the user's timezone, which only the browser knows, see `chats.js`.
+pub fn scheduled_messages_loader(receiver_id: i32) -> Html {
+    html!(
+        "<ul id=\"scheduled_messages\" class=\"chats__scheduled\" hx-get=\"/chats/scheduled?receiver_id={}\" hx-trigger=\"load\" hx-target=\"this\" hx-swap=\"outerHTML\"></ul>",
+        receiver_id
+    )
+}
+
+/// Removes a scheduled message from the list once it was sent
+pub fn removed_scheduled_message(id: i32) -> Html {
+    html!("<li id=\"scheduled_{}\" hx-swap-oob=\"delete\"></li>", id)
+}
+
+/// The conversation with its header, latest messages, and the form to send a message.
+/// `presence_text` is whether the other user is online, see [`crate::chats::presence::Presence::describe`].
+pub fn chat(user_chats: &MessagesInChat, user_id: i32, presence_text: &str) -> Html {
+    let receiver_name = if user_chats.receiver_id == user_id {
+        &user_chats.sender_name
+    } else {
+        &user_chats.receiver_name
+    };
+    let receiver_avatar = if user_chats.receiver_id == user_id {
+        &user_chats.sender_avatar
+    } else {
+        &user_chats.receiver_avatar
+    };
+    let other_user_id = if user_chats.receiver_id == user_id {
+        user_chats.sender_id
+    } else {
+        user_chats.receiver_id
+    };
+
+    html!(
+        "
+        <div class=\"chats__container\" hx-swap=\"beforeend scroll:bottom\" hx-target=\"#chat_info_container\" sse-swap=\"message\" sse-connect=\"/events/chats?{}\">
+            <nav class=\"chats__header\">
+                <div>
+                    <img
+                        src=\"{}\"
+                        alt=\"{}'s Profile picture\"
+                        width=\"40\"
+                        height=\"40\"
+                        loading=\"lazy\"
+                        class=\"profile\"
+                    />
+                    <div class=\"chats__header_name\">
+                        <span>{}</span>
+                        {}
+                    </div>
+                </div>
+                {}
+            </nav>
+            <ul id=\"chat_info_container\" data-conversation-id=\"{}\">
+                {}
+            </ul>
+            <small id=\"chat_typing\" class=\"chats__typing\" sse-swap=\"typing\" hx-target=\"this\" hx-swap=\"innerHTML\"></small>
+            <small id=\"chat_seen\" class=\"chats__seen\" sse-swap=\"seen\" hx-target=\"this\" hx-swap=\"innerHTML\"></small>
+            <div hidden sse-swap=\"edited,deleted,reacted,status,expired\" hx-swap=\"none\"></div>
+            {}
+            <div>
+                <form
+                    id=\"chats__form\"
+                    hx-post=\"/chats/send\"
+                    hx-encoding=\"multipart/form-data\"
+                    hx-trigger=\"submit\"
+                    hx-swap=\"outerHTML\"
+                    hx-target=\"#message_input\"
+                >
+                    <input name=\"receiver_id\" value=\"{}\" hidden>
+                    <input name=\"sender_id\" value=\"{}\" hidden>
+                    {}
+                    {}
+                    {}
+                    {}
+                    {}
+                    {}
+                    <button data-loading-disable data-loading-aria-busy data-loading-delay=\"100\" class=\"primary loading__on_submit\" type=\"submit\" title=\"Send Message\">Send</button>
+                </form>
+            </div>
+        </div>
+        ",
+        user_chats.id,
+        receiver_avatar,
+        receiver_name,
+        receiver_name,
+        presence(other_user_id, presence_text, false),
+        // There is nothing to set until the conversation is started.
+        user_chats.conversation_id.map(|id| disappearing_setting(id, user_chats.disappear_after_seconds, false)).unwrap_or_default(),
+        user_chats.conversation_id.map(|id| id.to_string()).unwrap_or_default(),
+        messages(user_chats),
+        scheduled_messages_loader(other_user_id),
+        user_chats.receiver_id,
+        user_chats.sender_id,
+        reply_preview(None, false),
+        client_id_input(false),
+        message_input(),
+        attachment_input(false),
+        plain_input(),
+        schedule_input(false)
+    )
+}
+
+/// The input group messages are typed in
+pub fn group_message_input() -> Html {
+    Html::trusted("
+    <textarea
+        id=\"message_input\"
+        name=\"message\"
+        rows=\"1\"
+        placeholder=\"Type a message...\"
+        required
+    ></textarea>
+    ")
+}
+
+/// The preview of the latest message of a group in the sidebar.
+/// Set `oob` when swapping it into the sidebar from another response.
+pub fn group_preview(conversation_id: i32, message: &str, oob: bool) -> Html {
+    html!(
+        "<p id=\"group_msg_{}\" {}>{}</p>",
+        conversation_id,
+        oob_attribute(oob),
+        message
+    )
+}
+
+/// Every group of the user in the sidebar.
+/// Set `oob` when swapping it into the sidebar from another response.
+pub fn group_list(groups: &[GroupPreview], oob: bool) -> Html {
+    let mut html = html!(
+        "<ul id=\"user_groups\" {}>",
+        oob_attribute(oob)
+    );
+
+    if groups.is_empty() {
+        html.push_markup("<li><p>No groups yet.</p></li>");
+    }
+
+    for group in groups {
+        let message = match &group.message {
+            Some(_) if group.is_deleted => consts::DELETED_MESSAGE_PLACEHOLDER,
+            Some(message) => message,
+            None => "",
+        };
+
+        html.push(&html!(
+            "
+            <li id=\"group_{}\">
+                <button
+                    type=\"button\"
+                    title=\"Open {}\"
+                    hx-get=\"/chats/groups?conversation_id={}\"
+                    hx-trigger=\"click\"
+                    hx-target=\"#chat_container\"
+                    hx-sync=\"button[hx-target='#chat_container']:replace\"
+                    class=\"ghost\"
+                >
+                    <div>
+                        <span>{}</span>
+                        {}
+                    </div>
+                </button>
+                <hr />
+            </li>
+            ",
+            group.id,
+            group.name,
+            group.id,
+            group.name,
+            group_preview(group.id, message, false)
+        ));
+    }
+
+    html.push_markup("</ul>");
+    html
+}
+
+/// Who is in a group, and the form to add someone.
+/// The creator can remove anyone, everyone else can only leave.
+/// Set `oob` to replace the list that is already in the group.
+pub fn group_members(conversation: &Conversation, user_id: i32, oob: bool) -> Html {
+    let mut html = html!(
+        "
+        <details id=\"group_members_{}\" class=\"chats__group_members\" {}>
+            <summary><small>{} members</small></summary>
+            <ul>
+        ",
+        conversation.id,
+        oob_attribute(oob),
+        conversation.members.len()
+    );
+
+    for member in &conversation.members {
+        let action = if member.id == user_id {
+            html!(
+                "
+                <button
+                    type=\"button\"
+                    class=\"ghost chats__message_action\"
+                    title=\"Leave group\"
+                    hx-post=\"/chats/groups/members/remove\"
+                    hx-vals='{{\"conversation_id\": {}, \"user_id\": {}}}'
+                    hx-confirm=\"Leave this group?\"
+                    hx-target=\"#chat_container\"
+                >
+                    <small>Leave</small>
+                </button>
+                ",
+                conversation.id,
+                member.id
+            )
+        } else if conversation.created_by == Some(user_id) {
+            html!(
+                "
+                <button
+                    type=\"button\"
+                    class=\"ghost chats__message_action\"
+                    title=\"Remove {}\"
+                    hx-post=\"/chats/groups/members/remove\"
+                    hx-vals='{{\"conversation_id\": {}, \"user_id\": {}}}'
+                    hx-target=\"#group_members_{}\"
+                    hx-swap=\"outerHTML\"
+                >
+                    <small>Remove</small>
+                </button>
+                ",
+                member.display_name,
+                conversation.id,
+                member.id,
+                conversation.id
+            )
+        } else {
+            Html::new()
+        };
+
+        html.push(&html!(
+            "
+            <li>
+                <img
+                    src=\"{}\"
+                    alt=\"{}'s Profile picture\"
+                    width=\"24\"
+                    height=\"24\"
+                    loading=\"lazy\"
+                    class=\"profile\"
+                />
+                <small>{}</small>
+                {}
+            </li>
+            ",
+            member.display_image,
+            member.display_name,
+            member.display_name,
+            action
+        ));
+    }
+
+    html.push(&html!(
+        "
+            </ul>
+            <form
+                hx-post=\"/chats/groups/members\"
+                hx-target=\"#group_members_{}\"
+                hx-swap=\"outerHTML\"
+            >
+                <input name=\"conversation_id\" value=\"{}\" hidden>
+                <input name=\"display_name\" type=\"text\" placeholder=\"Add someone by name...\" required>
+                <button type=\"submit\" class=\"primary\" title=\"Add member\"><small>Add</small></button>
+            </form>
+        </details>
+        ",
+        conversation.id,
+        conversation.id
+    ));
+
+    html
+}
+
+/// A page of group messages, between the loaders of the pages before and after it
+pub fn group_messages(group: &MessagesInGroup) -> Html {
+    let mut html = Html::new();
+
+    if let Some(before) = &group.before {
+        html.push(&older_group_messages_loader(group.conversation.id, before));
+    }
+
+    for chat in &group.messages {
+        let sender = group.senders.get(&chat.sender_id).cloned().unwrap_or_default();
+
+        html.push(&message(chat, &sender.display_name, &sender.display_image, false));
+    }
+
+    if let Some(newer) = &group.newer {
+        html.push(&newer_group_messages_loader(group.conversation.id, newer));
+    }
+
+    html
+}
+
+/// A group with its members, latest messages, and the form to send a message
+pub fn group_chat(group: &MessagesInGroup, user_id: i32) -> Html {
+    let name = group.conversation.name.clone().unwrap_or_default();
+
+    html!(
+        "
+        <div class=\"chats__container\" hx-swap=\"beforeend scroll:bottom\" hx-target=\"#chat_info_container\" sse-swap=\"message\" sse-connect=\"/events/groups?conversation_id={}\">
+            <nav class=\"chats__header\">
+                <div>
+                    <span>{}</span>
+                </div>
+                {}
+            </nav>
+            <ul id=\"chat_info_container\" data-conversation-id=\"{}\">
+                {}
+            </ul>
+            <div hidden sse-swap=\"edited,deleted,reacted,status,members,expired\" hx-swap=\"none\"></div>
+            <div>
+                <form
+                    id=\"chats__form\"
+                    hx-post=\"/chats/groups/send\"
+                    hx-trigger=\"submit\"
+                    hx-swap=\"outerHTML\"
+                    hx-target=\"#message_input\"
+                >
+                    <input name=\"conversation_id\" value=\"{}\" hidden>
+                    {}
+                    {}
+                    {}
+                    {}
+                    <button data-loading-disable data-loading-aria-busy data-loading-delay=\"100\" class=\"primary loading__on_submit\" type=\"submit\" title=\"Send Message\">Send</button>
+                </form>
+            </div>
+        </div>
+        ",
+        group.conversation.id,
+        name,
+        group_members(&group.conversation, user_id, false),
+        group.conversation.id,
+        group_messages(group),
+        group.conversation.id,
+        reply_preview(None, false),
+        client_id_input(false),
+        group_message_input(),
+        plain_input()
+    )
+}
+
+/// Scrolls to a message once its conversation is shown, see `chats.js`
+pub fn focus_message(message_id: i32) -> Html {
+    html!("<div hidden data-focus-message=\"message_{}\"></div>", message_id)
+}
+
+/// Messages that matched a search, each opening its conversation at the message,
+/// followed by a button that loads the next page.
+pub fn message_search_results(search: &MessageSearchResults, user_id: i32) -> Html {
+    let mut html = Html::new();
+
+    if search.results.is_empty() && search.next_page.is_none() {
+        return Html::trusted("<li><p>No messages found.</p></li>");
+    }
+
+    for result in &search.results {
+        let url = match result.other_user_id {
+            Some(other_user_id) => format!(
+                "/chats?sender_id={}&receiver_id={}&is_htmx=true&message_id={}",
+                user_id,
+                other_user_id,
+                result.id
+            ),
+            None => format!("/chats/groups?conversation_id={}&message_id={}", result.conversation_id, result.id),
+        };
+
+        html.push(&html!(
+            "
+            <li>
+                <button
+                    type=\"button\"
+                    class=\"ghost chats__search_result\"
+                    title=\"Go to the message in {}\"
+                    hx-get=\"{}\"
+                    hx-target=\"#chat_container\"
+                    hx-sync=\"button[hx-target='#chat_container']:replace\"
+                >
+                    <small>{} &middot; {}</small>
+                    <p>{}</p>
+                    <time>{}</time>
+                </button>
+            </li>
+            ",
+            result.conversation_name,
+            url,
+            result.conversation_name,
+            result.sender_name,
+            highlighted_snippet(&result.snippet),
+            result.created_at
+        ));
+    }
+
+    if let Some(next_page) = search.next_page {
+        html.push(&html!(
+            "
+            <li>
+                <button
+                    type=\"button\"
+                    class=\"ghost\"
+                    title=\"More results\"
+                    hx-get=\"/chats/search?page={}\"
+                    hx-include=\"#message_search\"
+                    hx-target=\"closest li\"
+                    hx-swap=\"outerHTML\"
+                >
+                    <small>More results</small>
+                </button>
+            </li>
+            ",
+            next_page
+        ));
+    }
+
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chats::search::MessageSearchResult;
+
+    const SCRIPT: &str = "<script>alert(document.cookie)</script>";
+
+    fn chat_message(message: &str) -> ChatMessage {
+        ChatMessage {
+            id: 1,
+            is_receiver_message: true,
+            message: message.to_string(),
+            sender_id: 2,
+            created_at: "2024-02-28 10:00:00".to_string(),
+            receiver_id: Some(1),
+            viewer_id: 1,
+            edited_at: None,
+            deleted_at: None,
+            quoted: None,
+            reactions: Vec::new(),
+            attachments: Vec::new(),
+            status: MessageStatus::Sent,
+            is_plain: false,
+            is_system: false,
+        }
+    }
+
+    #[test]
+    fn message_text_is_escaped() {
+        let html = message(&chat_message(SCRIPT), "bob", "/images/bob.png", false);
+
+        assert!(!html.as_str().contains("<script>"));
+        assert!(html.as_str().contains("&lt;script&gt;alert(document.cookie)&lt;/script&gt;"));
+    }
+
+    #[test]
+    fn display_name_cannot_leave_its_attribute() {
+        let html = message(&chat_message("hi"), "\" onmouseover=\"alert(1)", "\"><img src=x onerror=alert(1)>", false);
+
+        assert!(!html.as_str().contains("\" onmouseover=\"alert(1)"));
+        assert!(!html.as_str().contains("<img src=x"));
+    }
+
+    #[test]
+    fn previews_and_notifications_are_escaped() {
+        assert!(!sidebar_preview(1, 2, SCRIPT).as_str().contains("<script>"));
+        assert!(!group_preview(1, SCRIPT, true).as_str().contains("<script>"));
+        assert!(!notification(1, "/chats", SCRIPT, SCRIPT).as_str().contains("<script>"));
+    }
+
+    #[test]
+    fn search_snippet_only_marks_matches() {
+        let search = MessageSearchResults {
+            results: vec![MessageSearchResult {
+                id: 1,
+                conversation_id: 1,
+                is_group: false,
+                conversation_name: SCRIPT.to_string(),
+                other_user_id: Some(2),
+                sender_name: "bob".to_string(),
+                snippet: format!("<b>{}hello{}</b> <mark>there</mark>", search::MATCH_START, search::MATCH_END),
+                created_at: "2024-02-28 10:00:00".to_string(),
+            }],
+            next_page: None,
+        };
+        let html = message_search_results(&search, 1);
+
+        assert!(html.as_str().contains("&lt;b&gt;<mark>hello</mark>&lt;/b&gt; &lt;mark&gt;there&lt;/mark&gt;"));
+        assert!(!html.as_str().contains("<script>"));
+    }
+
+    #[test]
+    fn reaction_emoji_cannot_leave_its_values() {
+        let reaction = Reaction {
+            emoji: "\"}' hx-post=\"/chats/delete".to_string(),
+            count: 1,
+            user_ids: vec![2],
+        };
+        let html = reactions(1, &[reaction], 1, false);
+
+        assert!(!html.as_str().contains("hx-post=\"/chats/delete"));
+        assert!(html.as_str().contains("&quot;emoji&quot;:&quot;\\&quot;}&#x27; hx-post=\\&quot;/chats/delete&quot;"));
+    }
+
+    #[test]
+    fn scheduled_messages_are_shown_in_the_users_timezone() {
+        let scheduled = ScheduledMessage {
+            id: 1,
+            owner_id: 1,
+            receiver_id: 2,
+            message: SCRIPT.to_string(),
+            is_plain: true,
+            send_at: OffsetDateTime::from_unix_timestamp(1_709_200_800).unwrap(),
+            failed_at: None,
+        };
+        let timezone = UtcOffset::from_hms(8, 0, 0).unwrap();
+
+        assert!(scheduled_message(&scheduled, timezone).as_str().contains("Sends 2024-February-29 at 18:0:0"));
+        assert!(!scheduled_message(&scheduled, timezone).as_str().contains("<script>"));
+        assert!(scheduled_message_editor(&scheduled, timezone).as_str().contains("value=\"2024-02-29T18:00\""));
+        assert!(!scheduled_message_editor(&scheduled, timezone).as_str().contains("<script>"));
+    }
+
+    #[test]
+    fn system_messages_are_escaped_notices() {
+        let mut msg = chat_message(&disappearing::setting_changed_message(SCRIPT, Some(3600)));
+
+        msg.is_system = true;
+
+        let html = message(&msg, "bob", "/images/bob.png", false);
+
+        assert!(html.as_str().contains("class=\"chats__system_message\""));
+        assert!(html.as_str().contains("New messages disappear 1 hour after they are seen."));
+        assert!(!html.as_str().contains("<script>"));
+    }
+
+    #[test]
+    fn disappearing_setting_shows_the_timer() {
+        let html = disappearing_setting(3, Some(90 * 60), false);
+
+        assert!(html.as_str().contains("Disappearing: 90 minutes"));
+        assert!(html.as_str().contains("value=\"90\""));
+        assert!(html.as_str().contains("<option value=\"minutes\" selected>"));
+        assert!(disappearing_setting(3, None, false).as_str().contains("Disappearing: off"));
+    }
+}
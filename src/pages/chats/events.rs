@@ -0,0 +1,788 @@
+//! Server-sent events that keep open conversations and the sidebar up to date.
+//!
+//! Changes to messages already on the page are rendered by [`message_change`] for 1:1 conversations
+//! and groups alike, and by [`preview_change`] for the sidebar, so the streams show them the same way.
+use std::{collections::HashMap, future::pending};
+
+use rocket::{get, http::Status, response::{status, stream::{Event, EventStream}}, tokio::{select, sync::broadcast::error::RecvError, time::{sleep_until, Duration, Instant}}, FromForm, Shutdown, State};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{chats::{chat_struct::{Chat, ChatRoomMessage, MessageStatus}, conversation::{Conversation, ConversationMember, GroupPreview}, event::{ChatEvent, LastEventId}, presence::Presence, pubsub::PubSub, scheduled::ScheduledMessage, topics::{Subscriptions, Topic}}, consts, db::Db, html, pages::chats::components, user::user_struct::User, utils::{get_placeholder_display_image, html::Html}};
+
+/// The display name and image of whoever sent a message in a conversation between `user` and `receiver`.
+fn sender_profile(sender_id: i32, user: &User, receiver: &User) -> (String, String) {
+    let sender = if sender_id == user.id { user } else { receiver };
+
+    (
+        sender.display_name.clone(),
+        get_placeholder_display_image(sender.display_image.as_ref(), &sender.gender)
+    )
+}
+
+/// Points the sidebar preview of a conversation at its latest message the user has not deleted for themselves.
+async fn latest_visible_preview(pool: &PgPool, user_id: i32, other_user_id: i32) -> Html {
+    let mut conn = match pool.acquire().await {
+        Ok(conn) => conn,
+        Err(err) => {
+            println!("Error: {:?}", err);
+            return Html::new();
+        }
+    };
+
+    match Chat::get_latest_visible_message(&mut conn, &user_id, &other_user_id).await {
+        Ok(Some(latest)) if latest.deleted_at.is_some() => {
+            components::sidebar_preview(user_id, other_user_id, consts::DELETED_MESSAGE_PLACEHOLDER)
+        },
+        Ok(Some(latest)) => components::sidebar_preview(user_id, other_user_id, &latest.preview()),
+        Ok(None) => components::sidebar_preview(user_id, other_user_id, ""),
+        Err(err) => {
+            println!("Error: {:?}", err);
+            Html::new()
+        }
+    }
+}
+
+/// Points the sidebar preview of a group at its latest message the user has not deleted for themselves.
+async fn latest_visible_group_preview(pool: &PgPool, user_id: i32, conversation_id: i32) -> Html {
+    let groups = match pool.acquire().await {
+        Ok(mut conn) => Conversation::get_groups_of_user(&mut conn, &user_id).await,
+        Err(err) => Err(err),
+    };
+
+    match groups {
+        Ok(groups) => match groups.into_iter().find(|group| group.id == conversation_id) {
+            Some(group) if group.is_deleted => {
+                components::group_preview(conversation_id, consts::DELETED_MESSAGE_PLACEHOLDER, true)
+            },
+            Some(group) => components::group_preview(conversation_id, &group.message.unwrap_or_default(), true),
+            None => Html::new(),
+        },
+        Err(err) => {
+            println!("Error: {:?}", err);
+            Html::new()
+        }
+    }
+}
+
+/// Points the sidebar preview of any conversation at its latest message the user can see.
+/// `other_user_id` is `None` for groups.
+async fn latest_visible_preview_of(pool: &PgPool, user_id: i32, conversation_id: i32, other_user_id: Option<i32>) -> Html {
+    match other_user_id {
+        Some(other_user_id) => latest_visible_preview(pool, user_id, other_user_id).await,
+        None => latest_visible_group_preview(pool, user_id, conversation_id).await,
+    }
+}
+
+/// The unread badge of a 1:1 conversation in the sidebar.
+async fn unread_badge(pool: &PgPool, user_id: i32, other_user_id: i32) -> Html {
+    let unread_count = match pool.acquire().await {
+        Ok(mut conn) => Chat::get_unread_count(&mut conn, &user_id, &other_user_id).await,
+        Err(err) => Err(err),
+    };
+
+    match unread_count {
+        Ok(unread_count) => components::unread_badge(user_id, other_user_id, unread_count, true),
+        Err(err) => {
+            println!("Error: {:?}", err);
+            Html::new()
+        }
+    }
+}
+
+/// The display name and image of whoever sent a message,
+/// looking them up the first time they send one.
+async fn message_sender(pool: &PgPool, senders: &mut HashMap<i32, ConversationMember>, sender_id: i32) -> ConversationMember {
+    if let Some(sender) = senders.get(&sender_id) {
+        return sender.clone();
+    }
+
+    let found = match pool.acquire().await {
+        Ok(mut conn) => Conversation::get_senders(&mut conn, &[sender_id]).await,
+        Err(err) => Err(err),
+    };
+
+    match found {
+        Ok(found) => senders.extend(found),
+        Err(err) => println!("Error: {:?}", err),
+    }
+
+    senders.get(&sender_id).cloned().unwrap_or_default()
+}
+
+/// Messages of a conversation the user missed since `after_id`, a page at a time.
+async fn missed_messages(pool: &PgPool, user_id: i32, conversation_id: i32, after_id: i32) -> Vec<ChatRoomMessage> {
+    let missed = match pool.acquire().await {
+        Ok(mut conn) => ChatRoomMessage::get_after(&mut conn, &user_id, &conversation_id, &after_id, consts::MESSAGES_PER_PAGE).await,
+        Err(err) => Err(err),
+    };
+
+    missed.unwrap_or_else(|err| {
+        println!("Error: {:?}", err);
+        Vec::new()
+    })
+}
+
+/// The 1:1 conversation between two users, if they ever sent each other a message.
+async fn direct_conversation_id(pool: &PgPool, first_user_id: i32, second_user_id: i32) -> Option<i32> {
+    let conversation_id = match pool.acquire().await {
+        Ok(mut conn) => Conversation::get_direct_id(&mut conn, &first_user_id, &second_user_id).await,
+        Err(err) => Err(err),
+    };
+
+    conversation_id.unwrap_or_else(|err| {
+        println!("Error: {:?}", err);
+        None
+    })
+}
+
+/// Where a stream of a conversation starts: right after the last message the client received
+/// before it reconnected, or after the latest message for a new client.
+async fn stream_start(db: &mut PgConnection, conversation_id: i32, last_event_id: &LastEventId) -> Result<i32, status::Custom<String>> {
+    if let Some(last_event_id) = last_event_id.0 {
+        return Ok(last_event_id);
+    }
+
+    match ChatRoomMessage::get_latest_id(db, &conversation_id).await {
+        Ok(latest_id) => Ok(latest_id.unwrap_or_default()),
+        Err(err) => {
+            println!("Error: {:?}", err);
+            Err(status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string()))
+        }
+    }
+}
+
+/// The conversation a stream follows, as its messages are shown.
+trait OpenConversation {
+    /// The display name and image of whoever sent a message
+    async fn sender(&mut self, sender_id: i32) -> (String, String);
+
+    /// The sidebar preview of the conversation as `msg` becomes its latest message
+    fn preview(&self, msg: &ChatRoomMessage) -> Html;
+
+    /// The sidebar preview of the conversation once its latest message may have changed
+    async fn latest_preview(&self) -> Html;
+}
+
+/// A 1:1 conversation between `user` and `receiver`, opened as `sender_id` and `receiver_id`
+struct DirectConversation {
+    pool: PgPool,
+    user: User,
+    receiver: User,
+    sender_id: i32,
+    receiver_id: i32,
+    other_user_id: i32,
+}
+
+impl OpenConversation for DirectConversation {
+    async fn sender(&mut self, sender_id: i32) -> (String, String) {
+        sender_profile(sender_id, &self.user, &self.receiver)
+    }
+
+    fn preview(&self, msg: &ChatRoomMessage) -> Html {
+        components::sidebar_preview(self.sender_id, self.receiver_id, &msg.preview())
+    }
+
+    async fn latest_preview(&self) -> Html {
+        latest_visible_preview(&self.pool, self.user.id, self.other_user_id).await
+    }
+}
+
+/// A group, with the members who sent messages to it so far
+struct GroupConversation {
+    pool: PgPool,
+    user_id: i32,
+    conversation_id: i32,
+    senders: HashMap<i32, ConversationMember>,
+}
+
+impl OpenConversation for GroupConversation {
+    async fn sender(&mut self, sender_id: i32) -> (String, String) {
+        let sender = message_sender(&self.pool, &mut self.senders, sender_id).await;
+
+        (sender.display_name, sender.display_image)
+    }
+
+    fn preview(&self, msg: &ChatRoomMessage) -> Html {
+        components::group_preview(self.conversation_id, &msg.preview(), true)
+    }
+
+    async fn latest_preview(&self) -> Html {
+        latest_visible_group_preview(&self.pool, self.user_id, self.conversation_id).await
+    }
+}
+
+/// A new message of an open conversation, along with its new sidebar preview.
+async fn new_message(conversation: &mut impl OpenConversation, msg: &ChatRoomMessage, user_id: i32) -> Html {
+    let (name, display_image) = conversation.sender(msg.sender_id).await;
+    let mut html = components::message(&msg.to_chat_message(user_id), &name, &display_image, false);
+
+    html.push(&conversation.preview(msg));
+
+    html
+}
+
+/// Shows a change to messages already on the page of an open conversation.
+/// `None` if the event is not such a change, or not one for the user.
+async fn message_change(conversation: &mut impl OpenConversation, event: &ChatEvent, user_id: i32) -> Option<Event> {
+    match event {
+        ChatEvent::Delivered { message_id, sender_id, .. } if *sender_id == user_id => Some(Event::data(
+            components::message_status(*message_id, MessageStatus::Delivered, true)
+        ).event("status")),
+        ChatEvent::Edited(msg) => {
+            let (name, display_image) = conversation.sender(msg.sender_id).await;
+
+            Some(Event::data(
+                components::message(&msg.to_chat_message(user_id), &name, &display_image, true)
+            ).event("edited"))
+        }
+        ChatEvent::Deleted(msg) => {
+            let (name, display_image) = conversation.sender(msg.sender_id).await;
+            let mut html = components::message(&msg.to_chat_message(user_id), &name, &display_image, true);
+
+            html.push(&conversation.latest_preview().await);
+
+            Some(Event::data(html).event("deleted"))
+        }
+        ChatEvent::Reacted { message_id, reactions, .. } => Some(Event::data(
+            components::reactions(*message_id, reactions, user_id, true)
+        ).event("reacted")),
+        ChatEvent::Hidden { user_id: hidden_by, message_id, .. } if *hidden_by == user_id => {
+            let mut html = components::removed_message(*message_id);
+
+            html.push(&conversation.latest_preview().await);
+
+            Some(Event::data(html).event("deleted"))
+        }
+        ChatEvent::Expired { message_ids, .. } => {
+            let mut html: Html = message_ids.iter().map(|message_id| components::removed_message(*message_id)).collect();
+
+            html.push(&conversation.latest_preview().await);
+
+            Some(Event::data(html).event("expired"))
+        }
+        _ => None,
+    }
+}
+
+/// The sidebar preview of a conversation after a change to its messages.
+/// `None` if the event is not such a change, or not one for the user.
+async fn preview_change(pool: &PgPool, event: &ChatEvent, user_id: i32) -> Option<Event> {
+    let other_user_id = |sender_id: i32, receiver_id: Option<i32>| {
+        receiver_id.map(|receiver_id| if sender_id == user_id { receiver_id } else { sender_id })
+    };
+    let (conversation_id, other_user_id) = match event {
+        ChatEvent::Edited(msg) | ChatEvent::Deleted(msg) => (msg.conversation_id, other_user_id(msg.sender_id, msg.receiver_id)),
+        ChatEvent::Hidden { user_id: hidden_by, conversation_id, other_user_id, .. } if *hidden_by == user_id => (*conversation_id, *other_user_id),
+        ChatEvent::Expired { conversation_id, sender_id, receiver_id, .. } => (*conversation_id, other_user_id(*sender_id, *receiver_id)),
+        _ => return None,
+    };
+
+    Some(Event::data(
+        latest_visible_preview_of(pool, user_id, conversation_id, other_user_id).await
+    ).event("preview"))
+}
+
+/// Every 1:1 conversation and group of the user as the sidebar lists them.
+async fn sidebar_of(pool: &PgPool, user_id: i32) -> Result<(Vec<Chat>, Vec<GroupPreview>), sqlx::Error> {
+    let mut conn = pool.acquire().await?;
+    let user_chats = Chat::get_user_chats(&mut conn, &user_id, &String::new()).await?;
+    let groups = Conversation::get_groups_of_user(&mut conn, &user_id).await?;
+
+    Ok((user_chats, groups))
+}
+
+#[derive(FromForm)]
+pub struct ChatParticipants {
+    sender_id: i32,
+    receiver_id: i32
+}
+
+/// Every change to a conversation between two users.
+/// Messages have their id as the event id, so a reconnecting client gets the ones it missed.
+#[get("/events/chats?<participants..>")]
+pub async fn chats_sse(
+    database: &State<Db>,
+    pubsub: &State<PubSub>,
+    presence: &State<Presence>,
+    participants: ChatParticipants,
+    user: User,
+    last_event_id: LastEventId,
+    mut end: Shutdown
+) -> Result<EventStream![], status::Custom<String>> {
+    let ChatParticipants { sender_id, receiver_id } = participants;
+
+    if user.id != sender_id && receiver_id != user.id {
+        return Err(status::Custom(Status::Unauthorized, "Unauthorized".to_string()));
+    }
+
+    let pool = (***database).clone();
+    let mut db = pool.acquire().await.map_err(|err| {
+        println!("Error: {:?}", err);
+        status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string())
+    })?;
+    let receiver = if receiver_id == user.id {
+        Some(user.clone())
+    } else {
+        User::get_by_id(&mut db, &receiver_id).await
+    };
+
+    if receiver.is_none() {
+        return Err(status::Custom(Status::NotFound, "User to chat with not found.".to_string()));
+    }
+
+    let receiver = receiver.unwrap();
+    let other_user_id = if sender_id == user.id { receiver_id } else { sender_id };
+    let pubsub = pubsub.inner().clone();
+    // Subscribe before looking for missed messages so none fall in between.
+    let mut rx = pubsub.subscribe(Topic::conversation(sender_id, receiver_id));
+    // Only sending the first message starts a conversation.
+    let mut conversation_id = Conversation::get_direct_id(&mut db, &sender_id, &receiver_id).await.map_err(|err| {
+        println!("Error: {:?}", err);
+        status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string())
+    })?;
+    let mut last_message_id = match conversation_id {
+        Some(conversation_id) => stream_start(&mut db, conversation_id, &last_event_id).await?,
+        None => last_event_id.0.unwrap_or_default(),
+    };
+
+    drop(db);
+
+    let connected = presence.connect(user.id).await;
+    let typing_text = html!("{} is typing...", receiver.display_name);
+    let mut conversation = DirectConversation { pool: pool.clone(), user: user.clone(), receiver, sender_id, receiver_id, other_user_id };
+
+    Ok(EventStream! {
+        let _connected = connected;
+        let mut typing_until: Option<Instant> = None;
+        // Catch up first when reconnecting.
+        let mut resync = last_event_id.0.is_some();
+
+        loop {
+            let messages = if resync {
+                if conversation_id.is_none() {
+                    conversation_id = direct_conversation_id(&pool, sender_id, receiver_id).await;
+                }
+
+                let missed = match conversation_id {
+                    Some(conversation_id) => missed_messages(&pool, user.id, conversation_id, last_message_id).await,
+                    None => Vec::new(),
+                };
+
+                // A full page may not be all of them.
+                resync = missed.len() as i64 == consts::MESSAGES_PER_PAGE;
+                missed
+            } else {
+                let typing_expires = async move {
+                    match typing_until {
+                        Some(typing_until) => sleep_until(typing_until).await,
+                        None => pending().await
+                    }
+                };
+
+                let event = select! {
+                    event = rx.recv() => match event {
+                        // Some events were dropped or lost, get the messages from the database instead.
+                        Ok(ChatEvent::Resync) | Err(RecvError::Lagged(_)) => {
+                            resync = true;
+                            continue;
+                        }
+                        Ok(event) => event,
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = typing_expires => {
+                        typing_until = None;
+                        yield Event::data("").event("typing");
+                        continue;
+                    },
+                    _ = &mut end => break
+                };
+
+                match event {
+                    ChatEvent::Message(msg) => vec![msg],
+                    ChatEvent::Seen { reader_id, read_at, message_ids, .. } => {
+                        if reader_id != user.id {
+                            yield Event::data(format!("Seen {}", read_at)).event("seen");
+
+                            if !message_ids.is_empty() {
+                                yield Event::data(
+                                    message_ids.iter().map(|message_id| components::message_status(*message_id, MessageStatus::Seen, true)).collect::<Html>()
+                                ).event("status");
+                            }
+                        }
+
+                        continue;
+                    }
+                    ChatEvent::Typing { user_id, .. } => {
+                        if user_id != user.id {
+                            typing_until = Some(Instant::now() + Duration::from_secs(consts::TYPING_INDICATOR_SECONDS));
+                            yield Event::data(typing_text.clone()).event("typing");
+                        }
+
+                        continue;
+                    }
+                    event => {
+                        if let Some(change) = message_change(&mut conversation, &event, user.id).await {
+                            yield change;
+                        }
+
+                        continue;
+                    }
+                }
+            };
+
+            for msg in messages {
+                let message_id = msg.id.unwrap_or_default();
+
+                // Already sent while catching up.
+                if message_id <= last_message_id {
+                    continue;
+                }
+
+                last_message_id = message_id;
+
+                // Whoever was typing has sent their message.
+                if msg.sender_id != user.id && typing_until.take().is_some() {
+                    yield Event::data("").event("typing");
+                }
+
+                let mut html = new_message(&mut conversation, &msg, user.id).await;
+
+                if msg.sender_id == user.id {
+                    // Whatever was seen before is now outdated.
+                    html.push_markup("<small id=\"chat_seen\" hx-swap-oob=\"innerHTML\"></small>");
+
+                    if let Some(id) = msg.client_id.as_deref().and_then(ScheduledMessage::id_from_client_id) {
+                        html.push(&components::removed_scheduled_message(id));
+                    }
+                }
+
+                yield Event::data(
+                    html
+                ).event("message").id(message_id.to_string());
+
+                if msg.sender_id != user.id {
+                    pubsub.mark_as_delivered(&pool, &msg).await;
+
+                    // The user has this conversation open, so they have read it.
+                    if let Ok(mut conn) = pool.acquire().await {
+                        if let Ok(Some(receipt)) = Chat::mark_as_read(&mut conn, &user.id, &other_user_id).await {
+                            pubsub.publish(ChatEvent::seen(user.id, other_user_id, receipt)).await;
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Every message and change to a group, for as long as the user is a member.
+/// Messages have their id as the event id, so a reconnecting client gets the ones it missed.
+#[get("/events/groups?<conversation_id>")]
+pub async fn groups_sse(
+    database: &State<Db>,
+    pubsub: &State<PubSub>,
+    presence: &State<Presence>,
+    conversation_id: i32,
+    user: User,
+    last_event_id: LastEventId,
+    mut end: Shutdown
+) -> Result<EventStream![], status::Custom<String>> {
+    let pool = (***database).clone();
+    let mut db = pool.acquire().await.map_err(|err| {
+        println!("Error: {:?}", err);
+        status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string())
+    })?;
+    let group = match Conversation::get_by_id(&mut db, &conversation_id).await {
+        Ok(Some(conversation)) if conversation.is_group => conversation,
+        Ok(_) => return Err(status::Custom(Status::NotFound, "Group not found.".to_string())),
+        Err(err) => {
+            println!("Error: {:?}", err);
+            return Err(status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string()));
+        }
+    };
+
+    if !group.has_member(user.id) {
+        return Err(status::Custom(Status::Unauthorized, "Unauthorized".to_string()));
+    }
+
+    let senders: HashMap<i32, ConversationMember> = group.members
+        .into_iter()
+        .map(|member| (member.id, member))
+        .collect();
+    let pubsub = pubsub.inner().clone();
+    // Subscribe before looking for missed messages so none fall in between.
+    let mut rx = pubsub.subscribe(Topic::Group(conversation_id));
+    let mut last_message_id = stream_start(&mut db, conversation_id, &last_event_id).await?;
+
+    drop(db);
+
+    let connected = presence.connect(user.id).await;
+    let mut conversation = GroupConversation { pool: pool.clone(), user_id: user.id, conversation_id, senders };
+
+    Ok(EventStream! {
+        let _connected = connected;
+        // Catch up first when reconnecting.
+        let mut resync = last_event_id.0.is_some();
+
+        loop {
+            let messages = if resync {
+                let missed = missed_messages(&pool, user.id, conversation_id, last_message_id).await;
+
+                // A full page may not be all of them.
+                resync = missed.len() as i64 == consts::MESSAGES_PER_PAGE;
+                missed
+            } else {
+                let event = select! {
+                    event = rx.recv() => match event {
+                        // Some events were dropped or lost, get the messages from the database instead.
+                        Ok(ChatEvent::Resync) | Err(RecvError::Lagged(_)) => {
+                            resync = true;
+                            continue;
+                        }
+                        Ok(event) => event,
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = &mut end => break
+                };
+
+                match event {
+                    ChatEvent::Message(msg) => vec![msg],
+                    ChatEvent::Membership { user_id, joined, .. } => {
+                        // Whoever left can no longer follow the group.
+                        if user_id == user.id && !joined {
+                            break;
+                        }
+
+                        let group = match pool.acquire().await {
+                            Ok(mut conn) => Conversation::get_by_id(&mut conn, &conversation_id).await,
+                            Err(err) => Err(err),
+                        };
+
+                        if let Ok(Some(group)) = group {
+                            yield Event::data(
+                                components::group_members(&group, user.id, true)
+                            ).event("members");
+                        }
+
+                        continue;
+                    }
+                    // Groups have no typing indicator or read receipts yet.
+                    event => {
+                        if let Some(change) = message_change(&mut conversation, &event, user.id).await {
+                            yield change;
+                        }
+
+                        continue;
+                    }
+                }
+            };
+
+            for msg in messages {
+                let message_id = msg.id.unwrap_or_default();
+
+                // Already sent while catching up.
+                if message_id <= last_message_id {
+                    continue;
+                }
+
+                last_message_id = message_id;
+
+                yield Event::data(
+                    new_message(&mut conversation, &msg, user.id).await
+                ).event("message").id(message_id.to_string());
+
+                if msg.sender_id != user.id {
+                    pubsub.mark_as_delivered(&pool, &msg).await;
+                }
+            }
+        }
+    })
+}
+
+/// Everything that happens in any conversation of the user, in one stream,
+/// so the sidebar stays up to date whichever conversation is open.
+/// Every event is addressed to the elements of its own conversation:
+/// - `message`: a new message, appended to its conversation if that is the one open
+/// - `preview`: the latest message of a conversation, or every group once the user joins or leaves one
+/// - `unread`: how many messages of a 1:1 conversation the user has not read yet
+/// - `notification`: a message someone else sent
+/// - `presence`: someone the user has a conversation with came online or went offline
+#[get("/events/me")]
+pub async fn me_sse(
+    database: &State<Db>,
+    pubsub: &State<PubSub>,
+    presence: &State<Presence>,
+    user: User,
+    mut end: Shutdown
+) -> Result<EventStream![], status::Custom<String>> {
+    let pool = (***database).clone();
+    let pubsub = pubsub.inner().clone();
+    let groups = match pool.acquire().await {
+        Ok(mut conn) => Conversation::get_groups_of_user(&mut conn, &user.id).await,
+        Err(err) => Err(err),
+    };
+    let groups = groups.map_err(|err| {
+        println!("Error: {:?}", err);
+        status::Custom(Status::InternalServerError, "Something went wrong. Please try again.".to_string())
+    })?;
+
+    // Group messages are only published to their group.
+    let mut subscriptions = Subscriptions::new(vec![pubsub.subscribe(Topic::User(user.id))]);
+
+    for group in groups {
+        subscriptions.add(pubsub.subscribe(Topic::Group(group.id)));
+    }
+
+    let mut senders: HashMap<i32, ConversationMember> = HashMap::new();
+    let presence = presence.inner().clone();
+    let connected = presence.connect(user.id).await;
+
+    Ok(EventStream! {
+        let _connected = connected;
+        let mut resync = false;
+
+        loop {
+            if resync {
+                resync = false;
+
+                match sidebar_of(&pool, user.id).await {
+                    Ok((user_chats, groups)) => {
+                        for chat in user_chats {
+                            let other_user_id = if chat.sender_id == user.id { chat.receiver_id } else { chat.sender_id };
+
+                            yield Event::data(
+                                components::sidebar_preview(user.id, other_user_id, &chat.message)
+                            ).event("preview");
+                            yield Event::data(
+                                components::unread_badge(user.id, other_user_id, chat.unread_count, true)
+                            ).event("unread");
+                        }
+
+                        // Joining or leaving a group may have been dropped too.
+                        subscriptions.retain(|topic| match topic {
+                            Topic::Group(conversation_id) => groups.iter().any(|group| group.id == conversation_id),
+                            _ => true,
+                        });
+
+                        for group in &groups {
+                            subscriptions.add(pubsub.subscribe(Topic::Group(group.id)));
+                        }
+
+                        yield Event::data(components::group_list(&groups, true)).event("preview");
+                    },
+                    Err(err) => println!("Error: {:?}", err),
+                }
+            }
+
+            let event = select! {
+                event = subscriptions.recv() => match event {
+                    // Some events were dropped or lost, get the sidebar from the database instead.
+                    Ok(ChatEvent::Resync) | Err(RecvError::Lagged(_)) => {
+                        resync = true;
+                        continue;
+                    }
+                    Ok(event) => event,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut end => break
+            };
+
+            match event {
+                ChatEvent::Message(msg) => {
+                    let sender = message_sender(&pool, &mut senders, msg.sender_id).await;
+                    let html = components::message(&msg.to_chat_message(user.id), &sender.display_name, &sender.display_image, false);
+
+                    yield Event::data(
+                        components::message_in_conversation(msg.conversation_id, &html)
+                    ).event("message");
+
+                    let url = match msg.receiver_id {
+                        Some(receiver_id) => {
+                            let other_user_id = if msg.sender_id == user.id { receiver_id } else { msg.sender_id };
+
+                            yield Event::data(
+                                components::sidebar_preview(user.id, other_user_id, &msg.preview())
+                            ).event("preview");
+
+                            if msg.sender_id != user.id {
+                                yield Event::data(unread_badge(&pool, user.id, other_user_id).await).event("unread");
+                            }
+
+                            format!("/chats?sender_id={}&receiver_id={}&is_htmx=true", user.id, other_user_id)
+                        },
+                        None => {
+                            yield Event::data(
+                                components::group_preview(msg.conversation_id, &msg.preview(), true)
+                            ).event("preview");
+
+                            format!("/chats/groups?conversation_id={}", msg.conversation_id)
+                        }
+                    };
+
+                    if msg.sender_id != user.id {
+                        yield Event::data(
+                            components::notification(msg.conversation_id, &url, &sender.display_name, &msg.preview())
+                        ).event("notification");
+                    }
+                }
+                // Read on another device
+                ChatEvent::Seen { reader_id, sender_id, .. } => {
+                    if reader_id == user.id {
+                        yield Event::data(
+                            components::unread_badge(user.id, sender_id, 0, true)
+                        ).event("unread");
+                    }
+                }
+                ChatEvent::Membership { conversation_id, user_id, joined } => {
+                    // Someone else joined or left one of the user's groups.
+                    if user_id != user.id {
+                        continue;
+                    }
+
+                    if joined {
+                        subscriptions.add(pubsub.subscribe(Topic::Group(conversation_id)));
+                    } else {
+                        subscriptions.remove(Topic::Group(conversation_id));
+                    }
+
+                    let groups = match pool.acquire().await {
+                        Ok(mut conn) => Conversation::get_groups_of_user(&mut conn, &user.id).await,
+                        Err(err) => Err(err),
+                    };
+
+                    match groups {
+                        Ok(groups) => {
+                            yield Event::data(components::group_list(&groups, true)).event("preview");
+                        },
+                        Err(err) => println!("Error: {:?}", err),
+                    }
+                }
+                // They may still be online on another instance, or have been offline for a while.
+                ChatEvent::Presence { user_id, .. } => {
+                    let text = match pool.acquire().await {
+                        Ok(mut conn) => presence.describe(&mut conn, user_id).await,
+                        Err(err) => {
+                            println!("Error: {:?}", err);
+                            continue;
+                        }
+                    };
+
+                    yield Event::data(components::presence(user_id, &text, true)).event("presence");
+                },
+                event => {
+                    if let Some(preview) = preview_change(&pool, &event, user.id).await {
+                        yield preview;
+                    }
+
+                    // A deleted message no longer counts as unread.
+                    match event {
+                        ChatEvent::Deleted(msg) if msg.receiver_id.is_some() && msg.sender_id != user.id => {
+                            yield Event::data(unread_badge(&pool, user.id, msg.sender_id).await).event("unread");
+                        },
+                        _ => (),
+                    }
+                }
+            }
+        }
+    })
+}
@@ -1,19 +1,46 @@
 use rocket_db_pools::Connection;
 use rocket_dyn_templates::{context, Template};
 
-use rocket::{get, http::{CookieJar, Status}, response::{content::RawHtml, status}};
+use rocket::{get, http::{CookieJar, Status}, response::{content::RawHtml, status}, FromForm, State};
 
-use crate::{auth_uri, chats::chat_struct::Chat, consts::{self, TemplateOrHtml}, cookies::settings::{self, Language, Theme}, db::Db, pages::auth::login, user::user_struct::User, utils};
+use crate::{auth_uri, chats::{chat_struct::{Chat, MessageWindow}, conversation::Conversation, event::ChatEvent, presence::Presence, pubsub::PubSub}, consts::{self, TemplateOrHtml}, cookies::settings::{self, Language, Theme}, db::Db, html, pages::auth::login, user::user_struct::User, utils::{self, html::Html}};
 
-#[get("/?<sender_id>&<receiver_id>&<is_htmx>")]
+use super::components;
+
+#[derive(FromForm)]
+pub struct ChatQuery {
+    sender_id: Option<i32>,
+    receiver_id: Option<i32>,
+    is_htmx: Option<bool>,
+    /// Opens the conversation at this message instead of its latest one
+    message_id: Option<i32>
+}
+
+/// The messages to load to open the conversation between two users at `message_id`,
+/// `None` if it is not one of its messages.
+async fn window_around(db: &mut Connection<Db>, user_id: &i32, sender_id: &i32, receiver_id: &i32, message_id: &i32) -> Option<MessageWindow> {
+    let window = match Conversation::get_direct_id(db, sender_id, receiver_id).await {
+        Ok(Some(conversation_id)) => Chat::messages_around(db, user_id, &conversation_id, message_id).await,
+        Ok(None) => Ok(None),
+        Err(err) => Err(err),
+    };
+
+    window.unwrap_or_else(|err| {
+        println!("Error: {:?}", err);
+        None
+    })
+}
+
+#[get("/?<query..>")]
 pub async fn page(
     mut db: Connection<Db>,
+    pubsub: &State<PubSub>,
+    presence: &State<Presence>,
     user: User,
     cookies: &CookieJar<'_>,
-    sender_id: Option<i32>,
-    receiver_id: Option<i32>,
-    is_htmx: Option<bool>
+    query: ChatQuery
 ) -> Result<TemplateOrHtml, status::Custom<String>> {
+    let ChatQuery { sender_id, receiver_id, is_htmx, message_id } = query;
     let sender_id_mut: Option<i32>;
 
     match sender_id {
@@ -34,149 +61,52 @@ pub async fn page(
 
     let sender_id_mut = sender_id_mut.unwrap();
 
+    // Opening a conversation reads it.
+    if let Some(receiver_id) = receiver_id {
+        let other_user_id = if sender_id_mut == user.id { receiver_id } else { sender_id_mut };
+
+        if let Ok(Some(receipt)) = Chat::mark_as_read(&mut db, &user.id, &other_user_id).await {
+            pubsub.publish(ChatEvent::seen(user.id, other_user_id, receipt)).await;
+        }
+    }
+
+    let window = match (receiver_id, message_id) {
+        (Some(receiver_id), Some(message_id)) => window_around(&mut db, &user.id, &sender_id_mut, &receiver_id, &message_id).await,
+        _ => None,
+    };
+    // Messages that are not in the conversation are not scrolled to.
+    let focus = window.and(message_id).map(components::focus_message).unwrap_or_default();
+    let window = window.unwrap_or(MessageWindow { before: None, limit: consts::MESSAGES_PER_PAGE, newer: None });
+
     match is_htmx {
         Some(true) => {
             if receiver_id.is_none() {
                 return Ok(TemplateOrHtml::Html(
-                    RawHtml("<div><p>Select a chat to start chatting.</p></div>".to_string())
+                    RawHtml(Html::trusted("<div><p>Select a chat to start chatting.</p></div>"))
                 ));  
             }
 
-            let user_chats = Chat::get_messages(&mut db, &sender_id_mut, &receiver_id.unwrap()).await;
+            let user_chats = Chat::get_messages(&mut db, &user.id, &sender_id_mut, &receiver_id.unwrap(), window.before.as_ref(), window.limit).await;
 
             match user_chats {
-                Ok(user_chats) => {
-                    let receiver_name = if user_chats.receiver_id == user.id {
-                        &user_chats.sender_name
-                    } else {
-                        &user_chats.receiver_name
-                    };
-                    let receiver_avatar = if user_chats.receiver_id == user.id {
-                        &user_chats.sender_avatar
-                    } else {
-                        &user_chats.receiver_avatar
-                    };
-
-                    let upper_html = format!(
-                        "
-                        <div class=\"chats__container\" hx-swap=\"beforeend scroll:down\" hx-target=\"#chat_info_container\" sse-swap=\"message\" sse-connect=\"/events/chats?{}\">
-                            <nav class=\"chats__header\">
-                                <div>
-                                    <img
-                                        src=\"{}\"
-                                        alt=\"{}'s Profile picture\"
-                                        width=\"40\"
-                                        height=\"40\"
-                                        loading=\"lazy\"
-                                        class=\"profile\"
-                                    />
-                                    <span>{}</span>
-                                </div>
-                            </nav>
-                            <ul id=\"chat_info_container\">
-                        ",
-                        user_chats.id,
-                        receiver_avatar,
-                        receiver_name,
-                        receiver_name,
-                    );
-                    let mut messages_html = String::new();
-
-                    for chat in user_chats.messages {
-                        let is_receiver = chat.is_receiver_message;
-                        let display_image = if is_receiver {
-                            &user_chats.receiver_avatar
-                        } else {
-                            &user_chats.sender_avatar
-                        };
-                        let display_name = if is_receiver {
-                            &user_chats.receiver_name
-                        } else {
-                            &user_chats.sender_name
-                        };
-
-                        if is_receiver {
-                            messages_html.push_str(
-                                format!(
-                                    "
-                                    <li data-isreceiver=\"{}\">
-                                        <div class=\"chats__message\">
-                                            <div>
-                                                <img
-                                                    src=\"{}\"
-                                                    alt=\"{}'s Profile picture\"
-                                                    width=\"28\"
-                                                    height=\"28\"
-                                                    loading=\"lazy\"
-                                                    class=\"profile\"
-                                                />
-                                                <div>
-                                                    <small>{}</small>
-                                                    <p>{}</p>
-                                                </div>
-                                            </div>
-                                            <time>{}</time>
-                                        </div>
-                                    </li>
-                                    ",
-                                    is_receiver,
-                                    display_image,
-                                    display_name,
-                                    display_name,
-                                    chat.message,
-                                    chat.created_at
-                                ).as_str()
-                            );
-                        } else {
-                            messages_html.push_str(
-                                format!(
-                                    "
-                                    <li data-isreceiver=\"{}\">
-                                        <div class=\"chats__message\">
-                                            <div>
-                                                <p>{}</p>
-                                            </div>
-                                            <time>{}</time>
-                                        </div>
-                                    </li>
-                                    ",
-                                    is_receiver,
-                                    chat.message,
-                                    chat.created_at
-                                ).as_str()
-                            );
-                        }
-                    }
-
-                    let end_html = format!(
-                        "   </ul>
-                        <div>
-                            <form
-                                id=\"chats__form\"
-                                hx-post=\"/chats/send\"
-                                hx-trigger=\"submit\"
-                                hx-swap=\"outerHTML\"
-                                hx-target=\"#message_input\"
-                            >
-                                <input name=\"receiver_id\" value=\"{}\" hidden>
-                                <input name=\"sender_id\" value=\"{}\" hidden>
-                                <input id=\"message_input\" name=\"message\" placeholder=\"Type a message...\" required>
-                                <button type=\"submit\" title=\"Send Message\">Send</button>
-                            </form>
-                        </div>
-                        </div>
-                        ",
-                        user_chats.receiver_id,
-                        user_chats.sender_id
-                    );
-
-                    Ok(TemplateOrHtml::Html(RawHtml(format!("{}{}{}", upper_html, messages_html, end_html))))
+                Ok(mut user_chats) => {
+                    user_chats.newer = window.newer;
+
+                    let other_user_id = if user_chats.sender_id == user.id { user_chats.receiver_id } else { user_chats.sender_id };
+                    let presence_text = presence.describe(&mut db, other_user_id).await;
+
+                    Ok(TemplateOrHtml::Html(RawHtml(html!(
+                        "{}{}{}",
+                        components::chat(&user_chats, user.id, &presence_text),
+                        components::unread_badge(user.id, other_user_id, 0, true),
+                        focus
+                    ))))
                 },
                 Err(err) => {
                     println!("Error: {:?}", err);
 
                     Ok(TemplateOrHtml::Html(
-                        RawHtml(format!("<div><p>{:?}</p></div>", err))
+                        RawHtml(html!("<div><p>{}</p></div>", format!("{:?}", err)))
                     ))
                 }
             }
@@ -207,10 +137,16 @@ pub async fn page(
                 ));
             }
 
-            let current_chat = Chat::get_messages(&mut db, &sender_id_mut, &receiver_id.unwrap()).await;
+            let current_chat = Chat::get_messages(&mut db, &user.id, &sender_id_mut, &receiver_id.unwrap(), window.before.as_ref(), window.limit).await;
             
             match current_chat {
-                Ok(current_chat) => {
+                Ok(mut current_chat) => {
+                    current_chat.newer = window.newer;
+
+                    let other_user_id = if current_chat.sender_id == user.id { current_chat.receiver_id } else { current_chat.sender_id };
+                    let presence_text = presence.describe(&mut db, other_user_id).await;
+                    let current_chat_html = html!("{}{}", components::chat(&current_chat, user.id, &presence_text), focus).into_string();
+
                     Ok(TemplateOrHtml::Template(Template::render(
                         "chats",
                         context! {
@@ -218,7 +154,7 @@ pub async fn page(
                             theme: preferred_theme,
                             lang: language,
                             chats: user_chats,
-                            current_chat,
+                            current_chat_html,
                             receiver_id,
                             metadata: consts::METADATA,
                             placeholder_display_image
@@ -1,3 +1,5 @@
 pub mod index;
 pub mod api;
 pub mod macro_rule;
+pub mod components;
+pub mod events;
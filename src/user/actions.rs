@@ -1,7 +1,7 @@
 use std::error::Error;
 
 use rocket_db_pools::Connection;
-use sqlx::Acquire;
+use sqlx::{Acquire, PgConnection};
 use time::OffsetDateTime;
 use crate::db::Db;
 
@@ -10,6 +10,36 @@ use super::user_struct::{User, UserStatus, Gender, UserRole};
 pub struct UserActions {}
 
 impl UserActions {
+    /// Saves that the user is around now, and returns when that is.
+    pub async fn update_last_seen(
+        db: &mut PgConnection,
+        user_id: &i32,
+    ) -> Result<OffsetDateTime, sqlx::Error> {
+        let record = sqlx::query!(
+            r#"
+            UPDATE users
+            SET last_seen = CURRENT_TIMESTAMP
+            WHERE id = $1
+            RETURNING last_seen AS "last_seen!""#,
+            user_id
+        ).fetch_one(&mut *db).await?;
+
+        Ok(record.last_seen)
+    }
+
+    /// When the user last had a chat open, if they ever did.
+    pub async fn get_last_seen(
+        db: &mut PgConnection,
+        user_id: &i32,
+    ) -> Result<Option<OffsetDateTime>, sqlx::Error> {
+        let record = sqlx::query!(
+            "SELECT last_seen FROM users WHERE id = $1",
+            user_id
+        ).fetch_optional(&mut *db).await?;
+
+        Ok(record.and_then(|record| record.last_seen))
+    }
+
     pub async fn update_last_login_date(
         db: &mut Connection<Db>,
         user_id: &i32,
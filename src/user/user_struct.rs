@@ -1,7 +1,7 @@
 use rocket::{http::Status, outcome::IntoOutcome, request::{self, FromRequest, Request}, serde::{Deserialize, Serialize}, time::OffsetDateTime};
 use serde_json;
 use rocket_db_pools::{sqlx, Connection};
-use sqlx::Acquire;
+use sqlx::{Acquire, PgConnection};
 
 use crate::{db::Db, utils};
 
@@ -166,14 +166,17 @@ impl User {
         }
     }
 
-    pub async fn get_display_name(db: &mut Connection<Db>, user_id: &i32) -> Option<String> {
-        sqlx::query!(
+    /// `None` if there is no such user.
+    pub async fn get_display_name(db: &mut PgConnection, user_id: &i32) -> Result<Option<String>, sqlx::Error> {
+        let record = sqlx::query!(
             "SELECT display_name FROM users WHERE id = $1", user_id
         )
-        .fetch_one(&mut ***db).await.ok().map(|user| user.display_name)
+        .fetch_optional(&mut *db).await?;
+
+        Ok(record.map(|user| user.display_name))
     }
 
-    pub async fn get_by_id(db: &mut Connection<Db>, id: &i32) -> Option<User> {
+    pub async fn get_by_id(db: &mut PgConnection, id: &i32) -> Option<User> {
         sqlx::query_as!(
             User,
             r#"
@@ -191,7 +194,7 @@ impl User {
             FROM users WHERE id = $1
             "#, id
         )
-        .fetch_one(&mut ***db).await.ok()
+        .fetch_one(&mut *db).await.ok()
     }
 
     pub async fn get_by_display_name(db: &mut Connection<Db>, display_name: &String) -> Option<User> {
@@ -1,5 +1,7 @@
 use dotenv;
 
+use crate::consts;
+
 pub fn load_db_url() -> String {
     dotenv::var("DATABASE_URL").expect("DATABASE_URL must be set")
 }
@@ -16,4 +18,29 @@ pub fn load_cloudinary_cloud_name() -> String {
     dotenv::var("CLOUDINARY_CLOUD_NAME").expect("CLOUDINARY_CLOUD_NAME must be set")
 }
 
+/// Either "postgres" (default) or "memory".
+/// Only use "memory" when running a single instance of the server.
+pub fn load_pubsub_backend() -> String {
+    dotenv::var("PUBSUB_BACKEND").unwrap_or_else(|_| "postgres".to_string())
+}
+
+/// Per-route rate limits, see [`crate::utils::rate_limit::RateLimiter::from_env`].
+pub fn load_rate_limits() -> String {
+    dotenv::var("RATE_LIMITS").unwrap_or_default()
+}
 
+/// The largest file that can be attached to a message, in bytes.
+pub fn load_max_attachment_size() -> u64 {
+    dotenv::var("MAX_ATTACHMENT_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(consts::DEFAULT_MAX_ATTACHMENT_SIZE)
+}
+
+/// How many lines a code block in a message can have before it is collapsed.
+pub fn load_code_block_collapse_lines() -> usize {
+    dotenv::var("CODE_BLOCK_COLLAPSE_LINES")
+        .ok()
+        .and_then(|lines| lines.parse().ok())
+        .unwrap_or(consts::DEFAULT_CODE_BLOCK_COLLAPSE_LINES)
+}
@@ -0,0 +1,158 @@
+//! Code blocks in messages, highlighted on the server.
+//!
+//! Every token is a `<span>` with the classes of its scope prefixed with `hl-`,
+//! like `hl-keyword hl-control hl-rust`, and `chats.css` colors them.
+use std::sync::OnceLock;
+
+use syntect::{
+    html::{line_tokens_to_classed_spans, ClassStyle},
+    parsing::{ParseState, Scope, ScopeStack, SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+use crate::html;
+
+use super::{env, html::Html};
+
+/// Prefixed to every class a scope becomes
+pub const CLASS_PREFIX: &str = "hl-";
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: CLASS_PREFIX };
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Opens a span for `scope`, for the scopes that go on from the line before.
+fn open_span(html: &mut String, scope: &Scope) {
+    html.push_str("<span class=\"");
+
+    for (i, atom) in scope.build_string().split('.').enumerate() {
+        if i != 0 {
+            html.push(' ');
+        }
+
+        html.push_str(CLASS_PREFIX);
+        html.push_str(atom);
+    }
+
+    html.push_str("\">");
+}
+
+/// Every line of `code` with its tokens in spans. Each line is complete on its own,
+/// so a comment or string that goes on for several lines is closed and opened again.
+fn highlighted_lines(code: &str, syntax: &SyntaxReference) -> Option<Vec<Html>> {
+    let mut parse_state = ParseState::new(syntax);
+    let mut scopes = ScopeStack::new();
+    let mut lines = Vec::new();
+
+    for line in LinesWithEndings::from(code) {
+        let mut html = String::new();
+
+        for scope in scopes.as_slice() {
+            open_span(&mut html, scope);
+        }
+
+        let ops = parse_state.parse_line(line, syntax_set()).ok()?;
+        let (spans, _) = line_tokens_to_classed_spans(line, &ops, CLASS_STYLE, &mut scopes).ok()?;
+
+        // The line break goes between the lines instead.
+        html.push_str(&spans.replace(['\r', '\n'], ""));
+        html.push_str(&"</span>".repeat(scopes.len()));
+        lines.push(Html::trusted(html));
+    }
+
+    Some(lines)
+}
+
+fn plain_lines(code: &str) -> Vec<Html> {
+    code.lines().map(Html::text).collect()
+}
+
+/// A code block with line numbers and a button to copy it, see `chats.js`.
+/// It is highlighted if `language` is one we know, like `rust` or `sql`,
+/// and collapsed if it has more lines than [`env::load_code_block_collapse_lines`].
+pub fn code_block(code: &str, language: Option<&str>) -> Html {
+    let syntax = language.and_then(|language| syntax_set().find_syntax_by_token(language));
+    let lines = syntax
+        .and_then(|syntax| highlighted_lines(code, syntax))
+        .unwrap_or_else(|| plain_lines(code));
+    let label = match (syntax, language) {
+        (Some(syntax), _) => syntax.name.as_str(),
+        (None, Some(language)) => language,
+        (None, None) => "Code",
+    };
+
+    let mut numbered = Html::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        numbered.push(&html!("<span class=\"chats__code_line\" data-line=\"{}\">{}</span>\n", i + 1, line));
+    }
+
+    let header = html!(
+        "<small>{}</small><button type=\"button\" class=\"ghost chats__code_copy\" title=\"Copy code\" data-copy-code>Copy</button>",
+        label
+    );
+    let body = html!("<pre><code>{}</code></pre>", numbered);
+
+    if lines.len() > env::load_code_block_collapse_lines() {
+        html!(
+            "<details class=\"chats__code\"><summary class=\"chats__code_header\">{} <small>{} lines</small></summary>{}</details>",
+            header,
+            lines.len(),
+            body
+        )
+    } else {
+        html!("<div class=\"chats__code\"><div class=\"chats__code_header\">{}</div>{}</div>", header, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlights_known_languages() {
+        let html = code_block("fn main() {}\n", Some("rust"));
+
+        assert!(html.as_str().contains("<small>Rust</small>"));
+        assert!(html.as_str().contains("<span class=\"hl-storage hl-type hl-function hl-rust\">fn</span>"));
+        assert!(html.as_str().contains("data-line=\"1\""));
+    }
+
+    #[test]
+    fn keeps_every_line_complete() {
+        let html = code_block("/* one\ntwo */\nSELECT 1;\n", Some("sql"));
+        let html = html.as_str();
+
+        assert_eq!(html.matches("data-line=").count(), 3);
+
+        for line in html.split("<span class=\"chats__code_line\"").skip(1) {
+            let line = line.split('\n').next().unwrap();
+
+            assert_eq!(line.matches("<span").count() + 1, line.matches("</span>").count());
+        }
+    }
+
+    #[test]
+    fn escapes_code() {
+        let html = code_block("<script>alert(1)</script>\n", Some("html"));
+
+        assert!(!html.as_str().contains("<script>"));
+
+        let html = code_block("<script>alert(1)</script>\n", Some("<b>"));
+
+        assert!(!html.as_str().contains("<script>"));
+        assert!(html.as_str().contains("<small>&lt;b&gt;</small>"));
+    }
+
+    #[test]
+    fn collapses_long_blocks() {
+        let code = "x\n".repeat(env::load_code_block_collapse_lines() + 1);
+
+        assert!(code_block(&code, None).as_str().starts_with("<details"));
+        assert!(code_block("x\n", None).as_str().starts_with("<div"));
+    }
+}
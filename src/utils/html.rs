@@ -0,0 +1,277 @@
+//! HTML fragments that escape whatever is put into them.
+//!
+//! Build fragments with [`html!`](crate::html), which works like `format!` except that
+//! every argument is escaped unless it already is [`Html`]. Markup from anywhere else has
+//! to be opted into with [`Html::trusted`]. Arguments must be passed after the format string,
+//! as `{name}` captures would not be escaped, so templates with anything but `{}` do not compile.
+use std::{borrow::Cow, fmt::{self, Display, Write}};
+
+use rocket::{response::Responder, Request};
+
+/// Markup that is safe to send to a browser.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Html(String);
+
+impl Html {
+    pub fn new() -> Self {
+        Self(String::new())
+    }
+
+    /// Sends `markup` as is. Only for markup this server wrote itself,
+    /// never for anything a user typed.
+    pub fn trusted(markup: impl Into<String>) -> Self {
+        Self(markup.into())
+    }
+
+    /// `text` as it should appear on the page
+    pub fn text(text: &str) -> Self {
+        Self(escape(text).into_owned())
+    }
+
+    /// Appends a fragment, or text that is escaped first.
+    pub fn push(&mut self, value: &(impl ToHtml + ?Sized)) {
+        // Writing to a String cannot fail.
+        let _ = write!(self.0, "{}", Render(value));
+    }
+
+    /// Appends markup written right here, like a closing tag.
+    pub fn push_markup(&mut self, markup: &'static str) {
+        self.0.push_str(markup);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl Display for Html {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<Html> for Cow<'static, str> {
+    fn from(html: Html) -> Self {
+        Cow::Owned(html.0)
+    }
+}
+
+impl FromIterator<Html> for Html {
+    fn from_iter<I: IntoIterator<Item = Html>>(iter: I) -> Self {
+        Self(iter.into_iter().map(|html| html.0).collect())
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Html {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        self.0.respond_to(request)
+    }
+}
+
+/// Escapes the characters that could end an element or a quoted attribute.
+pub fn escape(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len() + 16);
+
+    for char in text.chars() {
+        match char {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(char),
+        }
+    }
+
+    Cow::Owned(escaped)
+}
+
+/// Anything that can be put into an [`Html`] fragment.
+/// Text is escaped, fragments are kept as they are.
+pub trait ToHtml {
+    fn write_html(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+impl ToHtml for Html {
+    fn write_html(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl ToHtml for str {
+    fn write_html(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&escape(self))
+    }
+}
+
+impl ToHtml for String {
+    fn write_html(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_str().write_html(f)
+    }
+}
+
+impl<T: ToHtml + ?Sized> ToHtml for &T {
+    fn write_html(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).write_html(f)
+    }
+}
+
+/// Numbers and booleans cannot contain markup.
+macro_rules! impl_to_html_for_display {
+    ($($type:ty),*) => {
+        $(
+            impl ToHtml for $type {
+                fn write_html(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    Display::fmt(self, f)
+                }
+            }
+        )*
+    };
+}
+
+impl_to_html_for_display!(i32, i64, u64, usize, bool);
+
+/// Shows a [`ToHtml`] value escaped, used by [`html!`](crate::html).
+pub struct Render<'a, T: ToHtml + ?Sized>(pub &'a T);
+
+impl<T: ToHtml + ?Sized> Display for Render<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.write_html(f)
+    }
+}
+
+/// Whether every placeholder in `template` is a plain `{}`, checked by [`html!`](crate::html) while compiling.
+/// Named, numbered, and formatted placeholders could capture variables that would not be escaped.
+pub const fn has_only_plain_placeholders(template: &str) -> bool {
+    let bytes = template.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' if i + 1 < bytes.len() && (bytes[i + 1] == b'{' || bytes[i + 1] == b'}') => i += 2,
+            b'}' if i + 1 < bytes.len() && bytes[i + 1] == b'}' => i += 2,
+            b'{' => return false,
+            _ => i += 1,
+        }
+    }
+
+    true
+}
+
+/// Like `format!`, but escapes every argument that is not [`Html`] and returns [`Html`].
+/// Arguments can only be filled in with `{}`, a captured variable does not compile:
+///
+/// ```compile_fail
+/// let name = "<script>";
+/// chat_server::html!("<p>{name}</p>");
+/// ```
+#[macro_export]
+macro_rules! html {
+    ($template:literal $(, $arg:expr)* $(,)?) => {{
+        const _: () = assert!(
+            $crate::utils::html::has_only_plain_placeholders($template),
+            "html! only fills in `{{}}`, pass arguments after the template so they are escaped"
+        );
+
+        $crate::utils::html::Html::trusted(format!(
+            $template
+            $(, $crate::utils::html::Render(&$arg))*
+        ))
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html;
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            escape("<script>alert('hi')</script>"),
+            "&lt;script&gt;alert(&#x27;hi&#x27;)&lt;/script&gt;"
+        );
+        assert_eq!(escape("Tom & \"Jerry\""), "Tom &amp; &quot;Jerry&quot;");
+    }
+
+    #[test]
+    fn keeps_plain_text_as_is() {
+        assert!(matches!(escape("Hello, world!"), Cow::Borrowed("Hello, world!")));
+        assert_eq!(escape("こんにちは 👋"), "こんにちは 👋");
+    }
+
+    #[test]
+    fn escapes_ampersands_before_anything_else() {
+        assert_eq!(escape("&lt;b&gt;"), "&amp;lt;b&amp;gt;");
+    }
+
+    #[test]
+    fn macro_escapes_text_arguments() {
+        let message = "<img src=x onerror=alert(1)>";
+        let name = String::from("\"><script>alert(1)</script>");
+
+        assert_eq!(
+            html!("<p title=\"{}\">{}</p>", name, message).as_str(),
+            "<p title=\"&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;\">&lt;img src=x onerror=alert(1)&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn macro_keeps_fragments() {
+        let inner = html!("<b>{}</b>", "<i>");
+
+        assert_eq!(html!("<p>{}</p>", inner).as_str(), "<p><b>&lt;i&gt;</b></p>");
+    }
+
+    #[test]
+    fn macro_writes_numbers() {
+        assert_eq!(html!("<li id=\"message_{}\" data-seen=\"{}\"></li>", 7, true).as_str(), "<li id=\"message_7\" data-seen=\"true\"></li>");
+    }
+
+    #[test]
+    fn single_quotes_cannot_end_attributes() {
+        let payload = "' onmouseover='alert(1)";
+
+        assert_eq!(
+            html!("<p title='{}'></p>", payload).as_str(),
+            "<p title='&#x27; onmouseover=&#x27;alert(1)'></p>"
+        );
+    }
+
+    #[test]
+    fn templates_cannot_capture_unescaped_variables() {
+        assert!(has_only_plain_placeholders("<p title=\"{}\">{}</p>"));
+        assert!(has_only_plain_placeholders("hx-vals='{{\"id\": {}}}'"));
+        assert!(!has_only_plain_placeholders("<p>{message}</p>"));
+        assert!(!has_only_plain_placeholders("<p>{0}</p>"));
+        assert!(!has_only_plain_placeholders("<p>{:?}</p>"));
+        assert!(!has_only_plain_placeholders("<p>{{{message}}}</p>"));
+    }
+
+    #[test]
+    fn push_escapes_text_and_keeps_fragments() {
+        let mut html = Html::new();
+
+        html.push("<script>");
+        html.push(&Html::trusted("<br>"));
+
+        assert_eq!(html.as_str(), "&lt;script&gt;<br>");
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(Html::text("</p><p>").as_str(), "&lt;/p&gt;&lt;p&gt;");
+    }
+}
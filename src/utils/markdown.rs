@@ -0,0 +1,243 @@
+//! Markdown in messages: **bold**, _italic_, `code`, fenced code blocks and links.
+//!
+//! Messages are saved as they were typed and only turned into HTML when they are shown.
+//! Whatever the markdown renders to goes through a sanitizer that keeps nothing but [`ALLOWED_TAGS`].
+//! Code blocks are rendered by [`highlight::code_block`].
+use std::{borrow::Cow, collections::{HashMap, HashSet}, sync::OnceLock};
+
+use ammonia::{Builder, UrlRelative};
+use linkify::{LinkFinder, LinkKind};
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, LinkType, Options, Parser, Tag};
+
+use super::{highlight, html::Html};
+
+/// The only elements a rendered message can have, anything else is left out but its text.
+const ALLOWED_TAGS: [&str; 17] = [
+    "p", "br", "strong", "em", "code", "pre", "a", "blockquote", "ul", "ol", "li",
+    // Code blocks
+    "div", "span", "small", "details", "summary", "button",
+];
+
+/// Classes of code blocks, besides the ones of their tokens
+const ALLOWED_CLASSES: [&str; 5] = ["chats__code", "chats__code_header", "chats__code_line", "chats__code_copy", "ghost"];
+
+/// Links to anything else, like `javascript:`, lose their `href`.
+const ALLOWED_URL_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+fn sanitizer() -> &'static Builder<'static> {
+    static SANITIZER: OnceLock<Builder<'static>> = OnceLock::new();
+
+    SANITIZER.get_or_init(|| {
+        let mut sanitizer = Builder::empty();
+
+        sanitizer
+            .tags(HashSet::from(ALLOWED_TAGS))
+            .tag_attributes(HashMap::from([
+                ("a", HashSet::from(["href"])),
+                ("ol", HashSet::from(["start"])),
+                ("span", HashSet::from(["data-line"])),
+                ("button", HashSet::from(["title", "data-copy-code"])),
+            ]))
+            .generic_attributes(HashSet::from(["class"]))
+            .attribute_filter(|_, attribute, value| match attribute {
+                "class" if !value.split_whitespace().all(is_allowed_class) => None,
+                _ => Some(value.into()),
+            })
+            .url_schemes(HashSet::from(ALLOWED_URL_SCHEMES))
+            .url_relative(UrlRelative::Deny)
+            .link_rel(Some("noopener noreferrer nofollow"))
+            .set_tag_attribute_value("a", "target", "_blank")
+            .set_tag_attribute_value("button", "type", "button");
+
+        sanitizer
+    })
+}
+
+fn is_allowed_class(class: &str) -> bool {
+    class.starts_with(highlight::CLASS_PREFIX) || ALLOWED_CLASSES.contains(&class)
+}
+
+/// Adds `text` to `events`, with every URL in it as a link.
+fn push_linked_text<'a>(events: &mut Vec<Event<'a>>, text: String) {
+    let mut finder = LinkFinder::new();
+
+    finder.kinds(&[LinkKind::Url]);
+
+    for span in finder.spans(&text) {
+        let part = CowStr::from(span.as_str().to_string());
+
+        match span.kind() {
+            Some(LinkKind::Url) => {
+                events.push(Event::Start(Tag::Link(LinkType::Autolink, part.clone(), CowStr::Borrowed(""))));
+                events.push(Event::Text(part.clone()));
+                events.push(Event::End(Tag::Link(LinkType::Autolink, part, CowStr::Borrowed(""))));
+            },
+            _ => events.push(Event::Text(part)),
+        }
+    }
+}
+
+/// Adds the text collected so far to `events`. Links cannot have links in them.
+fn flush_text(events: &mut Vec<Event<'_>>, text: &mut String, in_link: bool) {
+    if text.is_empty() {
+        return;
+    }
+
+    if in_link {
+        events.push(Event::Text(CowStr::from(std::mem::take(text))));
+    } else {
+        push_linked_text(events, std::mem::take(text));
+    }
+}
+
+/// Parses markdown the way it is used in a chat: HTML is shown as text, every line break is kept,
+/// headings are plain paragraphs, images are links to them, URLs become links and code blocks are highlighted.
+fn message_events(source: &str) -> Vec<Event<'_>> {
+    let mut events = Vec::new();
+    // Text is split wherever something could have been markup, like `_`, so URLs are only found once it is put back together.
+    let mut text = String::new();
+    let mut in_link = false;
+    // The language of the code block the text is in, if it has one
+    let mut code_block: Option<Option<CowStr>> = None;
+
+    for event in Parser::new_ext(source, Options::empty()) {
+        let event = match event {
+            Event::Text(part) | Event::Html(part) => {
+                text.push_str(&part);
+                continue;
+            },
+            Event::Start(Tag::CodeBlock(kind)) => {
+                flush_text(&mut events, &mut text, in_link);
+                code_block = Some(match kind {
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next().map(|language| CowStr::from(language.to_string())),
+                    CodeBlockKind::Indented => None,
+                });
+                continue;
+            },
+            Event::End(Tag::CodeBlock(_)) => {
+                let language = code_block.take().flatten();
+                let html = highlight::code_block(&std::mem::take(&mut text), language.as_deref());
+
+                events.push(Event::Html(CowStr::from(html.into_string())));
+                continue;
+            },
+            _ if code_block.is_some() => continue,
+            Event::SoftBreak => Event::HardBreak,
+            Event::Start(Tag::Heading(..)) => Event::Start(Tag::Paragraph),
+            Event::End(Tag::Heading(..)) => Event::End(Tag::Paragraph),
+            Event::Start(Tag::Image(link_type, url, title)) => Event::Start(Tag::Link(link_type, url, title)),
+            Event::End(Tag::Image(link_type, url, title)) => Event::End(Tag::Link(link_type, url, title)),
+            event => event,
+        };
+
+        flush_text(&mut events, &mut text, in_link);
+
+        match &event {
+            Event::Start(Tag::Link(..)) => in_link = true,
+            Event::End(Tag::Link(..)) => in_link = false,
+            _ => (),
+        }
+
+        events.push(event);
+    }
+
+    flush_text(&mut events, &mut text, in_link);
+
+    events
+}
+
+/// Renders the markdown of a message.
+pub fn render(source: &str) -> Html {
+    let mut unsanitized = String::with_capacity(source.len() * 3 / 2);
+
+    pulldown_cmark::html::push_html(&mut unsanitized, message_events(source).into_iter());
+
+    Html::trusted(sanitizer().clean(&unsanitized).to_string())
+}
+
+/// The text of a message without its markdown, on a single line.
+pub fn to_plain_text(source: &str) -> String {
+    let mut text = String::with_capacity(source.len());
+
+    for event in Parser::new_ext(source, Options::empty()) {
+        match event {
+            Event::Text(part) | Event::Code(part) | Event::Html(part) => text.push_str(&part),
+            Event::SoftBreak
+            | Event::HardBreak
+            | Event::Rule
+            | Event::End(Tag::Paragraph | Tag::Heading(..) | Tag::BlockQuote | Tag::CodeBlock(_) | Tag::Item) => text.push(' '),
+            _ => (),
+        }
+    }
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// What a message says in previews, like the sidebar. Plain messages are shown as they are.
+pub fn preview(message: &str, is_plain: bool) -> Cow<'_, str> {
+    if is_plain {
+        Cow::Borrowed(message)
+    } else {
+        Cow::Owned(to_plain_text(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_inline_markdown() {
+        assert_eq!(
+            render("**bold**, _italic_ and `code`").as_str(),
+            "<p><strong>bold</strong>, <em>italic</em> and <code>code</code></p>\n"
+        );
+    }
+
+    #[test]
+    fn renders_fenced_code_as_text() {
+        let html = render("```\n<b>\"hi\"</b>\nhttps://example.com\n```");
+
+        assert!(html.as_str().contains("<span class=\"chats__code_line\" data-line=\"1\">&lt;b&gt;\"hi\"&lt;/b&gt;</span>"));
+        assert!(html.as_str().contains("<span class=\"chats__code_line\" data-line=\"2\">https://example.com</span>"));
+    }
+
+    #[test]
+    fn keeps_highlighted_code_blocks() {
+        let html = render("```rust\nlet x = 1;\n```");
+
+        assert!(html.as_str().contains("<span class=\"hl-storage hl-type hl-rust\">let</span>"));
+        assert!(html.as_str().contains("<button class=\"ghost chats__code_copy\" title=\"Copy code\" data-copy-code=\"\" type=\"button\">"));
+    }
+
+    #[test]
+    fn links_urls() {
+        assert_eq!(
+            render("see https://example.com/a_b_c").as_str(),
+            "<p>see <a href=\"https://example.com/a_b_c\" target=\"_blank\" rel=\"noopener noreferrer nofollow\">https://example.com/a_b_c</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn shows_html_as_text() {
+        let html = render("<script>alert(1)</script> <img src=x onerror=alert(1)>");
+
+        assert!(!html.as_str().contains("<script"));
+        assert!(!html.as_str().contains("<img"));
+        assert!(html.as_str().contains("&lt;script&gt;"));
+    }
+
+    #[test]
+    fn drops_unsafe_links() {
+        let html = render("[click](javascript:alert(1)) ![x](data:text/html,hi)");
+
+        assert!(!html.as_str().contains("javascript:"));
+        assert!(!html.as_str().contains("data:"));
+    }
+
+    #[test]
+    fn strips_markdown_from_plain_text() {
+        assert_eq!(to_plain_text("**Hello**\n\n- _there_\n- `you`"), "Hello there you");
+        assert_eq!(preview("**not bold**", true), "**not bold**");
+    }
+}
@@ -2,6 +2,11 @@ use crate::{consts::PLACEHOLDER_IMAGES, user::user_struct::Gender};
 
 pub mod env;
 pub mod custom_redirect;
+pub mod upload;
+pub mod rate_limit;
+pub mod highlight;
+pub mod html;
+pub mod markdown;
 
 pub fn get_placeholder_display_image(
     display_image: Option<&String>,
@@ -0,0 +1,251 @@
+use std::{collections::HashMap, net::IpAddr, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use rocket::{http::Status, request::{FromRequest, Outcome}, Request};
+
+use crate::{consts, user::user_struct::User, utils::env};
+
+/// At most `requests` requests every `seconds` seconds, refilled evenly.
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    pub requests: u32,
+    pub seconds: u64,
+}
+
+impl Limit {
+    /// Parses `requests/seconds`, like `30/60`.
+    fn parse(str: &str) -> Option<Self> {
+        let (requests, seconds) = str.trim().split_once('/')?;
+        let limit = Limit {
+            requests: requests.trim().parse().ok()?,
+            seconds: seconds.trim().parse().ok()?,
+        };
+
+        if limit.requests == 0 || limit.seconds == 0 {
+            return None;
+        }
+
+        Some(limit)
+    }
+
+    /// Tokens added back every second
+    fn rate(&self) -> f64 {
+        self.requests as f64 / self.seconds as f64
+    }
+}
+
+/// Who a bucket belongs to. Requests take from the bucket of the logged in user,
+/// wherever they connect from, and from the bucket of the IP they come from, whoever sends them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Client {
+    User(i32),
+    Ip(IpAddr),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    /// When a request last took from it
+    used_at: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &Limit, now: Instant) {
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * limit.rate()).min(limit.requests as f64);
+        self.updated_at = now;
+    }
+}
+
+/// Token buckets for every route and client that made a rate limited request.
+///
+/// Routes opt in with the [`RateLimit`] guard. Each route uses its limit from
+/// [`env::load_rate_limits`], or [`consts::DEFAULT_RATE_LIMIT`].
+#[derive(Clone)]
+pub struct RateLimiter {
+    limits: Arc<HashMap<String, Limit>>,
+    buckets: Arc<Mutex<HashMap<(String, Client), Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: HashMap<String, Limit>) -> Self {
+        Self {
+            limits: Arc::new(limits),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Reads the limits from `RATE_LIMITS`, a comma separated list of `route=requests/seconds`,
+    /// like `/chats/send=30/60,/search=20/60`. Invalid entries are skipped.
+    pub fn from_env() -> Self {
+        let limits = env::load_rate_limits()
+            .split(',')
+            .filter_map(|entry| {
+                let (route, limit) = entry.split_once('=')?;
+                let limit = Limit::parse(limit);
+
+                if limit.is_none() {
+                    println!("Error: invalid rate limit {:?}", entry);
+                }
+
+                Some((route.trim().to_string(), limit?))
+            })
+            .collect();
+
+        Self::new(limits)
+    }
+
+    pub fn limit_of(&self, route: &str) -> Limit {
+        self.limits.get(route).copied().unwrap_or(Limit {
+            requests: consts::DEFAULT_RATE_LIMIT.0,
+            seconds: consts::DEFAULT_RATE_LIMIT.1,
+        })
+    }
+
+    /// Takes a token from the bucket of every one of `clients` for `route`, or from none of them
+    /// if one is empty. Returns how long to wait before trying again in that case.
+    pub fn take(&self, route: &str, clients: &[Client]) -> Result<(), Duration> {
+        let limit = self.limit_of(route);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() + clients.len() > consts::RATE_LIMIT_MAX_BUCKETS {
+            self.evict(&mut buckets, now);
+        }
+
+        let mut wait = Duration::ZERO;
+
+        for client in clients {
+            let bucket = buckets.entry((route.to_string(), *client)).or_insert(Bucket {
+                tokens: limit.requests as f64,
+                updated_at: now,
+                used_at: now,
+            });
+
+            bucket.refill(&limit, now);
+            bucket.used_at = now;
+
+            if bucket.tokens < 1.0 {
+                wait = wait.max(Duration::from_secs_f64((1.0 - bucket.tokens) / limit.rate()));
+            }
+        }
+
+        if wait > Duration::ZERO {
+            return Err(wait);
+        }
+
+        for client in clients {
+            if let Some(bucket) = buckets.get_mut(&(route.to_string(), *client)) {
+                bucket.tokens -= 1.0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes room for new buckets. Full buckets are the same as new ones, so they go first.
+    /// If that is not enough, the ones that were used the longest ago go too,
+    /// down to a tenth below [`consts::RATE_LIMIT_MAX_BUCKETS`] so it does not happen on every request.
+    fn evict(&self, buckets: &mut HashMap<(String, Client), Bucket>, now: Instant) {
+        buckets.retain(|(route, _), bucket| {
+            let limit = self.limit_of(route);
+
+            bucket.refill(&limit, now);
+            bucket.tokens < limit.requests as f64
+        });
+
+        let keep = consts::RATE_LIMIT_MAX_BUCKETS - consts::RATE_LIMIT_MAX_BUCKETS / 10;
+
+        if buckets.len() <= keep {
+            return;
+        }
+
+        // Newest first, so the one at `keep` is the newest to go.
+        let mut used_at: Vec<Instant> = buckets.values().map(|bucket| bucket.used_at).collect();
+        let (_, newest_evicted, _) = used_at.select_nth_unstable_by(keep, |a, b| b.cmp(a));
+        let newest_evicted = *newest_evicted;
+
+        buckets.retain(|_, bucket| bucket.used_at > newest_evicted);
+    }
+}
+
+/// How long a rate limited client has to wait, in whole seconds.
+/// Kept in the request for the 429 catcher to send as `Retry-After`.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryAfter(pub u64);
+
+impl From<Duration> for RetryAfter {
+    fn from(wait: Duration) -> Self {
+        RetryAfter(wait.as_secs_f64().ceil().max(1.0) as u64)
+    }
+}
+
+/// The logged in user and the IP a request comes from, whichever are known.
+async fn clients_of(request: &Request<'_>) -> Vec<Client> {
+    let mut clients = Vec::new();
+
+    if let Outcome::Success(user) = request.guard::<User>().await {
+        clients.push(Client::User(user.id));
+    }
+
+    if let Some(ip) = request.client_ip() {
+        clients.push(Client::Ip(ip));
+    }
+
+    clients
+}
+
+/// Fails with 429 Too Many Requests once the client made too many requests to the route.
+/// Does nothing if no [`RateLimiter`] is managed.
+pub struct RateLimit;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimit {
+    type Error = RetryAfter;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let (Some(limiter), Some(route)) = (request.rocket().state::<RateLimiter>(), request.route()) else {
+            return Outcome::Success(RateLimit);
+        };
+
+        match limiter.take(route.uri.path(), &clients_of(request).await) {
+            Ok(()) => Outcome::Success(RateLimit),
+            Err(wait) => {
+                let retry_after = *request.local_cache(|| RetryAfter::from(wait));
+
+                Outcome::Error((Status::TooManyRequests, retry_after))
+            }
+        }
+    }
+}
+
+/// The buckets of the client that made a request, for routes that take from them
+/// more than once per request, like every message sent through a WebSocket.
+/// Never runs out if no [`RateLimiter`] is managed.
+#[derive(Clone)]
+pub struct ClientRateLimit {
+    limiter: Option<RateLimiter>,
+    clients: Vec<Client>,
+}
+
+impl ClientRateLimit {
+    /// Takes a token for `route`, see [`RateLimiter::take`].
+    pub fn take(&self, route: &str) -> Result<(), Duration> {
+        match &self.limiter {
+            Some(limiter) => limiter.take(route, &self.clients),
+            None => Ok(()),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientRateLimit {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ClientRateLimit {
+            limiter: request.rocket().state::<RateLimiter>().cloned(),
+            clients: clients_of(request).await,
+        })
+    }
+}
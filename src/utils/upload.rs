@@ -0,0 +1,109 @@
+use cloud_storage::Client;
+use rocket::{futures::{future::ready, Stream, StreamExt}, http::Status, response::status, tokio::fs};
+use rocket_multipart_form_data::FileField;
+
+use crate::{consts, errors::error::{Error, ErrorReason}};
+
+pub fn is_allowed_image(mime_type: &str) -> bool {
+    consts::ALLOWED_IMAGE_TYPES.contains(&mime_type)
+}
+
+/// Files that could run on whoever opens them, judging by their extension or type
+pub fn is_denied_file(file_name: &str, mime_type: &str) -> bool {
+    let extension = file_name.rsplit_once('.').map(|(_, extension)| extension.to_lowercase()).unwrap_or_default();
+
+    consts::DENIED_ATTACHMENT_EXTENSIONS.contains(&extension.as_str())
+        || consts::DENIED_ATTACHMENT_TYPES.contains(&mime_type)
+}
+
+/// A size in bytes, in the largest unit it is at least one of
+pub fn format_file_size(size: u64) -> String {
+    match size {
+        size if size >= 1024 * 1024 => format!("{:.1} MB", size as f64 / (1024.0 * 1024.0)),
+        size if size >= 1024 => format!("{:.1} KB", size as f64 / 1024.0),
+        size => format!("{} B", size),
+    }
+}
+
+/// Where an uploaded file can be viewed by anyone
+pub fn public_url(object_name: &str) -> String {
+    format!("https://storage.cloud.google.com/{}/{}", consts::STORAGE_BUCKET, object_name)
+}
+
+/// The size of a file received through a multipart form, in bytes
+pub async fn file_size(file: &FileField) -> u64 {
+    fs::metadata(&file.path).await.map(|metadata| metadata.len()).unwrap_or_default()
+}
+
+/// Uploads a file received through a multipart form under a random name.
+/// Returns the name of the object it is stored as.
+pub async fn upload_file(file: &FileField, mime_type: &str) -> Result<String, status::Custom<String>> {
+    let file_name = match &file.file_name {
+        Some(file_name) => file_name,
+        None => return Err(status::Custom(
+            Status::NotAcceptable,
+            Error::to_string(Error::new(ErrorReason::Invalid, "File name is required.".to_string()))
+        ))
+    };
+
+    let bytes = fs::read(&file.path).await.map_err(|err| {
+        println!("Error: {:?}", err);
+
+        status::Custom(
+            Status::InternalServerError,
+            Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to process file. File might be corrupted.".to_string()))
+        )
+    })?;
+
+    let random_name = random_string::generate(12, random_string::charsets::ALPHANUMERIC);
+    let name = format!("{}-chat_server-{}", random_name, file_name);
+    let result = Client::default().object().create(consts::STORAGE_BUCKET, bytes, &name, mime_type).await;
+
+    match result {
+        Ok(_obj) => Ok(name),
+        Err(err) => {
+            println!("Error: {:?}", err);
+
+            Err(status::Custom(
+                Status::InternalServerError,
+                Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to process file.".to_string()))
+            ))
+        }
+    }
+}
+
+/// Deletes a file that was uploaded with [`upload_file`].
+pub async fn delete_file(object_name: &str) -> Result<(), status::Custom<String>> {
+    Client::default().object().delete(consts::STORAGE_BUCKET, object_name).await.map_err(|err| {
+        println!("Error: {:?}", err);
+
+        status::Custom(
+            Status::InternalServerError,
+            Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to delete the file.".to_string()))
+        )
+    })
+}
+
+/// Streams the contents of a file that was uploaded with [`upload_file`], a chunk at a time,
+/// so it is never loaded into memory as a whole. Ends early if the download fails halfway.
+pub async fn download_file(object_name: &str) -> Result<impl Stream<Item = Vec<u8>> + Send, status::Custom<String>> {
+    let bytes = Client::default().object().download_streamed(consts::STORAGE_BUCKET, object_name).await.map_err(|err| {
+        println!("Error: {:?}", err);
+
+        status::Custom(
+            Status::InternalServerError,
+            Error::to_string(Error::new(ErrorReason::SomethingWentWrong, "Failed to get the file.".to_string()))
+        )
+    })?;
+
+    Ok(bytes
+        .take_while(|byte| {
+            if let Err(err) = byte {
+                println!("Error: {:?}", err);
+            }
+
+            ready(byte.is_ok())
+        })
+        .filter_map(|byte| ready(byte.ok()))
+        .chunks(consts::DOWNLOAD_CHUNK_SIZE))
+}